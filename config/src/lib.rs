@@ -5,12 +5,118 @@ pub mod state;
 use cosmic_config::{Config as CosmicConfig, ConfigGet, ConfigSet};
 use derive_setters::Setters;
 use serde::{Deserialize, Serialize};
-use std::{borrow::Cow, collections::HashSet, path::PathBuf};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+};
 
 pub const NAME: &str = "com.system76.CosmicBackground";
 pub const BACKGROUNDS: &str = "backgrounds";
 pub const DEFAULT_BACKGROUND: &str = "all";
 pub const SAME_ON_ALL: &str = "same-on-all";
+pub const POINTER_POSITION: &str = "pointer-position";
+pub const OVERLAY: &str = "overlay";
+pub const MAX_RENDER_EDGE: &str = "max-render-edge";
+pub const BRIGHTNESS_SCHEDULE: &str = "brightness-schedule";
+pub const NEW_OUTPUT_POLICY: &str = "new-output-policy";
+pub const COMMAND: &str = "command";
+pub const WATCH_DEPTH: &str = "watch-depth";
+pub const OUTPUT_RENDER_SIZE: &str = "output-render-size";
+pub const PRIMARY_OUTPUT: &str = "primary-output";
+pub const PREVIEW: &str = "preview";
+pub const NIGHT_LIGHT: &str = "night-light";
+pub const RECENT_WALLPAPERS: &str = "recent-wallpapers";
+
+/// Most sources kept in the history of recently set wallpapers.
+pub const RECENT_WALLPAPERS_LIMIT: usize = 16;
+
+/// Levels of subdirectories of a source that are scanned and watched by default.
+pub const DEFAULT_WATCH_DEPTH: usize = 8;
+
+/// System wallpapers of distributions, the first of which that exists is the
+/// fallback background.
+pub const FALLBACK_BACKGROUNDS: &[&str] = &[
+    "/usr/share/backgrounds/cosmic/orion_nebula_nasa_heic0601a.jpg",
+    "/usr/share/backgrounds/pop/kate-hazen-COSMIC-desktop-wallpaper.png",
+    "/usr/share/backgrounds/gnome/adwaita-l.jxl",
+    "/usr/share/backgrounds/gnome/adwaita-l.jpg",
+    "/usr/share/backgrounds/default.png",
+];
+
+/// File extensions of the images that can be decoded as backgrounds.
+pub const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp", "hdr", "jxl"];
+
+/// The kinds of background sources supported, for settings to hide options
+/// which cosmic-bg can't display.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SupportedSources {
+    /// File extensions of still images.
+    pub image_extensions: &'static [&'static str],
+    /// Whether single colors and gradients are supported.
+    pub colors: bool,
+    /// Whether two images can be blended as one background.
+    pub blends: bool,
+}
+
+/// The kinds of background sources supported by this version of cosmic-bg.
+#[must_use]
+pub fn supported_sources() -> SupportedSources {
+    SupportedSources {
+        image_extensions: IMAGE_EXTENSIONS,
+        colors: true,
+        blends: true,
+    }
+}
+
+/// Checks if a path has the extension of a supported image.
+#[must_use]
+pub fn is_supported_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| IMAGE_EXTENSIONS.iter().any(|e| e.eq_ignore_ascii_case(ext)))
+}
+
+/// Why an entry's source can't be displayed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The path does not exist.
+    NotFound(PathBuf),
+    /// The directory contains no files of supported image formats.
+    NoImages(PathBuf),
+    /// The file is not of a supported image format.
+    UnsupportedFormat(PathBuf),
+    /// The gradient has no colors.
+    EmptyGradient,
+    /// The fallback chain has no sources.
+    EmptyFallback,
+    /// A side of a split is neither an image nor a color.
+    UnsupportedSplitSide,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound(path) => write!(f, "file not found: {}", path.display()),
+            Self::NoImages(path) => write!(
+                f,
+                "directory contains no supported images: {}",
+                path.display()
+            ),
+            Self::UnsupportedFormat(path) => {
+                write!(f, "unsupported format: {}", path.display())
+            }
+            Self::EmptyGradient => f.write_str("gradient has no colors"),
+            Self::EmptyFallback => f.write_str("fallback chain has no sources"),
+            Self::UnsupportedSplitSide => {
+                f.write_str("sides of a split must be single images or colors")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
 
 /// Create a context to the `cosmic-bg` config.
 ///
@@ -40,8 +146,21 @@ impl Context {
         }
     }
 
+    /// The background of outputs without one of their own, whose source falls
+    /// back to a system wallpaper if its path is missing.
     pub fn default_background(&self) -> Entry {
-        self.entry("all").unwrap_or_else(|_| Entry::fallback())
+        let Ok(mut entry) = self.entry("all") else {
+            return Entry::fallback();
+        };
+
+        if let Source::Path(ref path) = entry.source {
+            if !path.exists() {
+                tracing::warn!(?path, "default background is missing, falling back");
+                entry.source = fallback_source();
+            }
+        }
+
+        entry
     }
 
     /// Get the entry for an output from cosmic-config.
@@ -64,6 +183,132 @@ impl Context {
         true
     }
 
+    /// Pointer position published by the compositor for parallax wallpapers.
+    ///
+    /// The position is relative to the center of the output under the pointer,
+    /// ranging from `-1.0` to `1.0` on each axis. cosmic-bg layer surfaces do not
+    /// receive pointer events, so parallax is unavailable unless the compositor
+    /// writes this key.
+    #[must_use]
+    pub fn pointer_position(&self) -> Option<(f32, f32)> {
+        self.0.get::<(f32, f32)>(POINTER_POSITION).ok()
+    }
+
+    /// Seconds for which wallpapers are shown above windows, such as for taking a
+    /// screenshot of the desktop. Writing `0` returns them to the background early.
+    #[must_use]
+    pub fn overlay(&self) -> u64 {
+        self.0.get::<u64>(OVERLAY).unwrap_or(0)
+    }
+
+    /// Largest width or height of the buffers that wallpapers are rendered to,
+    /// where `0` renders at the full resolution of each output. Larger outputs
+    /// are upscaled by the compositor.
+    #[must_use]
+    pub fn max_render_edge(&self) -> u32 {
+        self.0.get::<u32>(MAX_RENDER_EDGE).unwrap_or(0)
+    }
+
+    /// Buffer sizes that wallpapers are rendered at for specific outputs, instead
+    /// of the size configured by the compositor, for displays that misreport
+    /// their mode. The compositor scales the buffer to the surface.
+    #[must_use]
+    pub fn output_render_size(&self) -> HashMap<String, (u32, u32)> {
+        self.0
+            .get::<HashMap<String, (u32, u32)>>(OUTPUT_RENDER_SIZE)
+            .unwrap_or_default()
+    }
+
+    /// Connector name of the output matched by entries for the `primary` role,
+    /// instead of the first connected output.
+    #[must_use]
+    pub fn primary_output(&self) -> Option<String> {
+        self.0.get::<Option<String>>(PRIMARY_OUTPUT).ok().flatten()
+    }
+
+    /// Points of a schedule of wallpaper brightness through the day, as the
+    /// minute of the day and a brightness from 0.0 to 1.0.
+    #[must_use]
+    pub fn brightness_schedule(&self) -> Vec<(u32, f32)> {
+        self.0
+            .get::<Vec<(u32, f32)>>(BRIGHTNESS_SCHEDULE)
+            .unwrap_or_default()
+    }
+
+    /// Color temperature in kelvin that the night light shifts outputs to, which
+    /// wallpapers are warmed to match, or `None` while it is off.
+    #[must_use]
+    pub fn night_light(&self) -> Option<u32> {
+        self.0.get::<Option<u32>>(NIGHT_LIGHT).ok().flatten()
+    }
+
+    /// Levels of subdirectories of a source directory that are scanned for images
+    /// and watched for changes, where `0` only watches the directory itself.
+    #[must_use]
+    pub fn watch_depth(&self) -> usize {
+        self.0
+            .get::<usize>(WATCH_DEPTH)
+            .unwrap_or(DEFAULT_WATCH_DEPTH)
+    }
+
+    /// Which wallpaper is shown on outputs connected without a background of their own.
+    #[must_use]
+    pub fn new_output_policy(&self) -> NewOutputPolicy {
+        self.0
+            .get::<NewOutputPolicy>(NEW_OUTPUT_POLICY)
+            .unwrap_or_default()
+    }
+
+    /// A pending command written by a keybinding, for cosmic-bg to act on.
+    #[must_use]
+    pub fn command(&self) -> Option<Command> {
+        self.0.get::<Option<Command>>(COMMAND).ok().flatten()
+    }
+
+    /// Clears the pending command once it has been acted on.
+    pub fn clear_command(&self) -> Result<(), cosmic_config::Error> {
+        self.0.set::<Option<Command>>(COMMAND, None)
+    }
+
+    /// An entry shown on its output by a settings app without being saved, such
+    /// as while editing it.
+    #[must_use]
+    pub fn preview(&self) -> Option<Entry> {
+        self.0.get::<Option<Entry>>(PREVIEW).ok().flatten()
+    }
+
+    /// Shows an entry on its output without saving it, until the preview is
+    /// cancelled or another entry is previewed.
+    pub fn preview_entry(&self, entry: &Entry) -> Result<(), cosmic_config::Error> {
+        self.0.set(PREVIEW, Some(entry))
+    }
+
+    /// Restores the saved entry of the previewed output.
+    pub fn cancel_preview(&self) -> Result<(), cosmic_config::Error> {
+        self.0.set::<Option<Entry>>(PREVIEW, None)
+    }
+
+    /// Sources recently set as wallpapers, from the most recent, for settings
+    /// apps to offer again.
+    #[must_use]
+    pub fn recent_wallpapers(&self) -> Vec<Source> {
+        self.0
+            .get::<Vec<Source>>(RECENT_WALLPAPERS)
+            .unwrap_or_default()
+    }
+
+    /// Moves a source to the front of the recent wallpapers, or adds it there,
+    /// forgetting the oldest beyond [`RECENT_WALLPAPERS_LIMIT`].
+    pub fn add_recent_wallpaper(&self, source: &Source) -> Result<(), cosmic_config::Error> {
+        let mut recent = self.recent_wallpapers();
+        if recent.first() == Some(source) {
+            return Ok(());
+        }
+
+        push_recent(&mut recent, source);
+        self.0.set(RECENT_WALLPAPERS, recent)
+    }
+
     pub fn set_same_on_all(&self, value: bool) -> Result<(), cosmic_config::Error> {
         if self.same_on_all() != value {
             return self.0.set(SAME_ON_ALL, value);
@@ -73,8 +318,13 @@ impl Context {
     }
 }
 
+/// The background of an output.
+///
+/// Entries stay readable across versions of cosmic-bg, such as during a partial
+/// upgrade of the system: unknown fields written by newer versions are ignored,
+/// and fields missing from older versions take their defaults. New fields must
+/// therefore have a default, and only the output and source are required.
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Setters)]
-#[serde(deny_unknown_fields)]
 #[must_use]
 pub struct Entry {
     /// the configured output
@@ -84,8 +334,10 @@ pub struct Entry {
     #[setters(skip)]
     pub source: Source,
     /// whether the images should be filtered by the active theme
+    #[serde(default)]
     pub filter_by_theme: bool,
     /// frequency at which the wallpaper is rotated in seconds
+    #[serde(default = "default_rotation_frequency")]
     pub rotation_frequency: u64,
     /// filter used to scale images
     #[serde(default)]
@@ -95,13 +347,161 @@ pub struct Entry {
     pub scaling_mode: ScalingMode,
     #[serde(default)]
     pub sampling_method: SamplingMethod,
+    /// fraction of the surface by which a zoomed image shifts with the pointer
+    #[serde(default)]
+    pub parallax_strength: f32,
+    /// edges of the output the background is anchored to, or all edges if empty
+    #[serde(default)]
+    pub anchor: Vec<Edge>,
+    /// distance in logical pixels from the top edge
+    #[serde(default)]
+    pub margin_top: i32,
+    /// distance in logical pixels from the right edge
+    #[serde(default)]
+    pub margin_right: i32,
+    /// distance in logical pixels from the bottom edge
+    #[serde(default)]
+    pub margin_bottom: i32,
+    /// distance in logical pixels from the left edge
+    #[serde(default)]
+    pub margin_left: i32,
+    /// duration in milliseconds of the fade in when first shown at startup
+    #[serde(default)]
+    pub startup_fade_ms: u64,
+    /// keep the transparency of images with an alpha channel
+    ///
+    /// The background is the bottom layer, so transparent areas show the
+    /// compositor's clear color rather than another window.
+    #[serde(default)]
+    pub preserve_alpha: bool,
+    /// fill colors of the Fit scaling mode for specific output names
+    #[serde(default)]
+    pub fit_colors: HashMap<String, [f32; 3]>,
+    /// placement of an image scaled by the Fit scaling mode on the horizontal and
+    /// vertical axes, from 0.0 (left or top) to 1.0 (right or bottom)
+    #[serde(default = "centered")]
+    pub fit_gravity: [f32; 2],
+    /// strength of the darkening of the edges, from 0.0 (off) to 1.0
+    #[serde(default)]
+    pub vignette: f32,
+    /// pixels between the edges of the output and the wallpaper, which show the
+    /// compositor's clear color
+    #[serde(default)]
+    pub inset: u32,
+    /// radius in pixels of the rounded corners of the wallpaper
+    #[serde(default)]
+    pub corner_radius: u32,
+    /// upcoming images of a slideshow decoded ahead of being shown, which are
+    /// held in memory, for slideshows on slow storage
+    #[serde(default)]
+    pub preload: usize,
+    /// number of recently shown images that random selection avoids showing
+    /// again, which is ignored if the slideshow has no more images than that
+    #[serde(default)]
+    pub no_repeat_window: usize,
+    /// images hidden from the slideshow, as patterns where `*` matches any
+    /// characters and `?` any one character, which are matched against file
+    /// names, or against whole paths if they contain a `/`
+    #[serde(default)]
+    pub exclude: Vec<PathBuf>,
+    /// whether images added to or removed from the source directory reorder the
+    /// slideshow by its sampling method, instead of adding images to be shown next
+    #[serde(default)]
+    pub reshuffle_on_change: bool,
+    /// opacity of the image from 0.0 to 1.0, over the background color if set,
+    /// or else over the compositor's clear color
+    #[serde(default = "full_opacity")]
+    pub image_opacity: f32,
+    /// linear color shown through translucent images
+    #[serde(default)]
+    pub background_color: Option<[f32; 3]>,
+    /// color space that the colors of gradients are interpolated in
+    #[serde(default)]
+    pub gradient_blend_space: GradientBlendSpace,
+}
+
+/// An edge of an output.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    Top,
+    Bottom,
+    Left,
+    Right,
 }
 
 /// A background image which is colored.
+///
+/// Channels are linear RGB values from 0.0 to 1.0, rather than the sRGB encoded
+/// values of hex codes and color pickers. Use [`Color::from_hex`] to convert these.
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
 pub enum Color {
     Single([f32; 3]),
     Gradient(Gradient),
+    /// Gradients composited in order, each with a blend mode and opacity.
+    Gradients(Vec<(Gradient, BlendMode, f32)>),
+}
+
+/// sRGB hex codes of the colors accepted by [`Color::from_name`].
+const COLOR_NAMES: &[(&str, &str)] = &[
+    ("black", "#000000"),
+    ("white", "#ffffff"),
+    ("gray", "#808080"),
+    ("grey", "#808080"),
+    ("slate", "#708090"),
+    ("navy", "#000080"),
+    ("teal", "#008080"),
+    ("red", "#ff0000"),
+    ("green", "#008000"),
+    ("blue", "#0000ff"),
+];
+
+impl Color {
+    /// A single color from an sRGB hex code of the form `#RRGGBB`, or `RRGGBB`.
+    #[must_use]
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let hex = hex.strip_prefix('#').unwrap_or(hex);
+
+        if hex.len() != 6 || !hex.is_ascii() {
+            return None;
+        }
+
+        let channel = |i: usize| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .ok()
+                .map(|c| srgb_to_linear(f32::from(c) / 255.0))
+        };
+
+        Some(Self::Single([channel(0)?, channel(2)?, channel(4)?]))
+    }
+
+    /// A single color from a common color name such as `black`, `white`, or `slate`.
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        COLOR_NAMES
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name.trim()))
+            .and_then(|(_, hex)| Self::from_hex(hex))
+    }
+}
+
+/// Decodes an sRGB encoded channel value from 0.0 to 1.0 to linear light.
+#[must_use]
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.040_45 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encodes a linear channel value from 0.0 to 1.0 with the sRGB transfer function.
+#[must_use]
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
 }
 
 /// A background image which is colored by a gradient.
@@ -118,6 +518,110 @@ pub enum Source {
     Path(PathBuf),
     /// A background color or gradient.
     Color(Color),
+    /// Background images within a zip or uncompressed tar archive.
+    Archive(PathBuf),
+    /// An image located by the SHA-256 hash of its contents, in hexadecimal,
+    /// within the search paths, so that configs are portable across machines.
+    Hashed {
+        sha256: String,
+        search_paths: Vec<PathBuf>,
+    },
+    /// No wallpaper, leaving the compositor's background visible.
+    None,
+    /// The first available of several sources, such as a directory on a drive
+    /// that may not be mounted, followed by a local image.
+    Fallback(Vec<Source>),
+    /// Two images composited as one background.
+    Blend {
+        base: PathBuf,
+        overlay: PathBuf,
+        mode: BlendMode,
+        /// opacity of the overlay, from 0.0 to 1.0
+        opacity: f32,
+    },
+    /// Two sources side by side, such as on an ultrawide output, each of which
+    /// is an image or a color.
+    Split {
+        left: Box<Source>,
+        right: Box<Source>,
+        /// fraction of the width filled by the left source, from 0.0 to 1.0
+        ratio: f32,
+    },
+    /// A solid color matching the wallpaper of the primary output, such as for
+    /// secondary outputs, which is the average color of its image or a copy of
+    /// its color.
+    AutoMatchPrimary,
+    /// The current time in large text, redrawn every minute.
+    Clock {
+        /// format of the time, with the conversions of `strftime`, such as `%H:%M`
+        format: String,
+        color: [f32; 3],
+        background: [f32; 3],
+    },
+}
+
+/// Method of compositing an overlay image on top of a base image.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq, PartialOrd)]
+pub enum BlendMode {
+    /// Replace the base with the overlay
+    #[default]
+    Normal,
+    /// Multiply the base with the overlay, darkening the result
+    Multiply,
+    /// Invert, multiply, and invert again, lightening the result
+    Screen,
+    /// Multiply dark areas and screen light areas of the base
+    Overlay,
+}
+
+impl Source {
+    /// A background of a single color.
+    ///
+    /// ```
+    /// use cosmic_bg_config::{Color, Source};
+    ///
+    /// let black = Source::solid([0.0, 0.0, 0.0]);
+    /// assert!(black.is_color());
+    /// assert_eq!(black.as_color(), Some(&Color::Single([0.0, 0.0, 0.0])));
+    /// ```
+    #[must_use]
+    pub fn solid(rgb: [f32; 3]) -> Self {
+        Self::Color(Color::Single(rgb))
+    }
+
+    /// A background colored by a gradient of `colors` with the given radius.
+    ///
+    /// ```
+    /// use cosmic_bg_config::{Color, Source};
+    ///
+    /// let sunset = Source::gradient(vec![[1.0, 0.5, 0.0], [0.2, 0.0, 0.4]], 180.0);
+    /// let Some(Color::Gradient(gradient)) = sunset.as_color() else {
+    ///     panic!("not a gradient");
+    /// };
+    /// assert_eq!(gradient.colors.len(), 2);
+    /// ```
+    #[must_use]
+    pub fn gradient(colors: impl Into<Cow<'static, [[f32; 3]]>>, radius: f32) -> Self {
+        Self::Color(Color::Gradient(Gradient {
+            colors: colors.into(),
+            radius,
+        }))
+    }
+
+    /// Whether the background is a color or gradient rather than an image.
+    #[must_use]
+    pub fn is_color(&self) -> bool {
+        matches!(self, Self::Color(_))
+    }
+
+    /// The color or gradient of the background, if it is not an image.
+    #[must_use]
+    pub fn as_color(&self) -> Option<&Color> {
+        match self {
+            Self::Color(color) => Some(color),
+            _ => None,
+        }
+    }
 }
 
 impl Entry {
@@ -127,39 +631,137 @@ impl Entry {
             output,
             source,
             filter_by_theme: false,
-            rotation_frequency: 900,
+            rotation_frequency: default_rotation_frequency(),
             filter_method: FilterMethod::default(),
             scaling_mode: ScalingMode::default(),
             sampling_method: SamplingMethod::default(),
+            parallax_strength: 0.0,
+            anchor: Vec::new(),
+            margin_top: 0,
+            margin_right: 0,
+            margin_bottom: 0,
+            margin_left: 0,
+            startup_fade_ms: 0,
+            preserve_alpha: false,
+            fit_colors: HashMap::new(),
+            fit_gravity: [0.5, 0.5],
+            vignette: 0.0,
+            inset: 0,
+            corner_radius: 0,
+            preload: 0,
+            no_repeat_window: 0,
+            exclude: Vec::new(),
+            reshuffle_on_change: false,
+            image_opacity: 1.0,
+            background_color: None,
+            gradient_blend_space: GradientBlendSpace::default(),
         }
     }
 
-    /// Fallback in case config and default schema can't be loaded
+    /// Fallback in case config and default schema can't be loaded, showing the
+    /// first system wallpaper that exists, or else a gradient.
     pub fn fallback() -> Self {
         Self {
-            output: String::from("all"),
-            source: Source::Path(PathBuf::from(
-                "/usr/share/backgrounds/cosmic/orion_nebula_nasa_heic0601a.jpg",
-            )),
             filter_by_theme: true,
             rotation_frequency: 3600,
-            filter_method: FilterMethod::default(),
-            scaling_mode: ScalingMode::default(),
-            sampling_method: SamplingMethod::default(),
+            ..Self::new(String::from("all"), fallback_source())
+        }
+    }
+}
+
+/// Color space that the colors of a gradient are interpolated in
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GradientBlendSpace {
+    // linear RGB
+    #[default]
+    LinearRgb,
+    // perceptually uniform Oklab, with smoother midpoints between distant hues
+    Oklab,
+    // hue, saturation and value, which interpolates around the color wheel
+    Hsv,
+    // hue, saturation and lightness, which colorgrad can't interpolate in, so
+    // the similar Hsv is used instead
+    Hsl,
+}
+
+impl From<GradientBlendSpace> for colorgrad::BlendMode {
+    fn from(space: GradientBlendSpace) -> Self {
+        match space {
+            GradientBlendSpace::LinearRgb => colorgrad::BlendMode::LinearRgb,
+            GradientBlendSpace::Oklab => colorgrad::BlendMode::Oklab,
+            GradientBlendSpace::Hsv => colorgrad::BlendMode::Hsv,
+            GradientBlendSpace::Hsl => {
+                tracing::warn!("gradients can't be interpolated in Hsl, falling back to Hsv");
+                colorgrad::BlendMode::Hsv
+            }
         }
     }
 }
 
 /// Image filtering method
-#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(from = "StoredFilterMethod", into = "StoredFilterMethod")]
 pub enum FilterMethod {
     // nearest neighbor filtering
     Nearest,
     // linear filtering
     Linear,
-    // lanczos filtering with window 3
-    #[default]
+    // lanczos filtering with a window of the given size, where sizes other
+    // than 3 are not supported yet and fall back to 3
+    Lanczos(u8),
+    // choose a filter by the ratio between the image and output sizes
+    Auto,
+    // mitchell-netravali cubic filtering, which is softer than lanczos
+    Mitchell,
+    // catmull-rom cubic filtering, which is sharper than mitchell
+    CatmullRom,
+}
+
+impl Default for FilterMethod {
+    fn default() -> Self {
+        Self::Lanczos(3)
+    }
+}
+
+/// A filtering method as stored in the config, where `Lanczos` remains the
+/// lanczos filter with window 3 that it was before windows could be chosen.
+#[derive(Deserialize, Serialize)]
+enum StoredFilterMethod {
+    Nearest,
+    Linear,
     Lanczos,
+    LanczosWindow(u8),
+    Auto,
+    Mitchell,
+    CatmullRom,
+}
+
+impl From<StoredFilterMethod> for FilterMethod {
+    fn from(method: StoredFilterMethod) -> Self {
+        match method {
+            StoredFilterMethod::Nearest => Self::Nearest,
+            StoredFilterMethod::Linear => Self::Linear,
+            StoredFilterMethod::Lanczos => Self::Lanczos(3),
+            StoredFilterMethod::LanczosWindow(window) => Self::Lanczos(window),
+            StoredFilterMethod::Auto => Self::Auto,
+            StoredFilterMethod::Mitchell => Self::Mitchell,
+            StoredFilterMethod::CatmullRom => Self::CatmullRom,
+        }
+    }
+}
+
+impl From<FilterMethod> for StoredFilterMethod {
+    fn from(method: FilterMethod) -> Self {
+        match method {
+            FilterMethod::Nearest => Self::Nearest,
+            FilterMethod::Linear => Self::Linear,
+            FilterMethod::Lanczos(3) => Self::Lanczos,
+            FilterMethod::Lanczos(window) => Self::LanczosWindow(window),
+            FilterMethod::Auto => Self::Auto,
+            FilterMethod::Mitchell => Self::Mitchell,
+            FilterMethod::CatmullRom => Self::CatmullRom,
+        }
+    }
 }
 
 impl From<FilterMethod> for image::imageops::FilterType {
@@ -167,7 +769,13 @@ impl From<FilterMethod> for image::imageops::FilterType {
         match method {
             FilterMethod::Nearest => image::imageops::FilterType::Nearest,
             FilterMethod::Linear => image::imageops::FilterType::Triangle,
-            FilterMethod::Lanczos => image::imageops::FilterType::Lanczos3,
+            // Lanczos3 is the only lanczos filter of the image crate.
+            FilterMethod::Lanczos(_) | FilterMethod::Auto => image::imageops::FilterType::Lanczos3,
+            // The image crate has no Mitchell filter, of which Catmull-Rom is the
+            // closest cubic filter.
+            FilterMethod::Mitchell | FilterMethod::CatmullRom => {
+                image::imageops::FilterType::CatmullRom
+            }
         }
     }
 }
@@ -180,9 +788,47 @@ pub enum SamplingMethod {
     Alphanumeric,
     // Rotate through images in Random order
     Random,
+    // Rotate through images in reverse Alphanumeric order
+    Reverse,
+    // Rotate through images in Alphanumeric order, then back in reverse order
+    PingPong,
+    // Rotate through the images of one subdirectory each day, in Alphanumeric order
+    DailyAlbum,
+    // Show the image named by today's date as `YYYY-MM-DD`, or else by the latest earlier date
+    ByDate,
     // TODO GnomeWallpapers
 }
 
+/// An action on every wallpaper, such as from a keybinding, or on the wallpaper
+/// of one output.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Show the next image of each slideshow
+    Next,
+    /// Show the previous image of each slideshow
+    Previous,
+    /// Show a random image of each slideshow
+    Random,
+    /// Reload the config and recreate every wallpaper
+    Reload,
+    /// Keep showing the current image of the slideshow of an output, across
+    /// restarts, until it is unpinned or another image is shown
+    Pin(String),
+    /// Resume the slideshow of an output
+    Unpin(String),
+}
+
+/// Wallpaper shown on an output connected while running, which has no
+/// background of its own.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NewOutputPolicy {
+    /// Show the default background
+    #[default]
+    Default,
+    /// Show the same wallpaper as the first connected output
+    MirrorPrimary,
+}
+
 /// Image scaling mode
 #[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
 pub enum ScalingMode {
@@ -193,6 +839,18 @@ pub enum ScalingMode {
     /// Zoom the image so that it fill the whole area
     #[default]
     Zoom,
+    /// Stretch the image towards the aspect ratio of the area by up to
+    /// `max_stretch` times, then zoom to crop what remains
+    SmartFill {
+        max_stretch: f32,
+    },
+    /// Fit the image over a blurred zoom of itself if the aspect ratios of the
+    /// image and the area differ by more than `threshold`, as a fraction of the
+    /// narrower one, such as a portrait image on a landscape output, and
+    /// otherwise zoom it
+    SmartBlur {
+        threshold: f32,
+    },
 }
 
 impl Entry {
@@ -200,6 +858,17 @@ impl Entry {
     pub fn key(&self) -> String {
         self.output.to_string()
     }
+
+    /// The scaling mode on an output, with the Fit color overridden for that output.
+    #[must_use]
+    pub fn scaling_mode_for(&self, output: &str) -> ScalingMode {
+        match self.scaling_mode {
+            ScalingMode::Fit(color) => {
+                ScalingMode::Fit(self.fit_colors.get(output).copied().unwrap_or(color))
+            }
+            ref mode => mode.clone(),
+        }
+    }
 }
 
 #[must_use]
@@ -209,6 +878,13 @@ pub struct Config {
     pub outputs: HashSet<String>,
     pub backgrounds: Vec<Entry>,
     pub default_background: Entry,
+    pub max_render_edge: u32,
+    pub output_render_size: HashMap<String, (u32, u32)>,
+    pub primary_output: Option<String>,
+    pub brightness_schedule: Vec<(u32, f32)>,
+    pub night_light: Option<u32>,
+    pub new_output_policy: NewOutputPolicy,
+    pub watch_depth: usize,
 }
 
 impl Default for Config {
@@ -218,6 +894,13 @@ impl Default for Config {
             outputs: HashSet::new(),
             backgrounds: Vec::new(),
             default_background: Entry::fallback(),
+            max_render_edge: 0,
+            output_render_size: HashMap::new(),
+            primary_output: None,
+            brightness_schedule: Vec::new(),
+            night_light: None,
+            new_output_policy: NewOutputPolicy::default(),
+            watch_depth: DEFAULT_WATCH_DEPTH,
         }
     }
 }
@@ -231,6 +914,13 @@ impl Config {
     pub fn load(context: &Context) -> Result<Self, cosmic_config::Error> {
         let mut config = Self {
             same_on_all: context.same_on_all(),
+            max_render_edge: context.max_render_edge(),
+            output_render_size: context.output_render_size(),
+            primary_output: context.primary_output(),
+            brightness_schedule: context.brightness_schedule(),
+            night_light: context.night_light(),
+            new_output_policy: context.new_output_policy(),
+            watch_depth: context.watch_depth(),
             ..Default::default()
         };
 
@@ -274,41 +964,657 @@ impl Config {
             .find(|entry| entry.output == output)
     }
 
-    /// Applies the entry for the given output to cosmic-config.
+    /// Checks that the source of an entry can be displayed, such as before a
+    /// settings app saves it.
+    ///
+    /// # Errors
+    ///
+    /// Fails if a path does not exist, is a directory without supported images,
+    /// or is a file of an unsupported format, or if a gradient has no colors.
+    pub fn validate_entry(entry: &Entry) -> Result<(), ValidationError> {
+        Self::validate_source(&entry.source)
+    }
+
+    /// Checks that a source can be displayed. A fallback chain can be if any of
+    /// its sources can, and otherwise fails with the error of its first source.
     ///
     /// # Errors
     ///
-    /// Fails if the config could not be set in cosmic-config.
+    /// Fails for the same reasons as [`Config::validate_entry`].
+    pub fn validate_source(source: &Source) -> Result<(), ValidationError> {
+        match *source {
+            Source::Path(ref path) => validate_path(path, true),
+            Source::Color(Color::Single(_)) => Ok(()),
+            Source::Color(Color::Gradient(ref gradient)) => {
+                if gradient.colors.is_empty() {
+                    Err(ValidationError::EmptyGradient)
+                } else {
+                    Ok(())
+                }
+            }
+            Source::Color(Color::Gradients(ref layers)) => {
+                if layers
+                    .iter()
+                    .any(|(gradient, ..)| gradient.colors.is_empty())
+                {
+                    Err(ValidationError::EmptyGradient)
+                } else {
+                    Ok(())
+                }
+            }
+            Source::Blend {
+                ref base,
+                ref overlay,
+                ..
+            } => {
+                validate_path(base, false)?;
+                validate_path(overlay, false)
+            }
+            Source::Archive(ref archive) => {
+                if !archive.is_file() {
+                    return Err(ValidationError::NotFound(archive.clone()));
+                }
+                if archive
+                    .extension()
+                    .map_or(true, |ext| ext != "zip" && ext != "tar")
+                {
+                    return Err(ValidationError::UnsupportedFormat(archive.clone()));
+                }
+                Ok(())
+            }
+            Source::None | Source::Clock { .. } | Source::AutoMatchPrimary => Ok(()),
+            Source::Split {
+                ref left,
+                ref right,
+                ..
+            } => {
+                for side in [left, right] {
+                    match **side {
+                        Source::Path(ref path) => validate_path(path, false)?,
+                        Source::Color(_) => Self::validate_source(side)?,
+                        _ => return Err(ValidationError::UnsupportedSplitSide),
+                    }
+                }
+
+                Ok(())
+            }
+            Source::Fallback(ref sources) => {
+                let mut first_error = None;
+                for source in sources {
+                    match Self::validate_source(source) {
+                        Ok(()) => return Ok(()),
+                        Err(why) => {
+                            first_error.get_or_insert(why);
+                        }
+                    }
+                }
+
+                Err(first_error.unwrap_or(ValidationError::EmptyFallback))
+            }
+            Source::Hashed {
+                ref search_paths, ..
+            } => search_paths.iter().try_for_each(|path| {
+                if path.is_dir() {
+                    Ok(())
+                } else {
+                    Err(ValidationError::NotFound(path.clone()))
+                }
+            }),
+        }
+    }
+
+    /// Applies the entry for the given output to cosmic-config, along with the
+    /// list of outputs and the recent wallpapers in the same transaction.
+    ///
+    /// Nothing is written, and the in-memory config is unchanged, if any write fails.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the entry or the list of outputs could not be set in cosmic-config.
     pub fn set_entry(
         &mut self,
         context: &Context,
         entry: Entry,
     ) -> Result<(), cosmic_config::Error> {
-        let output_key = if entry.output == "all" {
-            entry.output.clone()
-        } else {
-            self.outputs.insert(entry.output.clone());
-            ["output.", &entry.output].concat()
-        };
+        self.set_entries(context, vec![entry])
+    }
+
+    /// Applies the entries of several outputs to cosmic-config in one transaction,
+    /// writing the list of outputs only once, so that cosmic-bg applies them all
+    /// together instead of after each entry.
+    ///
+    /// Their sources are moved to the front of the recent wallpapers in the same
+    /// transaction.
+    ///
+    /// Nothing is written, and the in-memory config is unchanged, if any write fails.
+    ///
+    /// # Errors
+    ///
+    /// Fails if an entry could not be serialized, or the transaction could not be
+    /// committed to cosmic-config.
+    pub fn set_entries(
+        &mut self,
+        context: &Context,
+        entries: Vec<Entry>,
+    ) -> Result<(), cosmic_config::Error> {
+        self.store_entries(context, entries)
+    }
 
-        if context.0.get(&output_key).ok().as_ref() != Some(&entry) {
-            context.0.set(&output_key, entry.clone())?;
+    fn store_entries<S: Store>(
+        &mut self,
+        store: &S,
+        entries: Vec<Entry>,
+    ) -> Result<(), cosmic_config::Error> {
+        let tx = store.transaction();
+        let mut outputs = self.outputs.clone();
+
+        for entry in &entries {
+            let is_output = entry.output != "all";
+
+            let output_key = if is_output {
+                ["output.", &entry.output].concat()
+            } else {
+                entry.output.clone()
+            };
+
+            if store.entry(&output_key).ok().as_ref() != Some(entry) {
+                tx.set(&output_key, entry)?;
+            }
+
+            if is_output {
+                outputs.insert(entry.output.clone());
+            }
         }
 
-        if let Some(old) = self.entry_mut(&output_key) {
-            *old = entry;
-        } else if entry.output != "all" {
-            self.backgrounds.push(entry);
+        let new_value = outputs.iter().cloned().collect::<Vec<_>>();
+
+        if store.backgrounds() != new_value {
+            tx.set(BACKGROUNDS, &new_value)?;
+        }
+
+        let old_recent = store.recent_wallpapers();
+        let mut recent = old_recent.clone();
+        for entry in &entries {
+            push_recent(&mut recent, &entry.source);
+        }
+
+        if recent != old_recent {
+            tx.set(RECENT_WALLPAPERS, &recent)?;
         }
 
-        let new_value = self.outputs.iter().cloned().collect::<Vec<_>>();
+        if let Err(why) = tx.commit() {
+            tracing::error!(?why, "failed to set entries");
+            return Err(why);
+        }
+
+        self.outputs = outputs;
 
-        if context.backgrounds() != new_value {
-            if let Err(why) = context.0.set::<Vec<String>>(BACKGROUNDS, new_value) {
-                tracing::error!(?why, "failed to update outputs");
+        for entry in entries {
+            if let Some(old) = self.entry_mut(&entry.output) {
+                *old = entry;
+            } else if entry.output != "all" {
+                self.backgrounds.push(entry);
             }
         }
 
         Ok(())
     }
 }
+
+/// The keys of the config that entries are saved to, which tests replace with
+/// one that can fail.
+trait Store {
+    type Transaction<'a>: Transaction
+    where
+        Self: 'a;
+
+    fn entry(&self, key: &str) -> Result<Entry, cosmic_config::Error>;
+
+    fn backgrounds(&self) -> Vec<String>;
+
+    fn recent_wallpapers(&self) -> Vec<Source>;
+
+    fn transaction(&self) -> Self::Transaction<'_>;
+}
+
+/// Writes to a [`Store`] which only take effect together once committed.
+trait Transaction {
+    fn set<T: Serialize>(&self, key: &str, value: T) -> Result<(), cosmic_config::Error>;
+
+    fn commit(self) -> Result<(), cosmic_config::Error>;
+}
+
+impl Store for Context {
+    type Transaction<'a> = cosmic_config::ConfigTransaction<'a>;
+
+    fn entry(&self, key: &str) -> Result<Entry, cosmic_config::Error> {
+        Context::entry(self, key)
+    }
+
+    fn backgrounds(&self) -> Vec<String> {
+        Context::backgrounds(self)
+    }
+
+    fn recent_wallpapers(&self) -> Vec<Source> {
+        Context::recent_wallpapers(self)
+    }
+
+    fn transaction(&self) -> Self::Transaction<'_> {
+        self.0.transaction()
+    }
+}
+
+impl Transaction for cosmic_config::ConfigTransaction<'_> {
+    fn set<T: Serialize>(&self, key: &str, value: T) -> Result<(), cosmic_config::Error> {
+        ConfigSet::set(self, key, value)
+    }
+
+    fn commit(self) -> Result<(), cosmic_config::Error> {
+        cosmic_config::ConfigTransaction::commit(self)
+    }
+}
+
+/// Moves a source to the front of a history of recent wallpapers, or adds it
+/// there, forgetting the oldest beyond [`RECENT_WALLPAPERS_LIMIT`].
+fn push_recent(recent: &mut Vec<Source>, source: &Source) {
+    recent.retain(|recent| recent != source);
+    recent.insert(0, source.clone());
+    recent.truncate(RECENT_WALLPAPERS_LIMIT);
+}
+
+/// Checks that a path is a supported image, or a directory with one if `allow_dir`.
+fn validate_path(path: &Path, allow_dir: bool) -> Result<(), ValidationError> {
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return Err(ValidationError::NotFound(path.to_path_buf()));
+    };
+
+    if metadata.is_dir() && allow_dir {
+        if dir_has_images(path) {
+            return Ok(());
+        }
+
+        return Err(ValidationError::NoImages(path.to_path_buf()));
+    }
+
+    if metadata.is_file() && is_supported_image(path) {
+        Ok(())
+    } else {
+        Err(ValidationError::UnsupportedFormat(path.to_path_buf()))
+    }
+}
+
+/// Checks if a directory or any of its subdirectories contains a supported image.
+fn dir_has_images(path: &Path) -> bool {
+    let Ok(dir) = std::fs::read_dir(path) else {
+        return false;
+    };
+
+    dir.filter_map(Result::ok).any(|entry| {
+        let path = entry.path();
+        // Symlinked directories are not followed, which could loop.
+        if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            dir_has_images(&path)
+        } else {
+            path.is_file() && is_supported_image(&path)
+        }
+    })
+}
+
+/// Linear colors of the gradient shown when no fallback background exists.
+const FALLBACK_GRADIENT: &[[f32; 3]] = &[[0.004, 0.008, 0.02], [0.03, 0.027, 0.09]];
+
+/// The first of the [`FALLBACK_BACKGROUNDS`] that exists, or a dark gradient if
+/// none do, so that some background is always visible.
+fn fallback_source() -> Source {
+    first_background(FALLBACK_BACKGROUNDS.iter().map(PathBuf::from))
+}
+
+/// The first of the `candidates` that exists, or the fallback gradient.
+fn first_background(mut candidates: impl Iterator<Item = PathBuf>) -> Source {
+    candidates
+        .find(|path| path.is_file())
+        .map_or_else(|| Source::gradient(FALLBACK_GRADIENT, 180.0), Source::Path)
+}
+
+/// Rotation frequency of slideshows unless configured otherwise, in seconds.
+fn default_rotation_frequency() -> u64 {
+    900
+}
+
+/// Opacity of images unless configured otherwise.
+fn full_opacity() -> f32 {
+    1.0
+}
+
+fn centered() -> [f32; 2] {
+    [0.5, 0.5]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[test]
+    fn fit_color_falls_back_to_the_entry() {
+        let mut entry = Entry::new(String::from("all"), Source::solid([0.0, 0.0, 0.0]));
+        entry.scaling_mode = ScalingMode::Fit([0.1, 0.2, 0.3]);
+        entry
+            .fit_colors
+            .insert(String::from("DP-1"), [1.0, 0.0, 0.0]);
+
+        assert_eq!(
+            entry.scaling_mode_for("DP-1"),
+            ScalingMode::Fit([1.0, 0.0, 0.0])
+        );
+        assert_eq!(
+            entry.scaling_mode_for("HDMI-A-1"),
+            ScalingMode::Fit([0.1, 0.2, 0.3])
+        );
+
+        // Outputs without a Fit scaling mode ignore the colors.
+        entry.scaling_mode = ScalingMode::Zoom;
+        assert_eq!(entry.scaling_mode_for("DP-1"), ScalingMode::Zoom);
+    }
+
+    #[test]
+    fn supported_formats_include_common_images() {
+        let extensions = supported_sources().image_extensions;
+
+        for extension in ["png", "jpg", "jxl"] {
+            assert!(extensions.contains(&extension), "{extension} is missing");
+        }
+
+        // Videos aren't decoded, so no video formats are advertised.
+        for extension in ["mp4", "webm", "mkv"] {
+            assert!(!extensions.contains(&extension), "{extension} is listed");
+        }
+
+        assert!(is_supported_image(Path::new("/tmp/a.JPG")));
+        assert!(!is_supported_image(Path::new("/tmp/a.mp4")));
+    }
+
+    #[test]
+    fn validates_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing.png");
+        assert_eq!(
+            Config::validate_source(&Source::Path(missing.clone())),
+            Err(ValidationError::NotFound(missing))
+        );
+
+        let empty = dir.path().join("empty");
+        std::fs::create_dir(&empty).unwrap();
+        std::fs::write(empty.join("notes.txt"), "").unwrap();
+        assert_eq!(
+            Config::validate_source(&Source::Path(empty.clone())),
+            Err(ValidationError::NoImages(empty))
+        );
+
+        let text = dir.path().join("empty/notes.txt");
+        assert_eq!(
+            Config::validate_source(&Source::Path(text.clone())),
+            Err(ValidationError::UnsupportedFormat(text))
+        );
+
+        let image = dir.path().join("album/nested/a.png");
+        std::fs::create_dir_all(image.parent().unwrap()).unwrap();
+        std::fs::write(&image, "").unwrap();
+        assert_eq!(Config::validate_source(&Source::Path(image)), Ok(()));
+        assert_eq!(
+            Config::validate_source(&Source::Path(dir.path().join("album"))),
+            Ok(())
+        );
+    }
+
+    /// A store keeping RON values in memory, whose transactions fail when they
+    /// write `failing_key`.
+    #[derive(Default)]
+    struct MockStore {
+        values: RefCell<HashMap<String, String>>,
+        failing_key: Option<&'static str>,
+        /// Keys written by each committed transaction.
+        commits: RefCell<Vec<Vec<String>>>,
+    }
+
+    struct MockTransaction<'a> {
+        store: &'a MockStore,
+        updates: RefCell<Vec<(String, String)>>,
+    }
+
+    impl Store for MockStore {
+        type Transaction<'a> = MockTransaction<'a>;
+
+        fn entry(&self, key: &str) -> Result<Entry, cosmic_config::Error> {
+            let values = self.values.borrow();
+            let value = values.get(key).ok_or(cosmic_config::Error::NotFound)?;
+            Ok(ron::from_str(value).unwrap())
+        }
+
+        fn backgrounds(&self) -> Vec<String> {
+            self.values
+                .borrow()
+                .get(BACKGROUNDS)
+                .map(|value| ron::from_str(value).unwrap())
+                .unwrap_or_default()
+        }
+
+        fn recent_wallpapers(&self) -> Vec<Source> {
+            self.values
+                .borrow()
+                .get(RECENT_WALLPAPERS)
+                .map(|value| ron::from_str(value).unwrap())
+                .unwrap_or_default()
+        }
+
+        fn transaction(&self) -> Self::Transaction<'_> {
+            MockTransaction {
+                store: self,
+                updates: RefCell::default(),
+            }
+        }
+    }
+
+    impl Transaction for MockTransaction<'_> {
+        fn set<T: Serialize>(&self, key: &str, value: T) -> Result<(), cosmic_config::Error> {
+            let value = ron::to_string(&value).unwrap();
+            self.updates.borrow_mut().push((key.to_owned(), value));
+            Ok(())
+        }
+
+        fn commit(self) -> Result<(), cosmic_config::Error> {
+            let updates = self.updates.into_inner();
+
+            if updates
+                .iter()
+                .any(|(key, _)| Some(key.as_str()) == self.store.failing_key)
+            {
+                return Err(cosmic_config::Error::Io(std::io::Error::other(
+                    "write failed",
+                )));
+            }
+
+            let keys = updates.iter().map(|(key, _)| key.clone()).collect();
+            self.store.commits.borrow_mut().push(keys);
+            self.store.values.borrow_mut().extend(updates);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn entry_and_outputs_are_written_together() {
+        let store = MockStore::default();
+        let mut config = Config::default();
+        let entry = Entry::new(String::from("DP-1"), Source::solid([1.0, 0.0, 0.0]));
+
+        config.store_entries(&store, vec![entry.clone()]).unwrap();
+
+        assert_eq!(store.entry("output.DP-1").unwrap(), entry);
+        assert_eq!(store.backgrounds(), ["DP-1"]);
+        assert_eq!(config.entry("DP-1"), Some(&entry));
+        assert!(config.outputs.contains("DP-1"));
+    }
+
+    #[test]
+    fn several_entries_write_outputs_once() {
+        let store = MockStore::default();
+        let mut config = Config::default();
+        let entries = ["DP-1", "DP-2", "HDMI-A-1"]
+            .map(|output| Entry::new(String::from(output), Source::solid([0.0, 0.0, 1.0])));
+
+        config.store_entries(&store, entries.to_vec()).unwrap();
+
+        let commits = store.commits.borrow();
+        assert_eq!(commits.len(), 1);
+        assert_eq!(
+            commits[0],
+            [
+                "output.DP-1",
+                "output.DP-2",
+                "output.HDMI-A-1",
+                BACKGROUNDS,
+                RECENT_WALLPAPERS
+            ]
+        );
+        drop(commits);
+
+        let mut backgrounds = store.backgrounds();
+        backgrounds.sort();
+        assert_eq!(backgrounds, ["DP-1", "DP-2", "HDMI-A-1"]);
+
+        // Entries that are unchanged are not written again.
+        config.store_entries(&store, entries[..1].to_vec()).unwrap();
+        assert!(store.commits.borrow()[1].is_empty());
+    }
+
+    #[test]
+    fn recent_wallpapers_are_deduplicated_and_capped() {
+        let store = MockStore::default();
+        let mut config = Config::default();
+        let set = |config: &mut Config, source: Source| {
+            let entry = Entry::new(String::from("DP-1"), source);
+            config.store_entries(&store, vec![entry]).unwrap();
+        };
+
+        let red = Source::solid([1.0, 0.0, 0.0]);
+        let forest = Source::Path(PathBuf::from("/forest.jpg"));
+        set(&mut config, red.clone());
+        set(&mut config, forest.clone());
+        set(&mut config, red.clone());
+        assert_eq!(store.recent_wallpapers(), [red.clone(), forest.clone()]);
+
+        // Each save writes the history once, with its entries.
+        let entries =
+            ["DP-1", "DP-2"].map(|output| Entry::new(String::from(output), forest.clone()));
+        config.store_entries(&store, entries.to_vec()).unwrap();
+        assert_eq!(store.recent_wallpapers(), [forest.clone(), red]);
+        assert_eq!(
+            store.commits.borrow().last().unwrap(),
+            &["output.DP-1", "output.DP-2", BACKGROUNDS, RECENT_WALLPAPERS]
+        );
+
+        for index in 0..RECENT_WALLPAPERS_LIMIT {
+            set(
+                &mut config,
+                Source::Path(PathBuf::from(format!("/{index}.jpg"))),
+            );
+        }
+
+        let recent = store.recent_wallpapers();
+        assert_eq!(recent.len(), RECENT_WALLPAPERS_LIMIT);
+        assert_eq!(
+            recent[0],
+            Source::Path(PathBuf::from(format!(
+                "/{}.jpg",
+                RECENT_WALLPAPERS_LIMIT - 1
+            )))
+        );
+        assert!(!recent.contains(&forest));
+    }
+
+    #[test]
+    fn failed_outputs_write_leaves_config_unchanged() {
+        let store = MockStore {
+            failing_key: Some(BACKGROUNDS),
+            ..MockStore::default()
+        };
+        let mut config = Config::default();
+        let before = config.clone();
+        let entry = Entry::new(String::from("DP-1"), Source::solid([1.0, 0.0, 0.0]));
+
+        assert!(config.store_entries(&store, vec![entry]).is_err());
+
+        // Neither the entry nor the list of outputs was written.
+        assert!(store.values.borrow().is_empty());
+        assert_eq!(config, before);
+    }
+
+    #[test]
+    fn hex_colors_round_trip_through_linear() {
+        for hex in ["000000", "ffffff", "336699", "0a0b0c", "808080"] {
+            let Some(Color::Single(color)) = Color::from_hex(hex) else {
+                panic!("{hex} is not a single color");
+            };
+
+            let encoded = color.map(|c| (linear_to_srgb(c) * 255.0).round() as u8);
+            assert_eq!(
+                format!("{:02x}{:02x}{:02x}", encoded[0], encoded[1], encoded[2]),
+                hex
+            );
+        }
+
+        assert_eq!(Color::from_hex("#ffffff"), Color::from_hex("ffffff"));
+        assert_eq!(Color::from_hex("#fffff"), None);
+        assert_eq!(Color::from_hex("#gggggg"), None);
+    }
+
+    #[test]
+    fn fallback_is_a_gradient_without_system_backgrounds() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing.jpg");
+        let existing = dir.path().join("default.png");
+
+        assert_eq!(
+            first_background([missing.clone()].into_iter()),
+            Source::gradient(FALLBACK_GRADIENT, 180.0)
+        );
+
+        std::fs::write(&existing, b"png").unwrap();
+        assert_eq!(
+            first_background([missing, existing.clone()].into_iter()),
+            Source::Path(existing)
+        );
+    }
+
+    #[test]
+    fn entries_from_other_versions_are_read() {
+        let entry: Entry =
+            ron::from_str(r#"(output: "DP-1", source: Path("/a.png"), from_the_future: [1, 2])"#)
+                .unwrap();
+
+        assert_eq!(
+            entry,
+            Entry::new(String::from("DP-1"), Source::Path(PathBuf::from("/a.png")))
+        );
+    }
+
+    #[test]
+    fn lanczos_filters_keep_their_stored_names() {
+        let read = |ron: &str| ron::from_str::<FilterMethod>(ron).unwrap();
+
+        // `Lanczos` was stored before windows could be chosen.
+        assert_eq!(read("Lanczos"), FilterMethod::Lanczos(3));
+        assert_eq!(read("LanczosWindow(2)"), FilterMethod::Lanczos(2));
+        assert_eq!(FilterMethod::default(), FilterMethod::Lanczos(3));
+
+        assert_eq!(
+            ron::to_string(&FilterMethod::Lanczos(3)).unwrap(),
+            "Lanczos"
+        );
+        assert_eq!(
+            ron::to_string(&FilterMethod::Lanczos(2)).unwrap(),
+            "LanczosWindow(2)"
+        );
+        assert_eq!(read("CatmullRom"), FilterMethod::CatmullRom);
+    }
+}
@@ -1,16 +1,46 @@
-use cosmic_config::{cosmic_config_derive::CosmicConfigEntry, Config, CosmicConfigEntry};
+use std::path::{Path, PathBuf};
+
+use cosmic_config::{
+    cosmic_config_derive::CosmicConfigEntry, Config, ConfigGet, ConfigSet, CosmicConfigEntry,
+};
 use derive_setters::Setters;
 use serde::{Deserialize, Serialize};
 
 use crate::{Source, NAME};
 
+// Unknown fields are ignored like those of `Entry`, for state written by newer
+// versions.
 #[derive(Default, Debug, Deserialize, Serialize, Clone, PartialEq, Setters, CosmicConfigEntry)]
-#[serde(deny_unknown_fields)]
 #[must_use]
 pub struct State {
     /// The active wallpaper for each output
     /// (output_name, source of wallpaper)
     pub wallpapers: Vec<(String, Source)>,
+    /// The display connected to each output when its wallpaper was saved
+    /// (output_name, make and model of the display)
+    #[serde(default)]
+    pub displays: Vec<(String, String)>,
+    /// Outputs of the wallpapers whose slideshows are pinned to the image saved
+    /// for them
+    #[serde(default)]
+    pub pinned: Vec<String>,
+}
+
+/// Whether the wallpaper of an output could be displayed, for the settings app
+/// to show a warning when it couldn't.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum Status {
+    /// The wallpaper is displayed
+    #[default]
+    Ok,
+    /// The image could not be decoded
+    DecodeFailed(PathBuf),
+    /// The source path does not exist
+    SourceMissing(PathBuf),
+    /// The source directory contains no images
+    NoImages(PathBuf),
+    /// No image in the search paths has the content hash
+    HashNotFound(String),
 }
 
 impl State {
@@ -21,4 +51,165 @@ impl State {
     pub fn state() -> Result<Config, cosmic_config::Error> {
         Config::new_state(NAME, Self::version())
     }
+
+    /// The state stored within `dir` instead of the XDG state directory.
+    pub fn state_in(dir: &Path) -> Result<Config, cosmic_config::Error> {
+        Config::with_custom_path(NAME, Self::version(), dir.to_path_buf())
+    }
+
+    /// The wallpaper saved for an output, preferring the one saved for the same
+    /// display, so that a different display plugged into a reused connector
+    /// doesn't resume the wallpaper of the previous one.
+    ///
+    /// Wallpapers saved without a display are matched by connector name.
+    #[must_use]
+    pub fn wallpaper(&self, name: &str, display: &str) -> Option<&Source> {
+        let find = |matches: &dyn Fn(&str) -> bool| {
+            self.wallpapers
+                .iter()
+                .find(|(output, _)| matches(output))
+                .map(|(_, source)| source)
+        };
+
+        find(&|output| output == name && self.display(output) == Some(display))
+            .or_else(|| find(&|output| self.display(output) == Some(display)))
+            .or_else(|| find(&|output| output == name && self.display(output).is_none()))
+    }
+
+    /// The display an output's wallpaper was saved for.
+    #[must_use]
+    pub fn display(&self, name: &str) -> Option<&str> {
+        self.displays
+            .iter()
+            .find(|(output, _)| output == name)
+            .map(|(_, display)| display.as_str())
+    }
+
+    /// Saves the wallpaper of an output along with its display.
+    pub fn set_wallpaper(&mut self, name: &str, display: &str, source: Source) {
+        match self
+            .wallpapers
+            .iter_mut()
+            .find(|(output, _)| output == name)
+        {
+            Some((_, saved)) => *saved = source,
+            None => self.wallpapers.push((name.to_owned(), source)),
+        }
+
+        match self.displays.iter_mut().find(|(output, _)| output == name) {
+            Some((_, saved)) => display.clone_into(saved),
+            None => self.displays.push((name.to_owned(), display.to_owned())),
+        }
+    }
+
+    /// Whether the slideshow of an output is pinned to its saved image.
+    #[must_use]
+    pub fn is_pinned(&self, output: &str) -> bool {
+        self.pinned.iter().any(|pinned| pinned == output)
+    }
+
+    /// Pins or unpins the slideshow of an output.
+    pub fn set_pinned(&mut self, output: &str, pinned: bool) {
+        self.pinned.retain(|existing| existing != output);
+        if pinned {
+            self.pinned.push(output.to_owned());
+        }
+    }
+
+    /// Forgets the wallpaper saved for an output.
+    pub fn remove_output(&mut self, name: &str) {
+        self.wallpapers.retain(|(output, _)| output != name);
+        self.displays.retain(|(output, _)| output != name);
+    }
+
+    /// Records the status of the wallpaper of an output.
+    pub fn set_status(
+        state: &Config,
+        output: &str,
+        status: &Status,
+    ) -> Result<(), cosmic_config::Error> {
+        state.set(&["status.", output].concat(), status)
+    }
+
+    /// Status of the wallpaper of an output, which is `Ok` if none was recorded.
+    pub fn status(state: &Config, output: &str) -> Status {
+        state.get(&["status.", output].concat()).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_is_recorded_per_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = State::state_in(dir.path()).unwrap();
+        let status = Status::DecodeFailed(PathBuf::from("/tmp/broken.png"));
+
+        State::set_status(&state, "DP-1", &status).unwrap();
+
+        assert!(dir
+            .path()
+            .join("cosmic")
+            .join(NAME)
+            .join("v1/status.DP-1")
+            .is_file());
+        assert_eq!(State::status(&state, "DP-1"), status);
+        assert_eq!(State::status(&state, "HDMI-A-1"), Status::Ok);
+    }
+
+    #[test]
+    fn wallpaper_of_reused_connector_follows_the_display() {
+        let forest = Source::Path(PathBuf::from("/usr/share/backgrounds/forest.jpg"));
+        let desert = Source::Path(PathBuf::from("/usr/share/backgrounds/desert.jpg"));
+
+        let mut state = State::default();
+        state.set_wallpaper("DP-1", "Dell Inc. U2720Q", forest.clone());
+
+        assert_eq!(state.wallpaper("DP-1", "Dell Inc. U2720Q"), Some(&forest));
+        assert_eq!(state.wallpaper("DP-1", "LG Electronics 27UL850"), None);
+
+        // The display keeps its wallpaper on a different connector.
+        assert_eq!(state.wallpaper("DP-2", "Dell Inc. U2720Q"), Some(&forest));
+
+        state.set_wallpaper("DP-1", "LG Electronics 27UL850", desert.clone());
+        assert_eq!(
+            state.wallpaper("DP-1", "LG Electronics 27UL850"),
+            Some(&desert)
+        );
+        assert_eq!(state.wallpaper("DP-1", "Dell Inc. U2720Q"), None);
+
+        // Wallpapers saved before displays were tracked match by connector.
+        let legacy = State {
+            wallpapers: vec![(String::from("DP-1"), forest.clone())],
+            ..State::default()
+        };
+        assert_eq!(legacy.wallpaper("DP-1", "Dell Inc. U2720Q"), Some(&forest));
+    }
+
+    #[test]
+    fn pins_keep_the_saved_wallpaper() {
+        let forest = Source::Path(PathBuf::from("/usr/share/backgrounds/forest.jpg"));
+        let mut state = State::default();
+        state.set_wallpaper("DP-1", "Dell Inc. U2720Q", forest.clone());
+
+        state.set_pinned("DP-1", true);
+        state.set_pinned("DP-1", true);
+        assert_eq!(state.pinned, ["DP-1"]);
+        assert!(state.is_pinned("DP-1"));
+        assert!(!state.is_pinned("HDMI-A-1"));
+
+        // Pins survive a restart along with the image they are pinned to.
+        let restored: State = ron::from_str(&ron::to_string(&state).unwrap()).unwrap();
+        assert!(restored.is_pinned("DP-1"));
+        assert_eq!(
+            restored.wallpaper("DP-1", "Dell Inc. U2720Q"),
+            Some(&forest)
+        );
+
+        state.set_pinned("DP-1", false);
+        assert!(!state.is_pinned("DP-1"));
+        assert_eq!(state.wallpaper("DP-1", "Dell Inc. U2720Q"), Some(&forest));
+    }
 }
@@ -0,0 +1,228 @@
+// SPDX-License-Identifier: MPL-2.0-only
+
+//! Reading images from zip and tar archives without extracting them.
+//!
+//! Images within an archive are addressed by virtual paths which join the path
+//! of the archive with the name of the entry, such as `pack.zip/forest.jpg`.
+//!
+//! The entries of an archive are located once, when it is listed, and kept until
+//! the archive is modified, so that reading each image doesn't parse it again.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex, PoisonError},
+    time::SystemTime,
+};
+
+/// The entries of each archive that was read, and when it was last modified.
+static INDEXES: LazyLock<Mutex<Indexes>> = LazyLock::new(Mutex::default);
+
+type Indexes = HashMap<PathBuf, (Option<SystemTime>, Arc<Mutex<Index>>)>;
+
+/// The image entries of an archive, in the order they are stored.
+enum Index {
+    /// Names of the images of a zip archive, which is kept open to read them
+    /// through its central directory.
+    Zip(Vec<String>, zip::ZipArchive<File>),
+    /// Names of the images of a tar archive, and the offsets and sizes of their
+    /// contents.
+    Tar(Vec<(String, u64, u64)>),
+}
+
+/// Names of the image entries in an archive, in the order they are stored.
+pub fn list(archive: &Path) -> io::Result<Vec<String>> {
+    let index = index(archive)?;
+    let index = index.lock().unwrap_or_else(PoisonError::into_inner);
+
+    Ok(match *index {
+        Index::Zip(ref names, _) => names.clone(),
+        Index::Tar(ref entries) => entries.iter().map(|(name, ..)| name.clone()).collect(),
+    })
+}
+
+/// Reads the contents of the entry `name` of an archive into memory.
+pub fn read(archive: &Path, name: &str) -> io::Result<Vec<u8>> {
+    let index = index(archive)?;
+    let mut data = Vec::new();
+
+    let (offset, size) = match *index.lock().unwrap_or_else(PoisonError::into_inner) {
+        Index::Zip(_, ref mut zip) => {
+            zip.by_name(name)?.read_to_end(&mut data)?;
+            return Ok(data);
+        }
+
+        Index::Tar(ref entries) => entries
+            .iter()
+            .find(|(entry, ..)| entry == name)
+            .map(|&(_, offset, size)| (offset, size))
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?,
+    };
+
+    let mut file = File::open(archive)?;
+    file.seek(SeekFrom::Start(offset))?;
+    file.take(size).read_to_end(&mut data)?;
+
+    Ok(data)
+}
+
+/// Splits a virtual path into its archive and the name of the entry within it.
+///
+/// Returns `None` if no ancestor of the path is an archive.
+#[must_use]
+pub fn split(path: &Path) -> Option<(PathBuf, String)> {
+    path.ancestors().skip(1).find_map(|archive| {
+        if !is_archive(archive) || !archive.is_file() {
+            return None;
+        }
+
+        let name = path.strip_prefix(archive).ok()?.to_str()?.to_owned();
+        Some((archive.to_path_buf(), name))
+    })
+}
+
+/// Checks if a path has the extension of a zip or tar archive.
+#[must_use]
+pub fn is_archive(path: &Path) -> bool {
+    is_zip(path) || path.extension().is_some_and(|ext| ext == "tar")
+}
+
+fn is_zip(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "zip")
+}
+
+/// The entries of an archive, which are located again if it was modified since.
+fn index(archive: &Path) -> io::Result<Arc<Mutex<Index>>> {
+    let modified = std::fs::metadata(archive)?.modified().ok();
+    let mut indexes = INDEXES.lock().unwrap_or_else(PoisonError::into_inner);
+
+    if let Some((indexed, index)) = indexes.get(archive) {
+        if *indexed == modified {
+            return Ok(index.clone());
+        }
+    }
+
+    tracing::debug!(?archive, "indexing archive");
+
+    let index = Arc::new(Mutex::new(read_index(archive)?));
+    indexes.insert(archive.to_path_buf(), (modified, index.clone()));
+
+    Ok(index)
+}
+
+fn read_index(archive: &Path) -> io::Result<Index> {
+    let file = File::open(archive)?;
+    let is_image = |name: &str| cosmic_bg_config::is_supported_image(Path::new(name));
+
+    if is_zip(archive) {
+        let mut zip = zip::ZipArchive::new(file)?;
+
+        let names = (0..zip.len())
+            .filter_map(|index| {
+                let entry = zip.by_index_raw(index).ok()?;
+                (entry.is_file() && is_image(entry.name())).then(|| entry.name().to_owned())
+            })
+            .collect();
+
+        return Ok(Index::Zip(names, zip));
+    }
+
+    let mut tar = tar::Archive::new(file);
+    let mut entries = Vec::new();
+
+    for entry in tar.entries_with_seek()? {
+        let entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let name = entry.path()?.to_string_lossy().into_owned();
+        if is_image(&name) {
+            entries.push((name, entry.raw_file_position(), entry.size()));
+        }
+    }
+
+    Ok(Index::Tar(entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+
+    /// A PNG of the given size.
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        image::RgbImage::new(width, height)
+            .write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)
+            .unwrap();
+        bytes
+    }
+
+    /// Files of the fixture archives, of which only the PNGs are images.
+    fn fixture() -> [(&'static str, Vec<u8>); 3] {
+        [
+            ("forest.png", png(4, 2)),
+            ("README.txt", b"not an image".to_vec()),
+            ("night/stars.png", png(3, 5)),
+        ]
+    }
+
+    fn assert_lists_and_decodes(archive: &Path) {
+        assert_eq!(list(archive).unwrap(), ["forest.png", "night/stars.png"]);
+
+        let image = image::load_from_memory(&read(archive, "night/stars.png").unwrap()).unwrap();
+        assert_eq!((image.width(), image.height()), (3, 5));
+
+        assert!(read(archive, "missing.png").is_err());
+    }
+
+    #[test]
+    fn lists_and_decodes_zip_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pack.zip");
+
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        for (name, contents) in fixture() {
+            zip.start_file(name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(&contents).unwrap();
+        }
+        zip.finish().unwrap();
+
+        assert_lists_and_decodes(&path);
+    }
+
+    #[test]
+    fn lists_and_decodes_tar_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pack.tar");
+
+        let mut tar = tar::Builder::new(File::create(&path).unwrap());
+        for (name, contents) in fixture() {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            tar.append_data(&mut header, name, contents.as_slice())
+                .unwrap();
+        }
+        tar.finish().unwrap();
+
+        assert_lists_and_decodes(&path);
+    }
+
+    #[test]
+    fn splits_virtual_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("pack.zip");
+        File::create(&archive).unwrap();
+
+        assert_eq!(
+            split(&archive.join("night/stars.png")),
+            Some((archive, String::from("night/stars.png")))
+        );
+        assert_eq!(split(&dir.path().join("forest.png")), None);
+    }
+}
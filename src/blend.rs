@@ -0,0 +1,126 @@
+// SPDX-License-Identifier: MPL-2.0-only
+
+//! Compositing of an overlay image on top of a base image.
+
+use cosmic_bg_config::BlendMode;
+use image::{DynamicImage, Rgb32FImage};
+
+/// Composite `overlay` onto `base` with the given blend mode and overlay opacity.
+///
+/// Both images are expected to already be scaled to the same dimensions.
+pub fn blend(
+    base: &DynamicImage,
+    overlay: &DynamicImage,
+    mode: BlendMode,
+    opacity: f32,
+) -> Rgb32FImage {
+    let opacity = opacity.clamp(0.0, 1.0);
+    let mut base = base.to_rgb32f();
+    let overlay = overlay.to_rgb32f();
+
+    for (dst, src) in base.pixels_mut().zip(overlay.pixels()) {
+        for (a, &b) in dst.0.iter_mut().zip(&src.0) {
+            *a = channel(*a, b, mode, opacity);
+        }
+    }
+
+    base
+}
+
+/// Blend a single channel value of the overlay `b` onto the base `a`.
+#[must_use]
+pub fn channel(a: f32, b: f32, mode: BlendMode, opacity: f32) -> f32 {
+    let blended = match mode {
+        BlendMode::Normal => b,
+        BlendMode::Multiply => a * b,
+        BlendMode::Screen => 1.0 - (1.0 - a) * (1.0 - b),
+        BlendMode::Overlay => {
+            if a < 0.5 {
+                2.0 * a * b
+            } else {
+                1.0 - 2.0 * (1.0 - a) * (1.0 - b)
+            }
+        }
+    };
+
+    a + (blended - a) * opacity
+}
+
+/// Composite an image at `opacity` over a solid linear `color`, or only lower its
+/// opacity if there is no color, leaving it translucent.
+pub fn over_color(image: &DynamicImage, color: Option<[f32; 3]>, opacity: f32) -> DynamicImage {
+    let opacity = opacity.clamp(0.0, 1.0);
+    let background = color.map(crate::colored::srgb);
+    let mut image = image.to_rgba32f();
+
+    for pixel in image.pixels_mut() {
+        let alpha = pixel.0[3] * opacity;
+
+        if let Some(background) = background {
+            for (a, &b) in pixel.0[..3].iter_mut().zip(&background) {
+                *a = channel(b, *a, BlendMode::Normal, alpha);
+            }
+            pixel.0[3] = 1.0;
+        } else {
+            pixel.0[3] = alpha;
+        }
+    }
+
+    DynamicImage::ImageRgba32F(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn pixel(value: u8) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(1, 1, Rgb([value; 3])))
+    }
+
+    fn blended(base: u8, overlay: u8, mode: BlendMode, opacity: f32) -> f32 {
+        blend(&pixel(base), &pixel(overlay), mode, opacity)
+            .get_pixel(0, 0)
+            .0[0]
+    }
+
+    fn assert_near(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-3, "{a} != {b}");
+    }
+
+    #[test]
+    fn modes_on_single_pixels() {
+        assert_near(blended(51, 204, BlendMode::Normal, 1.0), 0.8);
+        assert_near(blended(51, 204, BlendMode::Multiply, 1.0), 0.16);
+        assert_near(blended(51, 204, BlendMode::Screen, 1.0), 0.84);
+        // Dark bases are multiplied and light bases screened.
+        assert_near(blended(51, 204, BlendMode::Overlay, 1.0), 0.32);
+        assert_near(blended(204, 51, BlendMode::Overlay, 1.0), 0.68);
+    }
+
+    #[test]
+    fn opacity_mixes_with_the_base() {
+        assert_near(blended(0, 255, BlendMode::Normal, 0.25), 0.25);
+        assert_near(blended(51, 204, BlendMode::Multiply, 0.0), 0.2);
+        // Opacity out of range is clamped.
+        assert_near(blended(0, 255, BlendMode::Screen, 2.0), 1.0);
+    }
+
+    #[test]
+    fn opacity_over_a_color_is_opaque() {
+        let red = DynamicImage::ImageRgb8(RgbImage::from_pixel(1, 1, Rgb([255, 0, 0])));
+
+        let over_blue = over_color(&red, Some([0.0, 0.0, 1.0]), 0.5).to_rgba32f();
+        for (a, b) in over_blue
+            .get_pixel(0, 0)
+            .0
+            .into_iter()
+            .zip([0.5, 0.0, 0.5, 1.0])
+        {
+            assert_near(a, b);
+        }
+
+        let translucent = over_color(&red, None, 0.5).to_rgba32f();
+        assert_eq!(translucent.get_pixel(0, 0).0, [1.0, 0.0, 0.0, 0.5]);
+    }
+}
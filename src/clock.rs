@@ -0,0 +1,158 @@
+// SPDX-License-Identifier: MPL-2.0-only
+
+//! Wallpapers of the current time, drawn in large text with the font of the
+//! debug overlay and redrawn every minute.
+
+use std::{ffi::CString, time::Duration};
+
+use image::{Rgb, Rgb32FImage};
+
+use crate::overlay::{GLYPH_HEIGHT, GLYPH_WIDTH};
+
+/// Largest fraction of the width or height of the surface filled by the time.
+const FILL: f32 = 0.6;
+
+/// Formats the local time with the conversions of `strftime`, such as `%H:%M`.
+///
+/// Letters are drawn in upper case, and characters missing from the font are
+/// left as spaces.
+#[must_use]
+pub fn format(format: &str) -> String {
+    let (Ok(format), Some(tm)) = (CString::new(format), crate::schedule::local_time()) else {
+        return String::new();
+    };
+
+    let mut buffer = [0u8; 256];
+
+    // SAFETY: The buffer is valid for its length, the format is nul terminated,
+    // and `strftime` writes at most the length given to it.
+    let len = unsafe {
+        libc::strftime(
+            buffer.as_mut_ptr().cast(),
+            buffer.len(),
+            format.as_ptr(),
+            &tm,
+        )
+    };
+
+    String::from_utf8_lossy(&buffer[..len]).into_owned()
+}
+
+/// Time until the start of the next minute, when the time is redrawn.
+#[must_use]
+pub fn until_next_minute() -> Duration {
+    Duration::from_secs(u64::from(60 - crate::schedule::local_second_of_day() % 60))
+}
+
+/// Renders lines of text centered on a background, both of linear colors.
+///
+/// The text is scaled to fill the surface, and scaled down to fit when the
+/// format is long, down to a single pixel per pixel of the font. Text that is
+/// too long even then is clipped at the edges.
+#[must_use]
+pub fn render(
+    text: &str,
+    color: [f32; 3],
+    background: [f32; 3],
+    width: u32,
+    height: u32,
+) -> Rgb32FImage {
+    let mut image = crate::colored::single(background, width, height);
+    let color = Rgb(crate::colored::srgb(color));
+
+    let lines = text
+        .lines()
+        .map(|line| line.chars().count())
+        .collect::<Vec<_>>();
+    let columns = lines.iter().copied().max().unwrap_or(0);
+
+    if columns == 0 {
+        return image;
+    }
+
+    // Glyphs include a column and row of spacing, which the last one doesn't need.
+    let text_width = (columns * GLYPH_WIDTH - 1) as f32;
+    let text_height = (lines.len() * GLYPH_HEIGHT - 1) as f32;
+
+    let pixel =
+        ((width as f32 * FILL / text_width).min(height as f32 * FILL / text_height) as i64).max(1);
+
+    let top = (i64::from(height) - (text_height as i64) * pixel) / 2;
+
+    for (row, line) in text.lines().enumerate() {
+        let Some(line_width) = (lines[row] * GLYPH_WIDTH).checked_sub(1) else {
+            continue;
+        };
+
+        // Lines are centered on their own.
+        let left = (i64::from(width) - line_width as i64 * pixel) / 2;
+        let origin_y = top + (row * GLYPH_HEIGHT) as i64 * pixel;
+
+        for (column, c) in line.chars().enumerate() {
+            let Some(glyph) = crate::overlay::glyph(c) else {
+                continue;
+            };
+
+            let origin_x = left + (column * GLYPH_WIDTH) as i64 * pixel;
+
+            for (y, bits) in glyph.iter().enumerate() {
+                for x in 0..3 {
+                    if bits & (0b100 >> x) == 0 {
+                        continue;
+                    }
+
+                    let x = origin_x + x * pixel;
+                    let y = origin_y + y as i64 * pixel;
+
+                    for py in y.max(0)..(y + pixel).min(i64::from(height)) {
+                        for px in x.max(0)..(x + pixel).min(i64::from(width)) {
+                            image.put_pixel(px as u32, py as u32, color);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Columns and rows of the pixels of an image drawn over black.
+    fn text_pixels(image: &Rgb32FImage) -> Vec<(u32, u32)> {
+        image
+            .enumerate_pixels()
+            .filter(|(.., pixel)| pixel.0 != [0.0; 3])
+            .map(|(x, y, _)| (x, y))
+            .collect()
+    }
+
+    #[test]
+    fn time_is_drawn_centered_within_the_surface() {
+        let image = render("12:34", [1.0; 3], [0.0; 3], 400, 200);
+        let pixels = text_pixels(&image);
+        assert!(!pixels.is_empty());
+
+        let (min_x, max_x) = (
+            pixels.iter().map(|p| p.0).min().unwrap(),
+            pixels.iter().map(|p| p.0).max().unwrap(),
+        );
+        assert!(max_x - min_x < (400.0 * FILL) as u32);
+        assert!(min_x.abs_diff(399 - max_x) <= 1, "{min_x} {max_x}");
+    }
+
+    #[test]
+    fn long_formats_are_scaled_down() {
+        let short = text_pixels(&render("1", [1.0; 3], [0.0; 3], 400, 200)).len();
+        let long = text_pixels(&render(&"1".repeat(40), [1.0; 3], [0.0; 3], 400, 200)).len();
+
+        // The 8 pixels of the glyph are drawn 24 pixels large when alone, fitting
+        // the height, and down to a single pixel to fit many in the width.
+        assert_eq!(short, 8 * 24 * 24);
+        assert_eq!(long, 40 * 8);
+        assert!(text_pixels(&render("", [1.0; 3], [0.0; 3], 400, 200)).is_empty());
+    }
+}
@@ -1,14 +1,17 @@
 // SPDX-License-Identifier: MPL-2.0-only
 
 use colorgrad::Color;
-use cosmic_bg_config::Gradient;
+use cosmic_bg_config::{BlendMode, Gradient, GradientBlendSpace};
 use image::Rgb32FImage;
 
 /// Generate a background image from a color.
+///
+/// The color is linear, and is encoded as sRGB like the pixels of decoded images
+/// and gradients.
 pub fn single(color: [f32; 3], width: u32, height: u32) -> Rgb32FImage {
     let mut imgbuf = Rgb32FImage::new(width, height);
 
-    let pixel = image::Rgb(color);
+    let pixel = image::Rgb(srgb(color));
 
     for x in 0..width {
         for y in 0..height {
@@ -19,9 +22,10 @@ pub fn single(color: [f32; 3], width: u32, height: u32) -> Rgb32FImage {
     imgbuf
 }
 
-/// Generate a background image from a gradient.
+/// Generate a background image from a gradient interpolated in `space`.
 pub fn gradient(
     gradient: &Gradient,
+    space: GradientBlendSpace,
     width: u32,
     height: u32,
 ) -> Result<Rgb32FImage, colorgrad::CustomGradientError> {
@@ -38,7 +42,7 @@ pub fn gradient(
 
     let grad = colorgrad::CustomGradient::new()
         .colors(&colors)
-        .mode(colorgrad::BlendMode::LinearRgb)
+        .mode(space.into())
         .interpolation(colorgrad::Interpolation::Linear)
         .build()?;
 
@@ -85,3 +89,181 @@ pub fn gradient(
 
     Ok(imgbuf)
 }
+
+/// Generate a background image from gradients composited in order onto black.
+pub fn gradients(
+    layers: &[(Gradient, BlendMode, f32)],
+    space: GradientBlendSpace,
+    width: u32,
+    height: u32,
+) -> Result<Rgb32FImage, colorgrad::CustomGradientError> {
+    let mut imgbuf = Rgb32FImage::new(width, height);
+
+    if layers.is_empty() {
+        tracing::warn!("no gradients to composite, falling back to black");
+    }
+
+    for (layer, mode, opacity) in layers {
+        let overlay = gradient(layer, space, width, height)?;
+        let opacity = opacity.clamp(0.0, 1.0);
+
+        for (dst, src) in imgbuf.pixels_mut().zip(overlay.pixels()) {
+            for (a, &b) in dst.0.iter_mut().zip(&src.0) {
+                *a = crate::blend::channel(*a, b, *mode, opacity);
+            }
+        }
+    }
+
+    Ok(imgbuf)
+}
+
+/// Size of the thumbnail whose pixels are averaged by [`average`].
+const AVERAGE_SIZE: u32 = 64;
+
+/// The average color of an image, as a linear color like those of config.
+#[must_use]
+pub fn average(image: &image::DynamicImage) -> [f32; 3] {
+    let thumbnail = image.thumbnail(AVERAGE_SIZE, AVERAGE_SIZE).to_rgb32f();
+    let count = (thumbnail.width() * thumbnail.height()).max(1) as f32;
+
+    let sum = thumbnail.pixels().fold([0.0; 3], |sum, pixel| {
+        [0, 1, 2].map(|c| sum[c] + cosmic_bg_config::srgb_to_linear(pixel.0[c].clamp(0.0, 1.0)))
+    });
+
+    sum.map(|channel| channel / count)
+}
+
+/// Encodes a linear color from config as sRGB.
+#[must_use]
+pub fn srgb(color: [f32; 3]) -> [f32; 3] {
+    color.map(|c| cosmic_bg_config::linear_to_srgb(c.clamp(0.0, 1.0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn solid_colors_are_encoded_like_images() {
+        let Some(cosmic_bg_config::Color::Single(gray)) =
+            cosmic_bg_config::Color::from_hex("808080")
+        else {
+            unreachable!();
+        };
+
+        let image =
+            image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(1, 1, image::Rgb([128; 3])))
+                .to_rgb32f();
+
+        for (solid, pixel) in single(gray, 1, 1)
+            .get_pixel(0, 0)
+            .0
+            .iter()
+            .zip(image.get_pixel(0, 0).0)
+        {
+            assert!((solid - pixel).abs() < 1e-3, "{solid} != {pixel}");
+        }
+    }
+
+    #[test]
+    fn uniform_gradients_match_solid_colors() {
+        let color = [0.2, 0.5, 0.8];
+        let gradient = Gradient {
+            colors: vec![color, color].into(),
+            radius: 180.0,
+        };
+
+        let solid = single(color, 4, 4);
+        for space in [GradientBlendSpace::LinearRgb, GradientBlendSpace::Oklab] {
+            let image = super::gradient(&gradient, space, 4, 4).unwrap();
+
+            for (a, b) in image
+                .pixels()
+                .flat_map(|p| p.0)
+                .zip(solid.pixels().flat_map(|p| p.0))
+            {
+                assert!((a - b).abs() < 1e-3, "{a} != {b} in {space:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn gradients_are_composited_in_order() {
+        let dark = Gradient {
+            colors: vec![[0.0, 0.0, 0.0], [0.2, 0.2, 0.2]].into(),
+            radius: 90.0,
+        };
+        let light = Gradient {
+            colors: vec![[1.0, 1.0, 1.0], [1.0, 1.0, 1.0]].into(),
+            radius: 0.0,
+        };
+        let space = GradientBlendSpace::LinearRgb;
+
+        let base = super::gradient(&dark, space, 8, 2).unwrap();
+        let single_layer = gradients(&[(dark.clone(), BlendMode::Normal, 1.0)], space, 8, 2);
+        assert_eq!(single_layer.unwrap(), base);
+
+        // A white overlay at half opacity lands halfway to white, and screening
+        // white onto anything is white.
+        let washed = gradients(
+            &[
+                (dark.clone(), BlendMode::Normal, 1.0),
+                (light.clone(), BlendMode::Normal, 0.5),
+            ],
+            space,
+            8,
+            2,
+        )
+        .unwrap();
+        for (washed, base) in washed.pixels().zip(base.pixels()) {
+            assert!((washed.0[0] - (base.0[0] + 1.0) / 2.0).abs() < 1e-3);
+        }
+
+        let screened = gradients(
+            &[
+                (dark, BlendMode::Normal, 1.0),
+                (light, BlendMode::Screen, 1.0),
+            ],
+            space,
+            8,
+            2,
+        )
+        .unwrap();
+        assert!(screened
+            .pixels()
+            .all(|p| p.0.iter().all(|&c| (c - 1.0).abs() < 1e-3)));
+
+        let empty = gradients(&[], space, 8, 2).unwrap();
+        assert!(empty.pixels().all(|p| p.0 == [0.0; 3]));
+    }
+
+    #[test]
+    fn blend_space_changes_the_midpoint() {
+        let gradient = Gradient {
+            colors: vec![[1.0, 0.0, 0.0], [0.0, 0.0, 1.0]].into(),
+            radius: 90.0,
+        };
+
+        // The second of two pixels is halfway along a horizontal gradient.
+        let midpoint = |space| {
+            super::gradient(&gradient, space, 2, 1)
+                .unwrap()
+                .get_pixel(1, 0)
+                .0
+        };
+
+        let linear = midpoint(GradientBlendSpace::LinearRgb);
+        let expected = cosmic_bg_config::linear_to_srgb(0.5);
+        assert!((linear[0] - expected).abs() < 1e-3 && (linear[2] - expected).abs() < 1e-3);
+        assert!(linear[1].abs() < 1e-3);
+
+        let oklab = midpoint(GradientBlendSpace::Oklab);
+        let hsv = midpoint(GradientBlendSpace::Hsv);
+        for other in [oklab, hsv] {
+            assert!(other.iter().zip(linear).any(|(a, b)| (a - b).abs() > 0.05));
+        }
+
+        // Hsl isn't supported by colorgrad, and falls back to Hsv.
+        assert_eq!(midpoint(GradientBlendSpace::Hsl), hsv);
+    }
+}
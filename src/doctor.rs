@@ -0,0 +1,234 @@
+// SPDX-License-Identifier: MPL-2.0-only
+
+//! The `cosmic-bg doctor` subcommand, which reports the environment for bug reports.
+
+use std::fmt::Write;
+
+use cosmic_bg_config::{Config, Source};
+use sctk::reexports::client::{globals::registry_queue_init, Connection};
+use serde::{Deserialize, Serialize};
+
+/// Wayland globals used by cosmic-bg, and whether each is required.
+const GLOBALS: &[(&str, bool)] = &[
+    ("wl_compositor", true),
+    ("wl_shm", true),
+    ("zwlr_layer_shell_v1", true),
+    ("wp_viewporter", true),
+    ("wp_fractional_scale_manager_v1", true),
+    ("zwp_linux_dmabuf_v1", false),
+];
+
+/// Environment details gathered by the doctor subcommand.
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq)]
+struct Report {
+    version: String,
+    wayland_error: Option<String>,
+    /// The globals in [`GLOBALS`], in the same order.
+    globals: Vec<Global>,
+    config_error: Option<String>,
+    same_on_all: bool,
+    backgrounds: Vec<Background>,
+    default_background: String,
+    lockscreen_cache_bytes: u64,
+}
+
+/// A Wayland global, and its version if the compositor advertises it.
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+struct Global {
+    interface: String,
+    version: Option<u32>,
+}
+
+/// The source of the background of an output.
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+struct Background {
+    output: String,
+    source: String,
+}
+
+/// Prints the diagnostics report, as JSON if `json` is set.
+pub fn run(json: bool) -> eyre::Result<()> {
+    let report = report();
+
+    let output = if json {
+        serde_json::to_string(&report)?
+    } else {
+        to_text(&report)
+    };
+
+    println!("{output}");
+    Ok(())
+}
+
+fn report() -> Report {
+    let mut report = Report {
+        version: String::from(env!("CARGO_PKG_VERSION")),
+        ..Default::default()
+    };
+
+    match wayland_globals() {
+        Ok(found) => {
+            report.globals = GLOBALS
+                .iter()
+                .map(|&(interface, _)| Global {
+                    interface: String::from(interface),
+                    version: found
+                        .iter()
+                        .find(|(name, _)| name == interface)
+                        .map(|&(_, version)| version),
+                })
+                .collect();
+        }
+        Err(why) => report.wayland_error = Some(why.to_string()),
+    }
+
+    match cosmic_bg_config::context().and_then(|context| Config::load(&context)) {
+        Ok(config) => {
+            report.same_on_all = config.same_on_all;
+            report.default_background = source_summary(&config.default_background.source);
+            report.backgrounds = config
+                .backgrounds
+                .iter()
+                .map(|entry| Background {
+                    output: entry.output.clone(),
+                    source: source_summary(&entry.source),
+                })
+                .collect();
+        }
+        Err(why) => report.config_error = Some(why.to_string()),
+    }
+
+    report.lockscreen_cache_bytes = crate::lockscreen::dir()
+        .and_then(|dir| dir.read_dir().ok())
+        .map_or(0, |dir| {
+            dir.filter_map(Result::ok)
+                .filter_map(|entry| entry.metadata().ok())
+                .map(|metadata| metadata.len())
+                .sum()
+        });
+
+    report
+}
+
+/// Names and versions of the globals advertised by the compositor.
+fn wayland_globals() -> eyre::Result<Vec<(String, u32)>> {
+    let conn = Connection::connect_to_env()?;
+    let (globals, _event_queue) = registry_queue_init::<crate::CosmicBg>(&conn)?;
+
+    Ok(globals
+        .contents()
+        .clone_list()
+        .into_iter()
+        .map(|global| (global.interface, global.version))
+        .collect())
+}
+
+pub fn source_summary(source: &Source) -> String {
+    match source {
+        Source::Path(path) => path.display().to_string(),
+        Source::Color(_) => String::from("color"),
+        Source::Archive(path) => format!("archive {}", path.display()),
+        Source::Hashed { sha256, .. } => format!("image with sha256 {sha256}"),
+        Source::None => String::from("none"),
+        Source::AutoMatchPrimary => String::from("color of the primary output"),
+        Source::Clock { format, .. } => format!("clock of {format:?}"),
+        Source::Split { left, right, .. } => {
+            format!(
+                "split of {} and {}",
+                source_summary(left),
+                source_summary(right)
+            )
+        }
+        Source::Fallback(sources) => {
+            let sources = sources.iter().map(source_summary).collect::<Vec<_>>();
+            format!("first available of {}", sources.join(", "))
+        }
+        Source::Blend { base, overlay, .. } => {
+            format!("blend of {} and {}", base.display(), overlay.display())
+        }
+    }
+}
+
+fn to_text(report: &Report) -> String {
+    let mut out = String::new();
+
+    let _ = writeln!(out, "cosmic-bg {}", report.version);
+
+    let _ = writeln!(out, "\nWayland globals:");
+    if let Some(why) = &report.wayland_error {
+        let _ = writeln!(out, "  connection failed: {why}");
+    }
+    for (global, &(_, required)) in report.globals.iter().zip(GLOBALS) {
+        let status = match global.version {
+            Some(version) => format!("v{version}"),
+            None if required => String::from("MISSING (required)"),
+            None => String::from("missing"),
+        };
+        let _ = writeln!(out, "  {}: {status}", global.interface);
+    }
+
+    let _ = writeln!(out, "\nConfig:");
+    if let Some(why) = &report.config_error {
+        let _ = writeln!(out, "  failed to load: {why}");
+    } else {
+        let _ = writeln!(out, "  same-on-all: {}", report.same_on_all);
+        let _ = writeln!(out, "  default: {}", report.default_background);
+        for background in &report.backgrounds {
+            let _ = writeln!(out, "  {}: {}", background.output, background.source);
+        }
+    }
+
+    let _ = write!(
+        out,
+        "\nLock screen cache: {} bytes",
+        report.lockscreen_cache_bytes
+    );
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Report {
+        Report {
+            version: String::from("0.1.0"),
+            wayland_error: None,
+            globals: vec![
+                Global {
+                    interface: String::from("wl_compositor"),
+                    version: Some(6),
+                },
+                Global {
+                    interface: String::from("wl_shm"),
+                    version: None,
+                },
+            ],
+            config_error: Some(String::from("missing \"key\"\n")),
+            same_on_all: true,
+            backgrounds: vec![Background {
+                output: String::from("DP-1"),
+                source: String::from("/usr/share/backgrounds"),
+            }],
+            default_background: String::from("color"),
+            lockscreen_cache_bytes: 4096,
+        }
+    }
+
+    #[test]
+    fn json_deserializes_to_the_report() {
+        let report = sample();
+        let json = serde_json::to_string(&report).unwrap();
+
+        assert_eq!(serde_json::from_str::<Report>(&json).unwrap(), report);
+    }
+
+    #[test]
+    fn text_marks_missing_required_globals() {
+        let text = to_text(&sample());
+
+        assert!(text.contains("  wl_compositor: v6\n"));
+        assert!(text.contains("  wl_shm: MISSING (required)\n"));
+    }
+}
@@ -3,7 +3,10 @@
 use crate::{CosmicBg, CosmicBgLayer};
 use image::{DynamicImage, GenericImageView};
 use sctk::{
-    reexports::client::{protocol::wl_shm, QueueHandle},
+    reexports::client::{
+        protocol::{wl_output::Transform, wl_shm},
+        QueueHandle,
+    },
     shell::WaylandSurface,
     shm::slot::{Buffer, CreateBufferError, SlotPool},
 };
@@ -14,6 +17,8 @@ pub fn canvas(
     width: i32,
     height: i32,
     stride: i32,
+    alpha: bool,
+    overlay: Option<&str>,
 ) -> Result<Buffer, CreateBufferError> {
     // TODO: Check if we need 8-bit or 10-bit
     let hdr_layer = false;
@@ -22,7 +27,9 @@ pub fn canvas(
         width,
         height,
         stride,
-        if hdr_layer {
+        if alpha {
+            wl_shm::Format::Argb8888
+        } else if hdr_layer {
             wl_shm::Format::Xrgb2101010
         } else {
             wl_shm::Format::Xrgb8888
@@ -31,11 +38,17 @@ pub fn canvas(
 
     // Draw to the window:
     {
-        if hdr_layer {
+        if alpha {
+            argb8888_canvas(canvas, image);
+        } else if hdr_layer {
             xrgb21010_canvas(canvas, image);
         } else {
             xrgb888_canvas(canvas, image);
         }
+
+        if let Some(text) = overlay {
+            crate::overlay::draw(canvas, width as usize, height as usize, text);
+        }
     }
 
     Ok(buffer)
@@ -60,6 +73,8 @@ pub fn layer_surface(
         .wl_surface()
         .frame(queue_handle, wl_surface.clone());
 
+    wl_surface.set_buffer_transform(layer.transform);
+
     // Attach and commit to present.
     if let Err(why) = buffer.attach_to(wl_surface) {
         tracing::error!(?why, "buffer attachment failed");
@@ -70,6 +85,54 @@ pub fn layer_surface(
     wl_surface.commit();
 }
 
+/// Shows the part of an enlarged zoom at the parallax `offset`, or the whole
+/// buffer of other wallpapers.
+pub fn crop(layer: &CosmicBgLayer, offset: (f32, f32)) {
+    match layer.zoom_crop {
+        Some((enlarged, (width, height))) => {
+            let (x, y) = crate::scaler::zoom_crop(enlarged, (width, height), offset);
+            layer.viewport.set_source(
+                f64::from(x),
+                f64::from(y),
+                f64::from(width),
+                f64::from(height),
+            );
+        }
+
+        // The source is unset by a rectangle of -1.
+        None => layer.viewport.set_source(-1.0, -1.0, -1.0, -1.0),
+    }
+}
+
+/// Width and height of a buffer in the orientation of an output transform.
+#[must_use]
+pub fn buffer_size(width: u32, height: u32, transform: Transform) -> (u32, u32) {
+    match transform {
+        Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270 => {
+            (height, width)
+        }
+        _ => (width, height),
+    }
+}
+
+/// Transforms an image into the orientation of an output's buffers, where a
+/// transform rotates counter-clockwise after flipping around the vertical axis.
+///
+/// Returns `None` if the image needs no transform.
+#[must_use]
+pub fn transform(image: &DynamicImage, transform: Transform) -> Option<DynamicImage> {
+    match transform {
+        Transform::_90 => Some(image.rotate270()),
+        Transform::_180 => Some(image.rotate180()),
+        Transform::_270 => Some(image.rotate90()),
+        Transform::Flipped => Some(image.fliph()),
+        Transform::Flipped90 => Some(image.fliph().rotate270()),
+        Transform::Flipped180 => Some(image.flipv()),
+        Transform::Flipped270 => Some(image.fliph().rotate90()),
+        _ => None,
+    }
+}
+
 /// Draws the image on a 10-bit canvas.
 pub fn xrgb21010_canvas(canvas: &mut [u8], image: &DynamicImage) {
     const BIT_MASK: u32 = (1 << 10) - 1;
@@ -101,3 +164,101 @@ pub fn xrgb888_canvas(canvas: &mut [u8], image: &DynamicImage) {
         canvas[indice..indice + 4].copy_from_slice(&(r | g | b).to_le_bytes());
     }
 }
+
+/// Draws the image on an 8-bit canvas with alpha.
+///
+/// Wayland expects the color channels of ARGB buffers to be premultiplied by alpha.
+pub fn argb8888_canvas(canvas: &mut [u8], image: &DynamicImage) {
+    for (pos, (_, _, pixel)) in image.pixels().enumerate() {
+        let indice = pos * 4;
+
+        canvas[indice..indice + 4].copy_from_slice(&argb8888(pixel.0).to_le_bytes());
+    }
+}
+
+/// Packs a straight alpha RGBA pixel into a premultiplied ARGB value.
+#[must_use]
+pub fn argb8888([r, g, b, a]: [u8; 4]) -> u32 {
+    let premultiply = |c: u8| (u32::from(c) * u32::from(a) + 127) / 255;
+
+    let a = u32::from(a) << 24;
+    let r = premultiply(r) << 16;
+    let g = premultiply(g) << 8;
+    let b = premultiply(b);
+
+    a | r | g | b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn argb_is_premultiplied() {
+        assert_eq!(argb8888([255, 128, 0, 255]), 0xFFFF_8000);
+        assert_eq!(argb8888([255, 128, 0, 128]), 0x8080_4000);
+        assert_eq!(argb8888([255, 255, 255, 0]), 0);
+    }
+
+    #[test]
+    fn argb_canvas_is_little_endian() {
+        let image = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+            1,
+            1,
+            image::Rgba([255, 128, 0, 255]),
+        ));
+        let mut canvas = [0; 4];
+
+        argb8888_canvas(&mut canvas, &image);
+        assert_eq!(canvas, [0x00, 0x80, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn rotated_transforms_swap_dimensions() {
+        for transform in [
+            Transform::Normal,
+            Transform::_180,
+            Transform::Flipped,
+            Transform::Flipped180,
+        ] {
+            assert_eq!(buffer_size(1920, 1080, transform), (1920, 1080));
+        }
+
+        for transform in [
+            Transform::_90,
+            Transform::_270,
+            Transform::Flipped90,
+            Transform::Flipped270,
+        ] {
+            assert_eq!(buffer_size(1920, 1080, transform), (1080, 1920));
+        }
+    }
+
+    #[test]
+    fn transforms_orient_the_image() {
+        // A pixel in the top left corner of a 2x1 image.
+        let mut image = image::RgbImage::new(2, 1);
+        image.put_pixel(0, 0, image::Rgb([255, 255, 255]));
+        let image = DynamicImage::ImageRgb8(image);
+
+        assert!(transform(&image, Transform::Normal).is_none());
+
+        let white = |transform| {
+            let image = super::transform(&image, transform).unwrap().to_rgb8();
+            let (x, y, _) = image
+                .enumerate_pixels()
+                .find(|(_, _, p)| p.0[0] == 255)
+                .unwrap();
+            (image.dimensions(), (x, y))
+        };
+
+        // Buffers are rotated counter-clockwise to be rotated back clockwise.
+        assert_eq!(white(Transform::_90), ((1, 2), (0, 1)));
+        assert_eq!(white(Transform::_180), ((2, 1), (1, 0)));
+        assert_eq!(white(Transform::_270), ((1, 2), (0, 0)));
+        assert_eq!(white(Transform::Flipped), ((2, 1), (1, 0)));
+        assert_eq!(white(Transform::Flipped90), ((1, 2), (0, 0)));
+        assert_eq!(white(Transform::Flipped180), ((2, 1), (0, 0)));
+        assert_eq!(white(Transform::Flipped270), ((1, 2), (0, 1)));
+    }
+}
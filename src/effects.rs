@@ -0,0 +1,269 @@
+// SPDX-License-Identifier: MPL-2.0-only
+
+//! Post-processing effects applied to scaled wallpapers.
+
+use cosmic_bg_config::linear_to_srgb;
+use image::DynamicImage;
+
+/// Darkens an image towards black by `factor`, as if composited over black
+/// at an opacity of `factor`.
+pub fn darken(image: &DynamicImage, factor: f32) -> DynamicImage {
+    let factor = factor.clamp(0.0, 1.0);
+    let mut image = image.to_rgba8();
+
+    for pixel in image.pixels_mut() {
+        for channel in &mut pixel.0[..3] {
+            *channel = (f32::from(*channel) * factor).round() as u8;
+        }
+    }
+
+    DynamicImage::ImageRgba8(image)
+}
+
+/// Tints an image to the white point of a color temperature in kelvin, like the
+/// gamma ramps of a night light, where 6500 K leaves it unchanged.
+pub fn color_temperature(image: &DynamicImage, kelvin: u32) -> DynamicImage {
+    let white = whitepoint(6500);
+    let tint = whitepoint(kelvin);
+    let factors = [0, 1, 2].map(|channel| (tint[channel] / white[channel]).min(1.0));
+    let mut image = image.to_rgba8();
+
+    for pixel in image.pixels_mut() {
+        for (channel, factor) in pixel.0[..3].iter_mut().zip(factors) {
+            *channel = (f32::from(*channel) * factor).round() as u8;
+        }
+    }
+
+    DynamicImage::ImageRgba8(image)
+}
+
+/// Color of a black body at a temperature in kelvin from 1000 to 40000, from
+/// Tanner Helland's fit of its red, green and blue channels.
+fn whitepoint(kelvin: u32) -> [f32; 3] {
+    let t = kelvin.clamp(1000, 40000) as f32 / 100.0;
+
+    let red = if t <= 66.0 {
+        255.0
+    } else {
+        329.698_73 * (t - 60.0).powf(-0.133_204_76)
+    };
+
+    let green = if t <= 66.0 {
+        99.470_8 * t.ln() - 161.119_57
+    } else {
+        288.122_17 * (t - 60.0).powf(-0.075_514_85)
+    };
+
+    let blue = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.517_73 * (t - 10.0).ln() - 305.044_8
+    };
+
+    [red, green, blue].map(|channel| channel.clamp(0.0, 255.0) / 255.0)
+}
+
+/// Darkens the edges of an image with a radial falloff from its center, where
+/// the corners are darkened by `strength` from 0.0 to 1.0.
+pub fn vignette(image: &DynamicImage, strength: f32) -> DynamicImage {
+    let strength = strength.clamp(0.0, 1.0);
+    let mut image = image.to_rgba8();
+
+    let (cx, cy) = (image.width() as f32 / 2.0, image.height() as f32 / 2.0);
+    let max_distance = cx * cx + cy * cy;
+
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+        let factor = 1.0 - strength * ((dx * dx + dy * dy) / max_distance).min(1.0);
+
+        for channel in &mut pixel.0[..3] {
+            *channel = (f32::from(*channel) * factor).round() as u8;
+        }
+    }
+
+    DynamicImage::ImageRgba8(image)
+}
+
+/// Masks an image to a rectangle `inset` pixels within its edges with corners
+/// rounded by `radius`, leaving the rest transparent. Both are clamped to fit
+/// within the image.
+pub fn frame(image: &DynamicImage, inset: u32, radius: u32) -> DynamicImage {
+    let mut image = image.to_rgba8();
+    let (width, height) = image.dimensions();
+
+    let inset = inset.min(width.min(height).saturating_sub(1) / 2);
+    let (inner_width, inner_height) = (width - 2 * inset, height - 2 * inset);
+    let radius = radius.min(inner_width.min(inner_height) / 2) as f32;
+
+    let (left, top) = (inset as f32, inset as f32);
+    let (right, bottom) = (left + inner_width as f32, top + inner_height as f32);
+
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+
+        let coverage = if px < left || px > right || py < top || py > bottom {
+            0.0
+        } else {
+            // Distance past the center of the nearest corner's arc, for pixels
+            // within a corner.
+            let dx = (left + radius - px).max(px - (right - radius)).max(0.0);
+            let dy = (top + radius - py).max(py - (bottom - radius)).max(0.0);
+            let distance = (dx * dx + dy * dy).sqrt();
+
+            if distance == 0.0 {
+                1.0
+            } else {
+                (radius - distance + 0.5).clamp(0.0, 1.0)
+            }
+        };
+
+        if coverage < 1.0 {
+            pixel.0[3] = (f32::from(pixel.0[3]) * coverage).round() as u8;
+        }
+    }
+
+    DynamicImage::ImageRgba8(image)
+}
+
+/// Maps the linear values of a high dynamic range image, such as a Radiance HDR,
+/// into the displayable range with the ACES filmic curve, and encodes them as
+/// sRGB. Images with 8 or 16-bit channels are returned unchanged.
+pub fn tone_map(image: DynamicImage) -> DynamicImage {
+    let mut image = match image {
+        DynamicImage::ImageRgb32F(image) => image,
+        DynamicImage::ImageRgba32F(_) => image.to_rgb32f(),
+        image => return image,
+    };
+
+    for pixel in image.pixels_mut() {
+        for channel in &mut pixel.0 {
+            *channel = linear_to_srgb(aces(*channel));
+        }
+    }
+
+    DynamicImage::ImageRgb32F(image)
+}
+
+/// Narkowicz's fit of the ACES filmic tone curve, mapping `[0, inf)` to `[0, 1]`.
+/// Negative and NaN values from malformed images are treated as black.
+#[must_use]
+pub fn aces(value: f32) -> f32 {
+    let x = if value.is_nan() { 0.0 } else { value.max(0.0) };
+
+    if x.is_infinite() {
+        return 1.0;
+    }
+
+    ((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)).clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn darken_scales_color_and_keeps_alpha() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([200, 100, 0, 128])));
+
+        let darkened = darken(&image, 0.5).to_rgba8();
+        assert_eq!(darkened.get_pixel(0, 0).0, [100, 50, 0, 128]);
+    }
+
+    #[test]
+    fn vignette_darkens_corners_by_strength() {
+        let white = DynamicImage::ImageRgba8(RgbaImage::from_pixel(100, 100, Rgba([255; 4])));
+
+        for strength in [0.0, 0.5, 1.0] {
+            let image = vignette(&white, strength).to_rgba8();
+
+            assert_eq!(image.get_pixel(50, 50).0[0], 255);
+
+            let expected = 255.0 * (1.0 - strength * 0.98);
+            for (x, y) in [(0, 0), (99, 0), (0, 99), (99, 99)] {
+                let corner = f32::from(image.get_pixel(x, y).0[0]);
+                assert!((corner - expected).abs() <= 1.0, "{corner} != {expected}");
+            }
+        }
+    }
+
+    #[test]
+    fn aces_maps_hdr_values_into_range() {
+        assert_eq!(aces(0.0), 0.0);
+        assert!((aces(1.0) - 0.803_7).abs() < 1e-3);
+        assert!(aces(10.0) > 0.99 && aces(10.0) <= 1.0);
+        assert_eq!(aces(f32::INFINITY), 1.0);
+
+        // Malformed values are treated as black.
+        assert_eq!(aces(-4.0), 0.0);
+        assert_eq!(aces(f32::NAN), 0.0);
+    }
+
+    #[test]
+    fn tone_map_only_changes_float_images() {
+        let hdr = DynamicImage::ImageRgb32F(image::Rgb32FImage::from_pixel(
+            1,
+            1,
+            image::Rgb([0.0, 1.0, 100.0]),
+        ));
+        let mapped = tone_map(hdr).to_rgb32f();
+        let [black, mid, bright] = mapped.get_pixel(0, 0).0;
+
+        assert_eq!(black, 0.0);
+        assert!((mid - linear_to_srgb(aces(1.0))).abs() < 1e-6);
+        assert!(bright > 0.99 && bright <= 1.0);
+
+        let sdr = DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([10, 20, 30, 40])));
+        assert_eq!(tone_map(sdr.clone()), sdr);
+    }
+
+    #[test]
+    fn frame_masks_the_inset_and_rounded_corners() {
+        let white = DynamicImage::ImageRgba8(RgbaImage::from_pixel(40, 30, Rgba([255; 4])));
+        let framed = frame(&white, 5, 8).to_rgba8();
+        let alpha = |x, y| framed.get_pixel(x, y).0[3];
+
+        // The inset is transparent, and the center and straight edges opaque.
+        assert_eq!(alpha(2, 15), 0);
+        assert_eq!(alpha(37, 15), 0);
+        assert_eq!(alpha(20, 15), 255);
+        assert_eq!(alpha(20, 5), 255);
+        assert_eq!(alpha(5, 15), 255);
+
+        // Within the rounded corners, the very corner is cut off.
+        assert_eq!(alpha(5, 5), 0);
+        assert_eq!(alpha(34, 24), 0);
+        assert_eq!(alpha(10, 10), 255);
+
+        // Color is kept where the image is visible.
+        assert_eq!(framed.get_pixel(20, 15).0, [255; 4]);
+
+        // Without rounded corners, only the inset is masked.
+        assert_eq!(frame(&white, 0, 0).to_rgba8(), white.to_rgba8());
+        let inset = frame(&white, 5, 0).to_rgba8();
+        assert_eq!(inset.get_pixel(5, 5).0[3], 255);
+        assert_eq!(inset.get_pixel(4, 5).0[3], 0);
+    }
+
+    #[test]
+    fn lower_color_temperatures_are_warmer() {
+        let image =
+            DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([255, 255, 255, 128])));
+
+        assert_eq!(
+            color_temperature(&image, 6500).to_rgba8().get_pixel(0, 0).0,
+            [255, 255, 255, 128]
+        );
+
+        let [red, green, blue, alpha] =
+            color_temperature(&image, 3400).to_rgba8().get_pixel(0, 0).0;
+        assert_eq!((red, alpha), (255, 128));
+        assert!(blue < green && green < 255, "{green} {blue}");
+
+        let [_, warmer_green, warmer_blue, _] =
+            color_temperature(&image, 2000).to_rgba8().get_pixel(0, 0).0;
+        assert!(warmer_green < green && warmer_blue < blue);
+    }
+}
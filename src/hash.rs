@@ -0,0 +1,147 @@
+// SPDX-License-Identifier: MPL-2.0-only
+
+//! Locating images by the SHA-256 hash of their contents, so that a config can
+//! refer to a wallpaper that is stored at different paths on each machine.
+//!
+//! Hashing the search paths reads every image within them, so it is done off the
+//! event loop with [`resolve`], while [`cached`] only looks up images that were
+//! already located. The hash of each file is kept until it is modified, so that
+//! searching again only reads the images that were added or changed since.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex, PoisonError},
+    time::SystemTime,
+};
+
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+
+/// Paths of images whose hashes have already been resolved.
+static RESOLVED: LazyLock<Mutex<HashMap<String, PathBuf>>> = LazyLock::new(Mutex::default);
+
+/// Hashes of the files that were read, and when they were last modified.
+static HASHES: LazyLock<Mutex<HashMap<PathBuf, (SystemTime, String)>>> =
+    LazyLock::new(Mutex::default);
+
+/// The image previously located for a hash, if it still has that hash.
+#[must_use]
+pub fn cached(sha256: &str) -> Option<PathBuf> {
+    let sha256 = sha256.to_ascii_lowercase();
+    let path = RESOLVED
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(&sha256)
+        .cloned()?;
+
+    // Files without modification times can't be checked for changes.
+    match std::fs::metadata(&path).ok()?.modified() {
+        Ok(modified) => (known_hash(&path, modified)? == sha256).then_some(path),
+        Err(_) => Some(path),
+    }
+}
+
+/// Finds the image in the search paths whose contents have the given SHA-256
+/// hash, in hexadecimal.
+///
+/// This reads every image in the search paths that wasn't hashed before, so it
+/// should not be called on the event loop.
+pub fn resolve(sha256: &str, search_paths: &[PathBuf]) -> Option<PathBuf> {
+    if let Some(path) = cached(sha256) {
+        return Some(path);
+    }
+
+    let sha256 = sha256.to_ascii_lowercase();
+
+    let path = search_paths
+        .iter()
+        .flat_map(|dir| WalkDir::new(dir).follow_links(true))
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            entry.file_type().is_file() && cosmic_bg_config::is_supported_image(entry.path())
+        })
+        .find(|entry| match file_sha256(entry.path()) {
+            Ok(hash) => hash == sha256,
+            Err(why) => {
+                tracing::warn!(?why, "could not hash {}", entry.path().display());
+                false
+            }
+        })?
+        .into_path();
+
+    RESOLVED
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(sha256, path.clone());
+
+    Some(path)
+}
+
+/// The SHA-256 hash of the contents of a file, in hexadecimal.
+fn file_sha256(path: &Path) -> io::Result<String> {
+    let modified = std::fs::metadata(path)?.modified().ok();
+
+    if let Some(hash) = modified.and_then(|modified| known_hash(path, modified)) {
+        return Ok(hash);
+    }
+
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    let hash = hex(&hasher.finalize());
+
+    if let Some(modified) = modified {
+        HASHES
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(path.to_path_buf(), (modified, hash.clone()));
+    }
+
+    Ok(hash)
+}
+
+/// The hash of a file that was read before, unless it was modified since.
+fn known_hash(path: &Path, modified: SystemTime) -> Option<String> {
+    HASHES
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(path)
+        .filter(|(hashed, _)| *hashed == modified)
+        .map(|(_, hash)| hash.clone())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SHA-256 of `abc`, from the examples of FIPS 180-2.
+    const ABC: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    #[test]
+    fn known_file_is_resolved_by_its_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("photos");
+        std::fs::create_dir(&nested).unwrap();
+
+        std::fs::write(dir.path().join("other.png"), b"abd").unwrap();
+        std::fs::write(nested.join("notes.txt"), b"abc").unwrap();
+        let image = nested.join("wallpaper.png");
+        std::fs::write(&image, b"abc").unwrap();
+
+        let search_paths = [dir.path().to_path_buf()];
+        assert_eq!(cached(ABC), None);
+        assert_eq!(
+            resolve(&ABC.to_ascii_uppercase(), &search_paths),
+            Some(image.clone())
+        );
+        assert_eq!(cached(ABC), Some(image));
+
+        assert_eq!(resolve(&"0".repeat(64), &search_paths), None);
+    }
+}
@@ -0,0 +1,380 @@
+// SPDX-License-Identifier: MPL-2.0-only
+
+//! Conversion of images with embedded ICC profiles, such as Display P3 and
+//! Adobe RGB photos, to the sRGB that outputs are assumed to display.
+//!
+//! Only RGB matrix/TRC profiles are supported, which are what cameras and image
+//! editors embed. Profiles built from lookup tables are left unconverted.
+
+use image::DynamicImage;
+use rayon::prelude::*;
+
+/// Colorants of sRGB in the D50 profile connection space, as columns.
+const SRGB_TO_XYZ: [[f32; 3]; 3] = [
+    [0.436_074_7, 0.385_064_9, 0.143_080_4],
+    [0.222_504_5, 0.716_878_6, 0.060_616_9],
+    [0.013_932_2, 0.097_104_5, 0.714_173_3],
+];
+
+/// Inverse of [`SRGB_TO_XYZ`].
+const XYZ_TO_SRGB: [[f32; 3]; 3] = [
+    [3.133_856_1, -1.616_866_7, -0.490_614_6],
+    [-0.978_768_4, 1.916_141_5, 0.033_454_0],
+    [0.071_945_3, -0.228_991_4, 1.405_242_7],
+];
+
+/// Largest difference to sRGB within which a profile is treated as sRGB.
+const SRGB_TOLERANCE: f32 = 0.002;
+
+/// Why an embedded profile is not applied.
+#[derive(Clone, Copy, Debug)]
+pub enum Error {
+    /// The profile is truncated or its tags are invalid.
+    Malformed(&'static str),
+    /// The profile is valid, but not an RGB matrix/TRC profile.
+    Unsupported(&'static str),
+}
+
+/// Tone response curve of a channel, mapping its encoded values to linear.
+#[derive(Debug)]
+enum Curve {
+    Gamma(f32),
+    /// Linear values at evenly spaced encoded values.
+    Table(Vec<f32>),
+    /// Parameters `g, a, b, c, d, e, f` of the ICC's parametric curve function 4,
+    /// to which the other parametric functions are normalized.
+    Parametric([f32; 7]),
+}
+
+impl Curve {
+    fn linearize(&self, x: f32) -> f32 {
+        match self {
+            Curve::Gamma(gamma) => x.powf(*gamma),
+
+            Curve::Table(table) => {
+                let position = x.clamp(0.0, 1.0) * (table.len() - 1) as f32;
+                let index = (position as usize).min(table.len() - 2);
+                let t = position - index as f32;
+                table[index] + (table[index + 1] - table[index]) * t
+            }
+
+            Curve::Parametric([g, a, b, c, d, e, f]) => {
+                if x >= *d {
+                    (a * x + b).max(0.0).powf(*g) + e
+                } else {
+                    c * x + f
+                }
+            }
+        }
+    }
+}
+
+/// An RGB matrix/TRC profile.
+#[derive(Debug)]
+pub struct Profile {
+    /// Colorants of the profile in the D50 profile connection space, as columns.
+    to_xyz: [[f32; 3]; 3],
+    curves: [Curve; 3],
+}
+
+impl Profile {
+    /// Parses an ICC profile embedded in an image.
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < 132 {
+            return Err(Error::Malformed("truncated header"));
+        }
+
+        if &bytes[16..20] != b"RGB " {
+            return Err(Error::Unsupported("not an RGB profile"));
+        }
+
+        let count = u32_at(bytes, 128).ok_or(Error::Malformed("truncated tag table"))? as usize;
+
+        let tag = |signature: &[u8; 4]| -> Result<&[u8], Error> {
+            for index in 0..count {
+                let entry = 132 + 12 * index;
+                let (Some(sig), Some(offset), Some(size)) = (
+                    bytes.get(entry..entry + 4),
+                    u32_at(bytes, entry + 4),
+                    u32_at(bytes, entry + 8),
+                ) else {
+                    return Err(Error::Malformed("truncated tag table"));
+                };
+
+                if sig == signature {
+                    let (offset, size) = (offset as usize, size as usize);
+                    return bytes
+                        .get(offset..offset.saturating_add(size))
+                        .ok_or(Error::Malformed("tag outside of the profile"));
+                }
+            }
+
+            Err(Error::Unsupported("no colorant and tone curve tags"))
+        };
+
+        let [red, green, blue] = [b"rXYZ", b"gXYZ", b"bXYZ"].map(|sig| tag(sig).and_then(xyz));
+        let (red, green, blue) = (red?, green?, blue?);
+
+        let [red_curve, green_curve, blue_curve] =
+            [b"rTRC", b"gTRC", b"bTRC"].map(|sig| tag(sig).and_then(curve));
+
+        Ok(Self {
+            to_xyz: [0, 1, 2].map(|row| [red[row], green[row], blue[row]]),
+            curves: [red_curve?, green_curve?, blue_curve?],
+        })
+    }
+
+    /// Whether the profile is sRGB, or close enough that converting to sRGB
+    /// would not change the image.
+    #[must_use]
+    pub fn is_srgb(&self) -> bool {
+        let colorants_match = self
+            .to_xyz
+            .iter()
+            .flatten()
+            .zip(SRGB_TO_XYZ.iter().flatten())
+            .all(|(a, b)| (a - b).abs() <= SRGB_TOLERANCE);
+
+        colorants_match
+            && self.curves.iter().all(|curve| {
+                (1..10).all(|step| {
+                    let x = step as f32 / 10.0;
+                    (curve.linearize(x) - cosmic_bg_config::srgb_to_linear(x)).abs()
+                        <= SRGB_TOLERANCE
+                })
+            })
+    }
+}
+
+/// Converts an image from the color space of its profile to sRGB. Colors outside
+/// of the sRGB gamut are clipped.
+///
+/// Images with floating point channels are linear, and returned unchanged.
+#[must_use]
+pub fn to_srgb(mut image: DynamicImage, profile: &Profile) -> DynamicImage {
+    let matrix = multiply(&XYZ_TO_SRGB, &profile.to_xyz);
+
+    match &mut image {
+        DynamicImage::ImageRgb8(buffer) => {
+            transform(buffer, 3, profile, &matrix, |v| (v * 255.0).round() as u8);
+        }
+        DynamicImage::ImageRgba8(buffer) => {
+            transform(buffer, 4, profile, &matrix, |v| (v * 255.0).round() as u8);
+        }
+        DynamicImage::ImageRgb16(buffer) => {
+            transform(buffer, 3, profile, &matrix, |v| {
+                (v * 65535.0).round() as u16
+            });
+        }
+        DynamicImage::ImageRgba16(buffer) => {
+            transform(buffer, 4, profile, &matrix, |v| {
+                (v * 65535.0).round() as u16
+            });
+        }
+        _ => (),
+    }
+
+    image
+}
+
+/// Converts the samples of an image with `channels` per pixel, of which the
+/// first three are red, green and blue, through the profile's curves and a
+/// matrix from its linear values to linear sRGB.
+fn transform<T>(
+    samples: &mut [T],
+    channels: usize,
+    profile: &Profile,
+    matrix: &[[f32; 3]; 3],
+    from_unit: impl Fn(f32) -> T + Sync,
+) where
+    T: Copy + Send + Sync,
+    u32: From<T>,
+{
+    let levels = if std::mem::size_of::<T>() == 1 {
+        256
+    } else {
+        65536
+    };
+    let max = (levels - 1) as f32;
+
+    // Lookup tables of the linear value of each encoded level per channel, and
+    // of the sRGB encoding of linear values quantized to 16 bits.
+    let linear = [0, 1, 2].map(|channel| {
+        (0..levels)
+            .map(|level| profile.curves[channel].linearize(level as f32 / max))
+            .collect::<Vec<_>>()
+    });
+
+    let encoded = (0..=u16::MAX)
+        .map(|value| cosmic_bg_config::linear_to_srgb(f32::from(value) / 65535.0))
+        .collect::<Vec<_>>();
+
+    samples.par_chunks_exact_mut(channels).for_each(|pixel| {
+        let rgb = [0, 1, 2].map(|channel| linear[channel][u32::from(pixel[channel]) as usize]);
+
+        for (channel, row) in matrix.iter().enumerate() {
+            let value = (row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2]).clamp(0.0, 1.0);
+            pixel[channel] = from_unit(encoded[(value * 65535.0).round() as usize]);
+        }
+    });
+}
+
+fn multiply(a: &[[f32; 3]; 3], b: &[[f32; 3]; 3]) -> [[f32; 3]; 3] {
+    [0, 1, 2].map(|row| [0, 1, 2].map(|col| (0..3).map(|k| a[row][k] * b[k][col]).sum()))
+}
+
+/// Reads an `XYZType` tag.
+fn xyz(data: &[u8]) -> Result<[f32; 3], Error> {
+    if data.get(..4) != Some(b"XYZ ") {
+        return Err(Error::Unsupported("colorant that is not an XYZ value"));
+    }
+
+    let [x, y, z] = [8, 12, 16].map(|offset| s15_fixed16_at(data, offset));
+    Ok([
+        x.ok_or(Error::Malformed("truncated colorant"))?,
+        y.ok_or(Error::Malformed("truncated colorant"))?,
+        z.ok_or(Error::Malformed("truncated colorant"))?,
+    ])
+}
+
+/// Reads a `curveType` or `parametricCurveType` tag.
+fn curve(data: &[u8]) -> Result<Curve, Error> {
+    let truncated = Error::Malformed("truncated tone curve");
+
+    match data.get(..4) {
+        Some(b"curv") => {
+            let count = u32_at(data, 8).ok_or(truncated)? as usize;
+            match count {
+                0 => Ok(Curve::Gamma(1.0)),
+                1 => {
+                    let gamma = u16_at(data, 12).ok_or(truncated)?;
+                    Ok(Curve::Gamma(f32::from(gamma) / 256.0))
+                }
+                _ => (0..count)
+                    .map(|index| u16_at(data, 12 + 2 * index).map(|v| f32::from(v) / 65535.0))
+                    .collect::<Option<Vec<_>>>()
+                    .map(Curve::Table)
+                    .ok_or(truncated),
+            }
+        }
+
+        Some(b"para") => {
+            let function = u16_at(data, 8).ok_or(truncated)?;
+            let count = match function {
+                0 => 1,
+                1 => 3,
+                2 => 4,
+                3 => 5,
+                4 => 7,
+                _ => return Err(Error::Unsupported("unknown parametric tone curve")),
+            };
+
+            let params = (0..count)
+                .map(|index| s15_fixed16_at(data, 12 + 4 * index))
+                .collect::<Option<Vec<_>>>()
+                .ok_or(truncated)?;
+
+            let params = match *params.as_slice() {
+                [g] => [g, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+                [g, a, b] => [g, a, b, 0.0, -b / a, 0.0, 0.0],
+                [g, a, b, c] => [g, a, b, 0.0, -b / a, c, c],
+                [g, a, b, c, d] => [g, a, b, c, d, 0.0, 0.0],
+                [g, a, b, c, d, e, f] => [g, a, b, c, d, e, f],
+                _ => unreachable!("parameter counts are matched above"),
+            };
+
+            Ok(Curve::Parametric(params))
+        }
+
+        _ => Err(Error::Unsupported("tone curve of an unknown type")),
+    }
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> Option<u16> {
+    let bytes = bytes.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn s15_fixed16_at(bytes: &[u8], offset: usize) -> Option<f32> {
+    u32_at(bytes, offset).map(|v| v as i32 as f32 / 65536.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Colorants of Display P3 in the D50 profile connection space, as columns.
+    const P3_TO_XYZ: [[f32; 3]; 3] = [
+        [0.515_121, 0.291_977, 0.157_104],
+        [0.241_196, 0.692_245, 0.066_574],
+        [-0.001_053, 0.041_885, 0.784_073],
+    ];
+
+    fn s15_fixed16(value: f32) -> [u8; 4] {
+        ((value * 65536.0).round() as i32).to_be_bytes()
+    }
+
+    /// A matrix/TRC profile with the given colorants and the sRGB tone curve,
+    /// which Display P3 shares.
+    fn profile(to_xyz: &[[f32; 3]; 3]) -> Vec<u8> {
+        let mut curve = b"para\0\0\0\0\0\x03\0\0".to_vec();
+        for param in [2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.040_45] {
+            curve.extend(s15_fixed16(param));
+        }
+
+        let mut tags = Vec::new();
+        for (column, signature) in [b"rXYZ", b"gXYZ", b"bXYZ"].into_iter().enumerate() {
+            let mut data = b"XYZ \0\0\0\0".to_vec();
+            for row in to_xyz {
+                data.extend(s15_fixed16(row[column]));
+            }
+            tags.push((signature, data));
+        }
+        for signature in [b"rTRC", b"gTRC", b"bTRC"] {
+            tags.push((signature, curve.clone()));
+        }
+
+        let mut bytes = vec![0; 128];
+        bytes[16..20].copy_from_slice(b"RGB ");
+        bytes.extend((tags.len() as u32).to_be_bytes());
+
+        let mut offset = 132 + 12 * tags.len();
+        for (signature, data) in &tags {
+            bytes.extend(*signature);
+            bytes.extend((offset as u32).to_be_bytes());
+            bytes.extend((data.len() as u32).to_be_bytes());
+            offset += data.len();
+        }
+        for (_, data) in tags {
+            bytes.extend(data);
+        }
+
+        bytes
+    }
+
+    #[test]
+    fn display_p3_pixels_are_converted_to_srgb() {
+        let p3 = Profile::parse(&profile(&P3_TO_XYZ)).unwrap();
+        assert!(!p3.is_srgb());
+
+        let image = image::RgbImage::from_raw(2, 1, vec![200, 100, 50, 255, 255, 255]).unwrap();
+        let converted = to_srgb(DynamicImage::ImageRgb8(image), &p3).into_rgb8();
+
+        for (channel, expected) in [215, 93, 31].into_iter().enumerate() {
+            let value = converted.get_pixel(0, 0).0[channel];
+            assert!(value.abs_diff(expected) <= 1, "{value} != {expected}");
+        }
+        assert_eq!(converted.get_pixel(1, 0).0, [255; 3]);
+    }
+
+    #[test]
+    fn srgb_profiles_are_recognized() {
+        assert!(Profile::parse(&profile(&SRGB_TO_XYZ)).unwrap().is_srgb());
+        assert!(matches!(Profile::parse(&[0; 64]), Err(Error::Malformed(_))));
+    }
+}
@@ -1,45 +1,392 @@
 // SPDX-License-Identifier: MPL-2.0-only
 
-use notify::event::{ModifyKind, RenameMode};
-use sctk::reexports::calloop::{channel, LoopHandle};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+    rc::{Rc, Weak},
+    time::Duration,
+};
+
+use notify::{
+    event::{ModifyKind, RenameMode},
+    RecommendedWatcher, RecursiveMode, Watcher,
+};
+use sctk::reexports::calloop::{
+    channel,
+    timer::{TimeoutAction, Timer},
+    LoopHandle,
+};
+use walkdir::WalkDir;
 
 use crate::CosmicBg;
 
-pub fn img_source(handle: &LoopHandle<CosmicBg>) -> channel::SyncSender<(String, notify::Event)> {
+/// Names of files and directories left by other operating systems and NAS
+/// software in synced directories, which are never wallpapers.
+pub const JUNK_NAMES: &[&str] = &[
+    ".DS_Store",
+    "Thumbs.db",
+    "desktop.ini",
+    "@eaDir",
+    "__MACOSX",
+    "$RECYCLE.BIN",
+    "System Volume Information",
+];
+
+/// Delay between checks of whether a created image is still being written.
+const STABILITY_DELAY: Duration = Duration::from_millis(500);
+
+/// Checks of a created image after which it is skipped if still incomplete.
+const MAX_STABILITY_CHECKS: u32 = 20;
+
+/// The images found in a source path, which is watched for changes as long as
+/// any wallpaper holds the scan.
+pub struct Scan {
+    /// The path that was scanned.
+    pub source: PathBuf,
+    pub images: RefCell<Vec<PathBuf>>,
+    _watcher: Option<RecommendedWatcher>,
+}
+
+impl std::fmt::Debug for Scan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Scan")
+            .field("source", &self.source)
+            .field("images", &self.images)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Scans and watches of source paths, shared between all wallpapers whose
+/// sources are the same path. Each wallpaper keeps its own image queue.
+#[derive(Clone, Debug)]
+pub struct Sources {
+    tx: channel::SyncSender<(PathBuf, notify::Event)>,
+    scans: Rc<RefCell<HashMap<PathBuf, Weak<Scan>>>>,
+    /// Levels of subdirectories that are scanned and watched.
+    watch_depth: Rc<Cell<usize>>,
+}
+
+impl Sources {
+    /// Get the scan of a source path, scanning and watching it if no other
+    /// wallpaper is using it.
+    pub fn scan(&self, source: &Path) -> Rc<Scan> {
+        let mut scans = self.scans.borrow_mut();
+
+        if let Some(scan) = scans.get(source).and_then(Weak::upgrade) {
+            tracing::debug!(?source, "reusing scan of source");
+            return scan;
+        }
+
+        scans.retain(|_, scan| scan.strong_count() > 0);
+
+        let scan = Rc::new(Scan {
+            source: source.to_path_buf(),
+            images: RefCell::new(scan_images(source, self.watch_depth.get())),
+            _watcher: self.watch(source),
+        });
+
+        scans.insert(source.to_path_buf(), Rc::downgrade(&scan));
+        scan
+    }
+
+    /// Sets the levels of subdirectories scanned and watched by later scans.
+    pub fn set_watch_depth(&self, depth: usize) {
+        self.watch_depth.set(depth);
+    }
+
+    /// Get the scan of a source path, if any wallpaper is using it.
+    pub fn get(&self, source: &Path) -> Option<Rc<Scan>> {
+        self.scans.borrow().get(source).and_then(Weak::upgrade)
+    }
+
+    fn watch(&self, source: &Path) -> Option<RecommendedWatcher> {
+        let tx = self.tx.clone();
+        let key = source.to_path_buf();
+
+        let mut watcher = RecommendedWatcher::new(
+            move |res| {
+                if let Ok(e) = res {
+                    let _ = tx.send((key.clone(), e));
+                }
+            },
+            notify::Config::default(),
+        )
+        .ok()?;
+
+        tracing::debug!(?source, "watching source");
+
+        if let Ok(m) = fs::metadata(source) {
+            if m.is_dir() {
+                // Each directory is watched on its own, so that the recursion is
+                // limited to the watch depth.
+                for dir in watched_dirs(source, self.watch_depth.get()) {
+                    if !add_watch(&mut watcher, &dir) {
+                        break;
+                    }
+                }
+            } else if m.is_file() {
+                add_watch(&mut watcher, source);
+            }
+        }
+
+        Some(watcher)
+    }
+}
+
+/// Directories of a source directory that are watched, which are the source and
+/// its subdirectories down to `depth` levels.
+fn watched_dirs(source: &Path, depth: usize) -> Vec<PathBuf> {
+    WalkDir::new(source)
+        .follow_links(true)
+        .max_depth(depth)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_dir())
+        .map(walkdir::DirEntry::into_path)
+        .collect()
+}
+
+/// Watches a path without recursion, returning `false` once the limit of inotify
+/// watches has been reached.
+fn add_watch(watcher: &mut RecommendedWatcher, path: &Path) -> bool {
+    match watcher.watch(path, RecursiveMode::NonRecursive) {
+        Ok(()) => true,
+
+        Err(why) if matches!(why.kind, notify::ErrorKind::MaxFilesWatch) => {
+            tracing::warn!(
+                ?path,
+                "reached the inotify watch limit, so changes to further directories \
+                 are not detected; raise fs.inotify.max_user_watches or lower watch-depth"
+            );
+            false
+        }
+
+        Err(why) => {
+            tracing::warn!(?why, ?path, "failed to watch path");
+            true
+        }
+    }
+}
+
+/// Collects the paths of images within a source path, down to `depth` levels of
+/// subdirectories, which are the directories from [`watched_dirs`].
+fn scan_images(source: &Path, depth: usize) -> Vec<PathBuf> {
+    tracing::debug!(?source, "loading images");
+
+    let Ok(source) = source.canonicalize() else {
+        return Vec::new();
+    };
+
+    if source.is_file() {
+        return vec![source];
+    }
+
+    // Images are one level below the deepest directory that is scanned.
+    WalkDir::new(source)
+        .follow_links(true)
+        .max_depth(depth + 1)
+        .into_iter()
+        .filter_entry(|entry| entry.depth() == 0 || !is_junk(entry.file_name()))
+        .filter_map(Result::ok)
+        .filter(|p| p.path().is_file() && cosmic_bg_config::is_supported_image(p.path()))
+        .map(walkdir::DirEntry::into_path)
+        .collect()
+}
+
+/// Whether a file or directory name is hidden or left by another system.
+#[must_use]
+pub fn is_junk(name: &OsStr) -> bool {
+    name.to_str()
+        .is_some_and(|name| name.starts_with('.') || JUNK_NAMES.contains(&name))
+}
+
+/// Whether a path within a source directory is an image for its slideshow, which
+/// excludes images within hidden or junk directories below the source.
+fn is_slideshow_image(source: &Path, path: &Path) -> bool {
+    let within = path.strip_prefix(source).unwrap_or(path);
+
+    cosmic_bg_config::is_supported_image(path)
+        && !within
+            .components()
+            .any(|component| is_junk(component.as_os_str()))
+}
+
+/// Adds images created within a source path to its scan and the slideshows of
+/// its wallpapers.
+fn add_images(state: &mut CosmicBg, source: &Path, paths: &[PathBuf]) {
+    if let Some(scan) = state.sources.get(source) {
+        let mut images = scan.images.borrow_mut();
+        for p in paths {
+            if !images.contains(p) {
+                images.push(p.into());
+            }
+        }
+    }
+
+    for w in state
+        .wallpapers
+        .iter_mut()
+        .filter(|w| w.uses_source_path(source))
+    {
+        for p in paths {
+            if !w.image_queue.contains(p) && !w.excludes(p) {
+                w.image_queue.push_front(p.into());
+            }
+        }
+
+        if w.entry.reshuffle_on_change {
+            w.reorder_queue();
+        }
+    }
+}
+
+/// Adds created images once they are no longer being written, such as by a
+/// download in progress, which is when their size is the same across a check.
+///
+/// Files that are empty, unreadable or still growing are checked again, up to
+/// [`MAX_STABILITY_CHECKS`] times before they are skipped, so that images being
+/// written are never shown broken.
+fn add_when_stable(state: &mut CosmicBg, source: PathBuf, paths: Vec<PathBuf>) {
+    let mut pending = paths
+        .into_iter()
+        .map(|path| {
+            let size = file_size(&path);
+            (path, size)
+        })
+        .collect::<Vec<_>>();
+
+    let mut checks = 0;
+
+    let result = state.loop_handle.insert_source(
+        Timer::from_duration(STABILITY_DELAY),
+        move |_, _, state: &mut CosmicBg| {
+            checks += 1;
+
+            let mut stable = Vec::new();
+            pending.retain_mut(|(path, size)| {
+                let current = file_size(path);
+                if is_stable(*size, current) {
+                    stable.push(path.clone());
+                    return false;
+                }
+
+                *size = current;
+                true
+            });
+
+            if !stable.is_empty() {
+                add_images(state, &source, &stable);
+            }
+
+            if pending.is_empty() {
+                TimeoutAction::Drop
+            } else if checks >= MAX_STABILITY_CHECKS {
+                for (path, _) in &pending {
+                    tracing::warn!(?path, "skipping image that is empty or still being written");
+                }
+                TimeoutAction::Drop
+            } else {
+                TimeoutAction::ToDuration(STABILITY_DELAY)
+            }
+        },
+    );
+
+    if let Err(why) = result {
+        tracing::error!(?why, "failed to check created images");
+    }
+}
+
+/// Size of a file, or `None` if it can't be read.
+fn file_size(path: &Path) -> Option<u64> {
+    fs::metadata(path).ok().map(|metadata| metadata.len())
+}
+
+/// Whether a file is complete, given its size at two checks. Only empty and
+/// unreadable files are incomplete regardless of their size, as small icons are
+/// valid images.
+fn is_stable(previous: Option<u64>, current: Option<u64>) -> bool {
+    match (previous, current) {
+        (Some(previous), Some(current)) => previous == current && current > 0,
+        _ => false,
+    }
+}
+
+/// How a file in a source path changed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Change {
+    /// Created, or moved into place such as by an atomic save.
+    Created,
+    /// Written to in place.
+    Modified,
+    /// Removed, or moved away.
+    Removed,
+}
+
+/// The change to the files of a source path described by a watcher event, if it
+/// affects the slideshow.
+fn change(kind: &notify::EventKind) -> Option<Change> {
+    match kind {
+        notify::EventKind::Create(_)
+        | notify::EventKind::Modify(ModifyKind::Name(RenameMode::To)) => Some(Change::Created),
+        notify::EventKind::Modify(ModifyKind::Data(_)) => Some(Change::Modified),
+        notify::EventKind::Remove(_)
+        | notify::EventKind::Modify(ModifyKind::Name(RenameMode::From)) => Some(Change::Removed),
+        _ => None,
+    }
+}
+
+pub fn img_source(handle: &LoopHandle<CosmicBg>, watch_depth: usize) -> Sources {
     let (notify_tx, notify_rx) = channel::sync_channel(20);
     let _res = handle
         .insert_source(
             notify_rx,
-            |e: channel::Event<(String, notify::Event)>, _, state| {
+            |e: channel::Event<(PathBuf, notify::Event)>, _, state| {
                 match e {
-                    channel::Event::Msg((source, event)) => match event.kind {
-                        notify::EventKind::Create(_)
-                        | notify::EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+                    channel::Event::Msg((source, event)) => match change(&event.kind) {
+                        Some(Change::Created) => {
+                            let paths = event
+                                .paths
+                                .iter()
+                                .filter(|p| is_slideshow_image(&source, p))
+                                .cloned()
+                                .collect::<Vec<_>>();
+
+                            if !paths.is_empty() {
+                                add_when_stable(state, source, paths);
+                            }
+                        }
+                        Some(Change::Modified) => {
                             for w in state
                                 .wallpapers
                                 .iter_mut()
-                                .filter(|w| w.entry.output == source)
+                                .filter(|w| w.uses_source_path(&source))
                             {
-                                for p in &event.paths {
-                                    if !w.image_queue.contains(p) {
-                                        w.image_queue.push_front(p.into());
-                                    }
-                                }
-                                w.image_queue.retain(|p| !event.paths.contains(p));
-                                // TODO maybe resort or shuffle at some point?
+                                w.reload_image(&event.paths);
                             }
                         }
-                        notify::EventKind::Remove(_)
-                        | notify::EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+                        Some(Change::Removed) => {
+                            if let Some(scan) = state.sources.get(&source) {
+                                scan.images
+                                    .borrow_mut()
+                                    .retain(|p| !event.paths.contains(p));
+                            }
+
                             for w in state
                                 .wallpapers
                                 .iter_mut()
-                                .filter(|w| w.entry.output == source)
+                                .filter(|w| w.uses_source_path(&source))
                             {
                                 w.image_queue.retain(|p| !event.paths.contains(p));
+
+                                if w.entry.reshuffle_on_change {
+                                    w.reorder_queue();
+                                }
                             }
                         }
-                        _ => {}
+                        None => {}
                     },
                     channel::Event::Closed => {
                         // TODO log drop
@@ -50,5 +397,162 @@ pub fn img_source(handle: &LoopHandle<CosmicBg>) -> channel::SyncSender<(String,
         .map(|_| {})
         .map_err(|err| eyre::eyre!("{}", err));
 
-    notify_tx
+    Sources {
+        tx: notify_tx,
+        scans: Rc::default(),
+        watch_depth: Rc::new(Cell::new(watch_depth)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sources(watch_depth: usize) -> Sources {
+        let (tx, _) = channel::sync_channel(20);
+
+        Sources {
+            tx,
+            scans: Rc::default(),
+            watch_depth: Rc::new(Cell::new(watch_depth)),
+        }
+    }
+
+    #[test]
+    fn identical_sources_share_one_scan() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.png"), b"png").unwrap();
+
+        let sources = sources(0);
+        let first = sources.scan(dir.path());
+        let second = sources.scan(dir.path());
+
+        assert!(Rc::ptr_eq(&first, &second));
+        assert_eq!(first.images.borrow().len(), 1);
+
+        // The scan is dropped along with the last wallpaper using it.
+        drop((first, second));
+        assert!(sources.get(dir.path()).is_none());
+    }
+
+    #[test]
+    fn event_kinds_dispatch_to_changes() {
+        use notify::event::{CreateKind, DataChange, EventKind, MetadataKind, RemoveKind};
+
+        assert_eq!(
+            change(&EventKind::Create(CreateKind::File)),
+            Some(Change::Created)
+        );
+        assert_eq!(
+            change(&EventKind::Modify(ModifyKind::Name(RenameMode::To))),
+            Some(Change::Created)
+        );
+        // Writing a single file source in place reloads it.
+        assert_eq!(
+            change(&EventKind::Modify(ModifyKind::Data(DataChange::Content))),
+            Some(Change::Modified)
+        );
+        assert_eq!(
+            change(&EventKind::Remove(RemoveKind::File)),
+            Some(Change::Removed)
+        );
+        assert_eq!(
+            change(&EventKind::Modify(ModifyKind::Name(RenameMode::From))),
+            Some(Change::Removed)
+        );
+        assert_eq!(
+            change(&EventKind::Modify(ModifyKind::Metadata(MetadataKind::Any))),
+            None
+        );
+    }
+
+    #[test]
+    fn watched_dirs_are_limited_to_the_depth() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("a/b/c");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(dir.path().join("a/image.png"), b"png").unwrap();
+
+        let depth = |depth| {
+            let mut dirs = watched_dirs(dir.path(), depth);
+            dirs.sort();
+            dirs
+        };
+
+        assert_eq!(depth(0), [dir.path()]);
+        assert_eq!(depth(1), [dir.path().to_path_buf(), dir.path().join("a")]);
+        assert_eq!(depth(3).len(), 4);
+        assert_eq!(depth(10).last(), Some(&nested));
+    }
+
+    #[test]
+    fn scans_skip_junk_and_non_images() {
+        for name in [
+            "Thumbs.db",
+            ".DS_Store",
+            ".hidden.png",
+            "@eaDir",
+            "__MACOSX",
+        ] {
+            assert!(is_junk(OsStr::new(name)), "{name}");
+        }
+        assert!(!is_junk(OsStr::new("forest.png")));
+
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["forest.png", ".hidden.png", "Thumbs.db", "notes.txt"] {
+            fs::write(dir.path().join(name), b"png").unwrap();
+        }
+
+        let images = scan_images(dir.path(), 0);
+        assert_eq!(
+            images,
+            [dir.path().canonicalize().unwrap().join("forest.png")]
+        );
+
+        // Subdirectories are scanned down to the watched depth.
+        fs::create_dir_all(dir.path().join("trip/day")).unwrap();
+        fs::write(dir.path().join("trip/beach.png"), b"png").unwrap();
+        fs::write(dir.path().join("trip/day/dunes.png"), b"png").unwrap();
+
+        assert_eq!(scan_images(dir.path(), 0).len(), 1);
+        assert_eq!(scan_images(dir.path(), 1).len(), 2);
+        assert_eq!(scan_images(dir.path(), 2).len(), 3);
+
+        // Images within junk directories below the source are skipped too.
+        let source = Path::new("/photos");
+        assert!(is_slideshow_image(
+            source,
+            Path::new("/photos/trip/beach.jpg")
+        ));
+        assert!(!is_slideshow_image(
+            source,
+            Path::new("/photos/@eaDir/beach.jpg")
+        ));
+        assert!(!is_slideshow_image(
+            source,
+            Path::new("/photos/trip/beach.txt")
+        ));
+    }
+
+    #[test]
+    fn images_are_added_once_their_size_settles() {
+        // Sizes of a download at each check, which is stable at the last one.
+        let sizes = [Some(0), Some(4096), Some(8192), Some(8192)];
+        let checks = sizes
+            .windows(2)
+            .map(|pair| is_stable(pair[0], pair[1]))
+            .collect::<Vec<_>>();
+        assert_eq!(checks, [false, false, true]);
+
+        // Tiny images are complete, unlike empty and unreadable files.
+        assert!(is_stable(Some(1), Some(1)));
+        assert!(!is_stable(Some(0), Some(0)));
+        assert!(!is_stable(Some(1), None));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("icon.png");
+        assert_eq!(file_size(&path), None);
+        fs::write(&path, b"png").unwrap();
+        assert_eq!(file_size(&path), Some(3));
+    }
 }
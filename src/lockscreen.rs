@@ -0,0 +1,58 @@
+// SPDX-License-Identifier: MPL-2.0-only
+
+//! Blurred thumbnails of the current wallpaper, for use by the greeter and lock screen.
+//!
+//! An image is written to `$XDG_STATE_HOME/cosmic-bg/lockscreen/<output>.png`
+//! whenever the wallpaper of an output changes.
+
+use std::path::{Path, PathBuf};
+
+use eyre::OptionExt;
+use image::DynamicImage;
+
+/// Largest width or height of a thumbnail.
+const THUMBNAIL_SIZE: u32 = 480;
+const BLUR_SIGMA: f32 = 8.0;
+
+/// Directory of the lock screen thumbnails.
+#[must_use]
+pub fn dir() -> Option<PathBuf> {
+    dirs::state_dir().map(|dir| dir.join("cosmic-bg").join("lockscreen"))
+}
+
+/// Writes a blurred thumbnail of the rendered wallpaper of an output.
+pub fn write(output: &str, image: &DynamicImage) -> eyre::Result<PathBuf> {
+    write_in(&dir().ok_or_eyre("no state directory")?, output, image)
+}
+
+/// Writes the thumbnail of an output as `<output>.png` in `dir`.
+fn write_in(dir: &Path, output: &str, image: &DynamicImage) -> eyre::Result<PathBuf> {
+    let path = dir.join(format!("{output}.png"));
+    std::fs::create_dir_all(dir)?;
+
+    image
+        .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+        .blur(BLUR_SIGMA)
+        .to_rgb8()
+        .save(&path)?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn writes_thumbnail_per_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(1920, 1080, Rgb([255, 0, 0])));
+
+        let path = write_in(dir.path(), "DP-1", &image).unwrap();
+        assert_eq!(path, dir.path().join("DP-1.png"));
+
+        let thumbnail = image::open(&path).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (480, 270));
+    }
+}
@@ -0,0 +1,130 @@
+// SPDX-License-Identifier: MPL-2.0-only
+
+//! Logging to a file for headless debugging, enabled with `COSMIC_BG_LOG_FILE`.
+//!
+//! Logs are written to files named after the given path with the date appended,
+//! such as `cosmic-bg.log.2024-03-01`, which are rotated daily, keeping at most
+//! [`MAX_FILES`] of them. Each day's file is limited to [`MAX_BYTES`], after which
+//! the logs of that day are dropped, so that a log loop can't fill the disk.
+
+use std::{
+    io::{self, Write},
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tracing_appender::rolling::{InitError, RollingFileAppender, Rotation};
+
+/// Environment variable with the path of the log file.
+pub const ENV: &str = "COSMIC_BG_LOG_FILE";
+
+/// Number of daily log files that are kept.
+pub const MAX_FILES: usize = 2;
+
+/// Size in bytes that the logs of a day are limited to.
+pub const MAX_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Opens the log files for the path, creating its directory if missing.
+pub fn appender(path: &Path) -> Result<Mutex<Limited<RollingFileAppender>>, InitError> {
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+
+    let mut builder = RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .max_log_files(MAX_FILES);
+
+    if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+        builder = builder.filename_prefix(name);
+    }
+
+    builder
+        .build(dir)
+        .map(|appender| Mutex::new(Limited::new(appender, MAX_BYTES)))
+}
+
+/// A writer of daily logs, which drops the logs of a day once `limit` bytes
+/// were written on it.
+pub struct Limited<W> {
+    writer: W,
+    limit: u64,
+    /// The day since the Unix epoch that the logs were last written on.
+    day: u64,
+    written: u64,
+}
+
+impl<W: Write> Limited<W> {
+    pub fn new(writer: W, limit: u64) -> Self {
+        Self {
+            writer,
+            limit,
+            day: 0,
+            written: 0,
+        }
+    }
+
+    /// Writes the logs of a `day`, noting once that the rest of them are dropped
+    /// when the limit is reached.
+    fn write_on(&mut self, day: u64, buf: &[u8]) -> io::Result<usize> {
+        if day != self.day {
+            self.day = day;
+            self.written = 0;
+        }
+
+        if self.written >= self.limit {
+            return Ok(buf.len());
+        }
+
+        self.written += buf.len() as u64;
+
+        if self.written >= self.limit {
+            self.writer
+                .write_all(b"log size limit reached, dropping further logs of the day\n")?;
+            return Ok(buf.len());
+        }
+
+        self.writer.write_all(buf)?;
+        Ok(buf.len())
+    }
+}
+
+impl<W: Write> Write for Limited<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Log files are rotated at midnight UTC.
+        let day = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs() / 86400);
+
+        self.write_on(day, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logs_are_dropped_past_the_daily_limit() {
+        let mut limited = Limited::new(Vec::new(), 10);
+
+        limited.write_on(1, b"12345\n").unwrap();
+        // The line that reaches the limit is replaced by a note.
+        limited.write_on(1, b"67890\n").unwrap();
+        limited.write_on(1, b"dropped\n").unwrap();
+        assert_eq!(
+            limited.writer,
+            b"12345\nlog size limit reached, dropping further logs of the day\n"
+        );
+
+        // Logs of the next day are written again.
+        limited.writer.clear();
+        limited.write_on(2, b"next\n").unwrap();
+        assert_eq!(limited.writer, b"next\n");
+    }
+}
@@ -1,12 +1,27 @@
 // SPDX-License-Identifier: MPL-2.0-only
 
+mod archive;
+mod blend;
+mod clock;
 mod colored;
+mod doctor;
 mod draw;
+mod effects;
+mod hash;
+mod icc;
 mod img_source;
+mod lockscreen;
+mod logfile;
+mod output;
+mod overlay;
 mod scaler;
+mod schedule;
+mod sleep;
+mod stats;
 mod wallpaper;
+mod warmstart;
 
-use cosmic_bg_config::{state::State, Config};
+use cosmic_bg_config::{state::State, Command, Config, Edge, Entry, NewOutputPolicy, Source};
 use cosmic_config::{calloop::ConfigWatchSource, CosmicConfigEntry};
 use eyre::Context;
 use sctk::{
@@ -14,7 +29,10 @@ use sctk::{
     delegate_compositor, delegate_layer, delegate_output, delegate_registry, delegate_shm,
     output::{OutputHandler, OutputInfo, OutputState},
     reexports::{
-        calloop,
+        calloop::{
+            self,
+            timer::{TimeoutAction, Timer},
+        },
         calloop_wayland_source::WaylandSource,
         client::{
             delegate_noop,
@@ -43,9 +61,10 @@ use sctk::{
     },
     shm::{slot::SlotPool, Shm, ShmHandler},
 };
+use std::{path::Path, time::Duration};
 use tracing::error;
 use tracing_subscriber::prelude::*;
-use wallpaper::Wallpaper;
+use wallpaper::{Fade, Wallpaper};
 
 #[cfg(target_env = "gnu")]
 extern "C" {
@@ -62,6 +81,171 @@ pub struct CosmicBgLayer {
     needs_redraw: bool,
     size: Option<(u32, u32)>,
     fractional_scale: Option<u32>,
+    /// Whether the buffer is rendered at a reduced resolution because shared
+    /// memory for a full resolution buffer could not be allocated.
+    degraded: bool,
+    /// Source of the wallpaper last written as the lock screen thumbnail and
+    /// warm start image.
+    lockscreen_source: Option<Source>,
+    /// Whether a buffer has been committed to the layer.
+    drawn: bool,
+    /// Startup fade of the layer, if it is still fading in.
+    fade: Fade,
+    /// Transform of the output, which buffers are rendered in so that the
+    /// compositor doesn't need to rotate them.
+    transform: wl_output::Transform,
+    /// Status of the wallpaper last recorded for the output.
+    status: Option<cosmic_bg_config::state::Status>,
+    /// Buffer size forced by config for the output, which the viewport scales
+    /// to the surface.
+    render_size: Option<(u32, u32)>,
+    /// Sizes of the enlarged buffer of a zoom that follows the pointer and of
+    /// the part of it that the viewport shows.
+    zoom_crop: Option<((u32, u32), (u32, u32))>,
+}
+
+impl CosmicBgLayer {
+    /// Creates or resizes the layer's pool for a buffer of the size that its
+    /// wallpaper draws, from [`draw_size`].
+    ///
+    /// If the allocation fails, a pool for a buffer of half the resolution is
+    /// attempted instead, which the viewport upscales to the full output size.
+    /// Full resolution is attempted again on every configure and scale change.
+    fn allocate_pool(&mut self, shm: &Shm, max_render_edge: u32) -> bool {
+        let Some(size) = self.size else {
+            return false;
+        };
+
+        // Until the compositor sends a preferred scale, buffers are sized as if
+        // the scale is 1.
+        let scale = self.fractional_scale.unwrap_or(120);
+        let (width, height) = draw_size(size, scale, self.render_size, max_render_edge, false);
+
+        if self.resize_pool(shm, width as usize * height as usize * 4) {
+            if self.degraded {
+                tracing::info!(width, height, "restored full resolution buffer");
+            }
+            self.degraded = false;
+            return true;
+        }
+
+        let (degraded_width, degraded_height) =
+            draw_size(size, scale, self.render_size, max_render_edge, true);
+
+        if self.resize_pool(shm, degraded_width as usize * degraded_height as usize * 4) {
+            tracing::warn!(
+                degraded_width,
+                degraded_height,
+                "rendering at reduced resolution"
+            );
+            self.degraded = true;
+            return true;
+        }
+
+        false
+    }
+
+    fn resize_pool(&mut self, shm: &Shm, len: usize) -> bool {
+        if let Some(pool) = self.pool.as_mut() {
+            if let Err(why) = pool.resize(len) {
+                tracing::error!(?why, len, "failed to resize pool");
+                return false;
+            }
+
+            return true;
+        }
+
+        match SlotPool::new(len, shm) {
+            Ok(pool) => {
+                self.pool = Some(pool);
+                true
+            }
+
+            Err(why) => {
+                tracing::error!(?why, len, "failed to create pool");
+                false
+            }
+        }
+    }
+}
+
+/// Longest time for which wallpapers may be shown above windows.
+const MAX_OVERLAY_DURATION: Duration = Duration::from_secs(60);
+
+/// Interval between checks of the brightness schedule.
+const BRIGHTNESS_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Smallest change in scheduled brightness for which wallpapers are redrawn.
+const BRIGHTNESS_THRESHOLD: f32 = 0.02;
+
+/// Largest width or height of a buffer that will be allocated.
+pub const MAX_BUFFER_SIZE: u32 = 16384;
+
+/// Whether a surface size can be drawn to. Outputs may transiently configure
+/// a zero size, or report implausible sizes that would over-allocate.
+#[must_use]
+pub fn valid_size(width: u32, height: u32) -> bool {
+    (1..=MAX_BUFFER_SIZE).contains(&width) && (1..=MAX_BUFFER_SIZE).contains(&height)
+}
+
+/// Size of the buffer of a surface of a logical size at a fractional scale in
+/// 120ths, unless the config forces a `render_size` for its output.
+#[must_use]
+pub fn scaled_size(
+    width: u32,
+    height: u32,
+    fractional_scale: u32,
+    render_size: Option<(u32, u32)>,
+) -> (u32, u32) {
+    render_size.unwrap_or((
+        width * fractional_scale / 120,
+        height * fractional_scale / 120,
+    ))
+}
+
+/// Size of the buffers drawn for a layer of a logical `size`, which is scaled by
+/// its fractional scale or forced by its `render_size`, limited to `max_edge`,
+/// and halved if the layer is `degraded`.
+#[must_use]
+pub fn draw_size(
+    (width, height): (u32, u32),
+    fractional_scale: u32,
+    render_size: Option<(u32, u32)>,
+    max_edge: u32,
+    degraded: bool,
+) -> (u32, u32) {
+    let (width, height) = scaled_size(width, height, fractional_scale, render_size);
+    let (width, height) = capped_size(width, height, max_edge);
+
+    if degraded {
+        degraded_size(width, height)
+    } else {
+        (width, height)
+    }
+}
+
+/// Buffer size used when a full resolution buffer can't be allocated.
+#[must_use]
+pub fn degraded_size(width: u32, height: u32) -> (u32, u32) {
+    ((width / 2).max(1), (height / 2).max(1))
+}
+
+/// Buffer size with its longest edge limited to `max_edge`, keeping the aspect
+/// ratio. A `max_edge` of `0` leaves the size unchanged.
+#[must_use]
+pub fn capped_size(width: u32, height: u32, max_edge: u32) -> (u32, u32) {
+    let longest = width.max(height);
+
+    if max_edge == 0 || longest <= max_edge {
+        return (width, height);
+    }
+
+    let scale = |edge: u32| {
+        ((u64::from(edge) * u64::from(max_edge) + u64::from(longest) / 2) / u64::from(longest))
+            .max(1) as u32
+    };
+
+    (scale(width), scale(height))
 }
 
 #[allow(clippy::too_many_lines)]
@@ -74,6 +258,11 @@ fn main() -> color_eyre::Result<()> {
 
     init_logger();
 
+    let mut args = std::env::args().skip(1);
+    if args.next().as_deref() == Some("doctor") {
+        return doctor::run(args.any(|arg| arg == "--json"));
+    }
+
     let conn = Connection::connect_to_env().wrap_err("wayland client connection failed")?;
 
     let mut event_loop: calloop::EventLoop<'static, CosmicBg> =
@@ -133,12 +322,116 @@ fn main() -> color_eyre::Result<()> {
                                 changes_applied = true;
                             }
 
+                            cosmic_bg_config::OVERLAY => {
+                                state.show_overlay(Duration::from_secs(conf_context.overlay()));
+                            }
+
+                            cosmic_bg_config::MAX_RENDER_EDGE => {
+                                tracing::debug!("updating max render edge");
+                                let max_render_edge = conf_context.max_render_edge();
+
+                                if state.config.max_render_edge != max_render_edge {
+                                    state.config.max_render_edge = max_render_edge;
+                                    changes_applied = true;
+                                }
+                            }
+
+                            cosmic_bg_config::PRIMARY_OUTPUT => {
+                                tracing::debug!("updating primary output");
+                                let primary_output = conf_context.primary_output();
+
+                                if state.config.primary_output != primary_output {
+                                    state.config.primary_output = primary_output;
+                                    changes_applied = true;
+                                }
+                            }
+
+                            cosmic_bg_config::OUTPUT_RENDER_SIZE => {
+                                tracing::debug!("updating output render sizes");
+                                let output_render_size = conf_context.output_render_size();
+
+                                if state.config.output_render_size != output_render_size {
+                                    state.config.output_render_size = output_render_size;
+                                    changes_applied = true;
+                                }
+                            }
+
+                            cosmic_bg_config::WATCH_DEPTH => {
+                                tracing::debug!("updating watch depth");
+                                let watch_depth = conf_context.watch_depth();
+
+                                if state.config.watch_depth != watch_depth {
+                                    state.config.watch_depth = watch_depth;
+                                    state.sources.set_watch_depth(watch_depth);
+                                    changes_applied = true;
+                                }
+                            }
+
+                            cosmic_bg_config::BRIGHTNESS_SCHEDULE => {
+                                tracing::debug!("updating brightness schedule");
+                                state.config.brightness_schedule =
+                                    conf_context.brightness_schedule();
+                                state.update_brightness();
+                                state.register_brightness_timer();
+                            }
+
+                            cosmic_bg_config::NIGHT_LIGHT => {
+                                tracing::debug!("updating night light");
+                                state.config.night_light = conf_context.night_light();
+                                state.update_night_light();
+                            }
+
+                            cosmic_bg_config::NEW_OUTPUT_POLICY => {
+                                tracing::debug!("updating new output policy");
+                                state.config.new_output_policy = conf_context.new_output_policy();
+                            }
+
+                            cosmic_bg_config::COMMAND => {
+                                // Clearing the command modifies the key again,
+                                // which is then read as no command.
+                                let Some(command) = conf_context.command() else {
+                                    continue;
+                                };
+
+                                tracing::debug!(?command, "running command");
+                                if let Err(why) = conf_context.clear_command() {
+                                    tracing::error!(?why, "failed to clear command");
+                                }
+
+                                if run_command(&mut state.wallpapers, &command) {
+                                    match Config::load(&conf_context) {
+                                        Ok(config) => {
+                                            state.config = config;
+                                            changes_applied = true;
+                                        }
+                                        Err(why) => {
+                                            tracing::error!(?why, "failed to reload config");
+                                        }
+                                    }
+                                }
+                            }
+
+                            cosmic_bg_config::PREVIEW => match conf_context.preview() {
+                                Some(entry) => state.preview(entry),
+                                None => state.cancel_preview(),
+                            },
+
+                            cosmic_bg_config::POINTER_POSITION => {
+                                state.pointer_position = conf_context.pointer_position();
+                                state.update_parallax();
+                            }
+
                             _ => {
                                 tracing::debug!(key, "key modified");
                                 if let Some(output) = key.strip_prefix("output.") {
                                     if let Ok(new_entry) = conf_context.entry(key) {
-                                        if let Some(existing) = state.config.entry_mut(output) {
-                                            *existing = new_entry;
+                                        if !update_output_entry(
+                                            &mut state.wallpapers,
+                                            &state.preview,
+                                            &mut state.config,
+                                            output,
+                                            new_entry,
+                                        ) {
                                             changes_applied = true;
                                         }
                                     }
@@ -177,7 +470,10 @@ fn main() -> color_eyre::Result<()> {
         }
     };
 
-    let source_tx = img_source::img_source(&event_loop.handle());
+    let sources = img_source::img_source(&event_loop.handle(), config.watch_depth);
+
+    let brightness =
+        schedule::brightness(&config.brightness_schedule, schedule::local_minute_of_day());
 
     // initial setup with all images
     let wallpapers = {
@@ -189,7 +485,10 @@ fn main() -> color_eyre::Result<()> {
                     bg.clone(),
                     qh.clone(),
                     event_loop.handle(),
-                    source_tx.clone(),
+                    sources.clone(),
+                    config.max_render_edge,
+                    brightness,
+                    config.night_light,
                 )
             })
         });
@@ -200,9 +499,16 @@ fn main() -> color_eyre::Result<()> {
             config.default_background.clone(),
             qh.clone(),
             event_loop.handle(),
-            source_tx.clone(),
+            sources.clone(),
+            config.max_render_edge,
+            brightness,
+            config.night_light,
         ));
 
+        for wallpaper in &mut wallpapers {
+            wallpaper.startup_fade = true;
+        }
+
         wallpapers
     };
 
@@ -215,14 +521,47 @@ fn main() -> color_eyre::Result<()> {
         viewporter: globals.bind(&qh, 1..=1, ()).unwrap(),
         fractional_scale_manager: globals.bind(&qh, 1..=1, ()).unwrap(),
         qh,
-        source_tx,
+        sources,
         loop_handle: event_loop.handle(),
         exit: false,
         wallpapers,
         config,
         active_outputs: Vec::new(),
+        pointer_position: None,
+        overlay: false,
+        overlay_token: None,
+        preview: Preview::default(),
+        brightness,
+        brightness_token: None,
     };
 
+    bg_state.register_brightness_timer();
+
+    // Wallpapers are brought up to date when logind reports a resume from suspend.
+    let (resume_tx, resume_rx) = calloop::channel::channel();
+    sleep::watch(resume_tx);
+    let _res = bg_state
+        .loop_handle
+        .insert_source(resume_rx, |event, _, state| {
+            if let calloop::channel::Event::Msg(()) = event {
+                state.reinitialize_after_resume();
+            }
+        });
+
+    // Health statistics are written periodically if a file is given for them.
+    if let Some(path) = std::env::var_os(stats::ENV).map(std::path::PathBuf::from) {
+        let _res = bg_state.loop_handle.insert_source(
+            Timer::from_duration(stats::INTERVAL),
+            move |_, _, state| {
+                if let Err(why) = stats::write(&path, &state.wallpapers) {
+                    tracing::warn!(?why, "failed to write stats file {}", path.display());
+                }
+
+                TimeoutAction::ToDuration(stats::INTERVAL)
+            },
+        );
+    }
+
     loop {
         event_loop.dispatch(None, &mut bg_state)?;
 
@@ -244,81 +583,309 @@ pub struct CosmicBg {
     viewporter: wp_viewporter::WpViewporter,
     fractional_scale_manager: wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
     qh: QueueHandle<CosmicBg>,
-    source_tx: calloop::channel::SyncSender<(String, notify::Event)>,
+    sources: img_source::Sources,
     loop_handle: calloop::LoopHandle<'static, CosmicBg>,
     exit: bool,
     wallpapers: Vec<Wallpaper>,
     config: Config,
     active_outputs: Vec<WlOutput>,
+    pointer_position: Option<(f32, f32)>,
+    /// Whether wallpapers are temporarily shown above windows.
+    overlay: bool,
+    overlay_token: Option<calloop::RegistrationToken>,
+    /// Brightness of wallpapers on the brightness schedule.
+    brightness: f32,
+    /// Timer following the brightness schedule, if there is one.
+    brightness_token: Option<calloop::RegistrationToken>,
+    /// Unsaved entry previewed by settings.
+    preview: Preview,
 }
 
 impl CosmicBg {
     fn apply_backgrounds(&mut self) {
         self.wallpapers.clear();
+        // Wallpapers are recreated from the saved config, without the preview.
+        self.preview = Preview::default();
 
         let mut all_wallpaper = Wallpaper::new(
             self.config.default_background.clone(),
             self.qh.clone(),
             self.loop_handle.clone(),
-            self.source_tx.clone(),
+            self.sources.clone(),
+            self.config.max_render_edge,
+            self.brightness,
+            self.config.night_light,
         );
 
         let mut backgrounds = self.config.backgrounds.clone();
         backgrounds.sort_by(|a, b| a.output.cmp(&b.output));
 
-        'outer: for output in &self.active_outputs {
+        let output_infos = self.output_infos();
+
+        for output in &self.active_outputs {
             let Some(output_info) = self.output_state.info(output) else {
                 continue;
             };
 
-            let o_name = output_info.name.clone().unwrap_or_default();
-            for background in &backgrounds {
-                if background.output == o_name {
-                    let mut new_wallpaper = Wallpaper::new(
-                        background.clone(),
-                        self.qh.clone(),
-                        self.loop_handle.clone(),
-                        self.source_tx.clone(),
-                    );
-
-                    new_wallpaper
-                        .layers
-                        .push(self.new_layer(output.clone(), output_info));
-                    _ = new_wallpaper.save_state();
-                    self.wallpapers.push(new_wallpaper);
-
-                    continue 'outer;
-                }
+            let background = backgrounds
+                .iter()
+                .filter_map(|background| {
+                    output::matches(
+                        &background.output,
+                        &output_info,
+                        &output_infos,
+                        self.config.primary_output.as_deref(),
+                    )
+                    .map(|matched| (matched, background))
+                })
+                .max_by_key(|(matched, _)| *matched)
+                .map(|(_, background)| background);
+
+            if let Some(background) = background {
+                let mut new_wallpaper = Wallpaper::new(
+                    background.clone(),
+                    self.qh.clone(),
+                    self.loop_handle.clone(),
+                    self.sources.clone(),
+                    self.config.max_render_edge,
+                    self.brightness,
+                    self.config.night_light,
+                );
+
+                let layer = self.new_layer(output.clone(), output_info, &new_wallpaper.entry);
+                new_wallpaper.layers.push(layer);
+                _ = new_wallpaper.save_state();
+                self.wallpapers.push(new_wallpaper);
+
+                continue;
             }
 
-            all_wallpaper
-                .layers
-                .push(self.new_layer(output.clone(), output_info));
+            let layer = self.new_layer(output.clone(), output_info, &all_wallpaper.entry);
+            all_wallpaper.layers.push(layer);
         }
 
         _ = all_wallpaper.save_state();
         self.wallpapers.push(all_wallpaper);
+
+        for wallpaper in &mut self.wallpapers {
+            wallpaper.parallax_offset = self.pointer_position.unwrap_or_default();
+        }
+    }
+
+    /// Brings wallpapers up to date after resuming from suspend, which the
+    /// brightness schedule and images of the day may have moved on during.
+    fn reinitialize_after_resume(&mut self) {
+        tracing::debug!("resumed from suspend, redrawing wallpapers");
+        self.update_brightness();
+
+        for wallpaper in &mut self.wallpapers {
+            wallpaper.resume();
+        }
+    }
+
+    /// Shows an entry on the wallpaper of its output without saving it.
+    fn preview(&mut self, entry: Entry) {
+        if !self
+            .preview
+            .show(&mut self.wallpapers, &self.config, entry.clone())
+        {
+            self.apply_backgrounds();
+            self.preview.show(&mut self.wallpapers, &self.config, entry);
+        }
+    }
+
+    /// Restores the saved entry of the previewed output.
+    fn cancel_preview(&mut self) {
+        if !self.preview.cancel(&mut self.wallpapers, &self.config) {
+            self.apply_backgrounds();
+        }
+    }
+
+    /// Redraws parallax wallpapers for the latest pointer position.
+    fn update_parallax(&mut self) {
+        let offset = self.pointer_position.unwrap_or_default();
+
+        for wallpaper in &mut self.wallpapers {
+            wallpaper.set_parallax_offset(offset);
+        }
+    }
+
+    /// Recreates the layer surfaces above windows for `duration`, after which they
+    /// return to the background. A zero duration ends an active overlay.
+    fn show_overlay(&mut self, duration: Duration) {
+        if let Some(token) = self.overlay_token.take() {
+            self.loop_handle.remove(token);
+        }
+
+        let duration = duration.min(MAX_OVERLAY_DURATION);
+        let overlay = !duration.is_zero();
+
+        if overlay {
+            // Reverts even if the client requesting the overlay goes away.
+            self.overlay_token = self
+                .loop_handle
+                .insert_source(Timer::from_duration(duration), |_, _, state| {
+                    state.overlay_token = None;
+                    state.show_overlay(Duration::ZERO);
+                    TimeoutAction::Drop
+                })
+                .ok();
+        }
+
+        if self.overlay != overlay {
+            tracing::debug!(overlay, ?duration, "changing wallpaper layer");
+            self.overlay = overlay;
+            // Surfaces can't move between layers, and must be recreated.
+            self.apply_backgrounds();
+        }
+    }
+
+    /// Follows the brightness schedule, which is checked every minute, or stops
+    /// following it if it has no points.
+    fn register_brightness_timer(&mut self) {
+        if let Some(token) = self.brightness_token.take() {
+            self.loop_handle.remove(token);
+        }
+
+        if self.config.brightness_schedule.is_empty() {
+            return;
+        }
+
+        let result = self.loop_handle.insert_source(
+            Timer::from_duration(BRIGHTNESS_INTERVAL),
+            |_, _, state| {
+                state.update_brightness();
+                TimeoutAction::ToDuration(BRIGHTNESS_INTERVAL)
+            },
+        );
+
+        match result {
+            Ok(token) => self.brightness_token = Some(token),
+            Err(why) => tracing::error!(?why, "failed to follow the brightness schedule"),
+        }
+    }
+
+    /// Redraws wallpapers if their brightness on the schedule has changed.
+    fn update_brightness(&mut self) {
+        let brightness = schedule::brightness(
+            &self.config.brightness_schedule,
+            schedule::local_minute_of_day(),
+        );
+
+        if (brightness - self.brightness).abs() < BRIGHTNESS_THRESHOLD {
+            return;
+        }
+
+        tracing::debug!(brightness, "changing wallpaper brightness");
+        self.brightness = brightness;
+
+        for wallpaper in &mut self.wallpapers {
+            wallpaper.brightness = brightness;
+            for layer in &mut wallpaper.layers {
+                layer.needs_redraw = true;
+            }
+            wallpaper.draw();
+        }
+    }
+
+    /// Redraws wallpapers warmed to the color temperature of the night light,
+    /// or without warming once it turns off.
+    fn update_night_light(&mut self) {
+        let night_light = self.config.night_light;
+
+        for wallpaper in &mut self.wallpapers {
+            if wallpaper.night_light == night_light {
+                continue;
+            }
+
+            wallpaper.night_light = night_light;
+            for layer in &mut wallpaper.layers {
+                layer.needs_redraw = true;
+            }
+            wallpaper.draw();
+        }
+    }
+
+    /// Colors the wallpapers of `AutoMatchPrimary` sources to match the wallpaper
+    /// of the primary output.
+    fn match_primary_colors(&mut self) {
+        if !self.wallpapers.iter().any(Wallpaper::matches_primary) {
+            return;
+        }
+
+        let output_infos = self.output_infos();
+        let primary = self.config.primary_output.as_deref();
+
+        let Some(color) = self
+            .wallpapers
+            .iter()
+            .find(|w| {
+                w.layers
+                    .iter()
+                    .any(|l| output::is_primary(&l.output_info, &output_infos, primary))
+            })
+            .and_then(Wallpaper::matching_color)
+        else {
+            return;
+        };
+
+        for wallpaper in &mut self.wallpapers {
+            if wallpaper.matches_primary() {
+                wallpaper.set_matched_color(color.clone());
+            }
+        }
+    }
+
+    /// Info of every connected output.
+    fn output_infos(&self) -> Vec<OutputInfo> {
+        self.active_outputs
+            .iter()
+            .filter_map(|output| self.output_state.info(output))
+            .collect()
     }
 
     #[must_use]
-    pub fn new_layer(&self, output: WlOutput, output_info: OutputInfo) -> CosmicBgLayer {
+    pub fn new_layer(
+        &self,
+        output: WlOutput,
+        output_info: OutputInfo,
+        entry: &Entry,
+    ) -> CosmicBgLayer {
         let surface = self.compositor_state.create_surface(&self.qh);
 
         let layer = self.layer_state.create_layer_surface(
             &self.qh,
             surface.clone(),
-            Layer::Background,
+            surface_layer(self.overlay),
             "wallpaper".into(),
             Some(&output),
         );
 
-        layer.set_anchor(Anchor::all());
+        let anchor = layer_anchor(&entry.anchor);
+        layer.set_anchor(anchor);
+        layer.set_margin(
+            entry.margin_top,
+            entry.margin_right,
+            entry.margin_bottom,
+            entry.margin_left,
+        );
+
+        if let Some((width, height)) = requested_size(entry, output_info.logical_size) {
+            layer.set_size(width, height);
+        }
+
         layer.set_exclusive_zone(-1);
         layer.set_keyboard_interactivity(KeyboardInteractivity::None);
         surface.commit();
 
         let viewport = self.viewporter.get_viewport(&surface, &self.qh, ());
 
+        let render_size = output_info
+            .name
+            .as_deref()
+            .and_then(|name| self.config.output_render_size.get(name))
+            .copied();
+
         self.fractional_scale_manager
             .get_fractional_scale(&surface, &self.qh, surface.downgrade());
 
@@ -331,6 +898,285 @@ impl CosmicBg {
             fractional_scale: None,
             needs_redraw: false,
             pool: None,
+            degraded: false,
+            lockscreen_source: None,
+            drawn: false,
+            fade: Fade::default(),
+            transform: wl_output::Transform::Normal,
+            status: None,
+            render_size,
+            zoom_crop: None,
+        }
+    }
+}
+
+/// Shell layer of wallpaper surfaces.
+fn surface_layer(overlay: bool) -> Layer {
+    if overlay {
+        Layer::Overlay
+    } else {
+        Layer::Background
+    }
+}
+
+/// Layer surface anchor for the configured edges, where no edges anchors to all.
+fn layer_anchor(edges: &[Edge]) -> Anchor {
+    if edges.is_empty() {
+        return Anchor::all();
+    }
+
+    edges.iter().fold(Anchor::empty(), |anchor, edge| {
+        anchor
+            | match edge {
+                Edge::Top => Anchor::TOP,
+                Edge::Bottom => Anchor::BOTTOM,
+                Edge::Left => Anchor::LEFT,
+                Edge::Right => Anchor::RIGHT,
+            }
+    })
+}
+
+/// Size to request for a layer on an output of the given logical size, or `None`
+/// if the layer is anchored to all edges.
+///
+/// The compositor only picks the size on axes anchored to both edges, which are
+/// left as `0`.
+fn requested_size(entry: &Entry, logical_size: Option<(i32, i32)>) -> Option<(u32, u32)> {
+    let anchor = layer_anchor(&entry.anchor);
+    let horizontal = anchor.contains(Anchor::LEFT | Anchor::RIGHT);
+    let vertical = anchor.contains(Anchor::TOP | Anchor::BOTTOM);
+
+    if horizontal && vertical {
+        return None;
+    }
+
+    let (width, height) = logical_size.unwrap_or_default();
+    let width = width - entry.margin_left - entry.margin_right;
+    let height = height - entry.margin_top - entry.margin_bottom;
+
+    Some((
+        if horizontal { 0 } else { width.max(1) as u32 },
+        if vertical { 0 } else { height.max(1) as u32 },
+    ))
+}
+
+/// Wallpaper shown on a newly connected output, which is the one whose entry
+/// matches the output, or else the one chosen by the policy for new outputs,
+/// among the one `mirrored` from the primary output and the `default` one.
+fn new_output_wallpaper(
+    policy: NewOutputPolicy,
+    matched: Option<usize>,
+    mirrored: Option<usize>,
+    default: Option<usize>,
+) -> Option<usize> {
+    matched
+        .or(match policy {
+            NewOutputPolicy::Default => None,
+            NewOutputPolicy::MirrorPrimary => mirrored,
+        })
+        .or(default)
+}
+
+/// The output whose wallpaper a `new` output mirrors, which is the primary one
+/// of the connected `outputs` if that is another output.
+fn mirrored_output<'a, D: output::Display>(
+    new: &D,
+    outputs: &'a [D],
+    primary: Option<&str>,
+) -> Option<&'a D> {
+    outputs
+        .iter()
+        .find(|info| info.name() != new.name() && output::is_primary(*info, outputs, primary))
+}
+
+/// A wallpaper that the actions of commands apply to.
+trait Slideshow {
+    fn entry(&self) -> &Entry;
+    /// Replaces the entry, returning `false` without changes if the wallpaper
+    /// must be recreated for it.
+    fn set_entry(&mut self, entry: Entry) -> bool;
+    fn shows_output(&self, output: &str) -> bool;
+    fn show_next(&mut self);
+    fn show_previous(&mut self);
+    fn show_random(&mut self);
+    fn pin(&mut self);
+    fn unpin(&mut self);
+}
+
+impl Slideshow for Wallpaper {
+    fn entry(&self) -> &Entry {
+        &self.entry
+    }
+
+    fn set_entry(&mut self, entry: Entry) -> bool {
+        Wallpaper::set_entry(self, entry)
+    }
+
+    fn shows_output(&self, output: &str) -> bool {
+        Wallpaper::shows_output(self, output)
+    }
+
+    fn show_next(&mut self) {
+        Wallpaper::show_next(self);
+    }
+
+    fn show_previous(&mut self) {
+        Wallpaper::show_previous(self);
+    }
+
+    fn show_random(&mut self) {
+        Wallpaper::show_random(self);
+    }
+
+    fn pin(&mut self) {
+        Wallpaper::pin(self);
+    }
+
+    fn unpin(&mut self) {
+        Wallpaper::unpin(self);
+    }
+}
+
+/// Runs a command on the slideshows it applies to. Reloading the config is left
+/// to the caller, and is requested by returning `true`.
+fn run_command<S: Slideshow>(slideshows: &mut [S], command: &Command) -> bool {
+    match command {
+        Command::Next => slideshows.iter_mut().for_each(S::show_next),
+        Command::Previous => slideshows.iter_mut().for_each(S::show_previous),
+        Command::Random => slideshows.iter_mut().for_each(S::show_random),
+        Command::Pin(output) => slideshows
+            .iter_mut()
+            .filter(|s| s.shows_output(output))
+            .for_each(S::pin),
+        Command::Unpin(output) => slideshows
+            .iter_mut()
+            .filter(|s| s.shows_output(output))
+            .for_each(S::unpin),
+        Command::Reload => return true,
+    }
+
+    false
+}
+
+/// Saves the changed entry of an output to the config, and updates only the
+/// wallpaper of that output in place.
+///
+/// A previewed output keeps its preview, and is restored to this entry when the
+/// preview is cancelled. Returns `false` if every wallpaper must be recreated
+/// for the entry.
+fn update_output_entry<S: Slideshow>(
+    slideshows: &mut [S],
+    preview: &Preview,
+    config: &mut Config,
+    output: &str,
+    entry: Entry,
+) -> bool {
+    let Some(existing) = config.entry_mut(output) else {
+        return true;
+    };
+
+    existing.clone_from(&entry);
+
+    if preview.is(output) {
+        return true;
+    }
+
+    slideshows
+        .iter_mut()
+        .find(|s| s.entry().output == output)
+        .is_some_and(|s| s.set_entry(entry))
+}
+
+/// The output whose wallpaper shows an unsaved entry previewed by settings.
+#[derive(Debug, Default)]
+struct Preview(Option<String>);
+
+impl Preview {
+    /// Whether the wallpaper of an output shows a preview.
+    fn is(&self, output: &str) -> bool {
+        self.0.as_deref() == Some(output)
+    }
+
+    /// Shows an entry on the slideshow of its output without saving it, after
+    /// restoring the saved entry of another previewed output.
+    ///
+    /// Returns `false` without showing the entry if every wallpaper must be
+    /// recreated to restore the other output.
+    fn show<S: Slideshow>(&mut self, slideshows: &mut [S], config: &Config, entry: Entry) -> bool {
+        if self
+            .0
+            .as_ref()
+            .is_some_and(|output| *output != entry.output)
+            && !self.cancel(slideshows, config)
+        {
+            return false;
+        }
+
+        let output = entry.output.clone();
+
+        let Some(slideshow) = slideshows.iter_mut().find(|s| s.entry().output == output) else {
+            tracing::warn!(output, "no wallpaper to preview entry on");
+            return true;
+        };
+
+        if slideshow.set_entry(entry) {
+            self.0 = Some(output);
+        } else {
+            tracing::warn!(output, "entries moving layer surfaces can't be previewed");
+        }
+
+        true
+    }
+
+    /// Restores the saved entry of the previewed output, which is the latest one
+    /// from cosmic-config even if it changed during the preview.
+    ///
+    /// Returns `false` if every wallpaper must be recreated to restore it.
+    fn cancel<S: Slideshow>(&mut self, slideshows: &mut [S], config: &Config) -> bool {
+        let Some(output) = self.0.take() else {
+            return true;
+        };
+
+        let saved = if output == "all" {
+            Some(&config.default_background)
+        } else {
+            config.entry(&output)
+        };
+
+        let Some(saved) = saved.cloned() else {
+            return true;
+        };
+
+        slideshows
+            .iter_mut()
+            .find(|s| s.entry().output == output)
+            .is_some_and(|s| s.set_entry(saved))
+    }
+}
+
+/// Forgets the wallpaper saved for the connector of a newly connected output if
+/// it was saved for a different display, so that it isn't resumed on this one.
+fn forget_replaced_display(info: &OutputInfo) {
+    let Some(name) = info.name.as_deref() else {
+        return;
+    };
+
+    let Ok(state_helper) = State::state() else {
+        return;
+    };
+
+    let mut state = State::get_entry(&state_helper).unwrap_or_else(|(_, state)| state);
+    let display = output::model_key(info);
+
+    if state.display(name).is_some_and(|saved| saved != display) {
+        tracing::debug!(
+            name,
+            display = display.as_str(),
+            "forgetting wallpaper of replaced display"
+        );
+        state.remove_output(name);
+        if let Err(err) = state.write_entry(&state_helper) {
+            error!("{err}");
         }
     }
 }
@@ -359,10 +1205,23 @@ impl CompositorHandler for CosmicBg {
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _surface: &wl_surface::WlSurface,
-        _new_transform: wl_output::Transform,
+        surface: &wl_surface::WlSurface,
+        new_transform: wl_output::Transform,
     ) {
-        // TODO
+        for wallpaper in &mut self.wallpapers {
+            if let Some(layer) = wallpaper
+                .layers
+                .iter_mut()
+                .find(|layer| layer.layer.wl_surface() == surface)
+            {
+                if layer.transform != new_transform {
+                    layer.transform = new_transform;
+                    layer.needs_redraw = true;
+                    wallpaper.draw();
+                }
+                break;
+            }
+        }
     }
 
     fn surface_enter(
@@ -400,32 +1259,93 @@ impl OutputHandler for CosmicBg {
             return;
         };
 
-        if let Some(pos) = self
+        let output_infos = self.output_infos();
+        let has_layer = |w: &Wallpaper| w.layers.iter().any(|l| l.wl_output == wl_output);
+
+        let matched = self
             .wallpapers
             .iter()
-            .position(|w| match w.entry.output.as_str() {
-                "all" => !w.layers.iter().any(|l| l.wl_output == wl_output),
-                name => {
-                    Some(name) == output_info.name.as_deref()
-                        && !w.layers.iter().any(|l| l.wl_output == wl_output)
-                }
+            .enumerate()
+            .filter(|(_, w)| !has_layer(w))
+            .filter_map(|(pos, w)| {
+                output::matches(
+                    &w.entry.output,
+                    &output_info,
+                    &output_infos,
+                    self.config.primary_output.as_deref(),
+                )
+                .map(|matched| (matched, pos))
             })
-        {
-            let layer = self.new_layer(wl_output, output_info);
+            .max_by_key(|(matched, _)| *matched)
+            .map(|(_, pos)| pos);
+
+        let mirrored = mirrored_output(
+            &output_info,
+            &output_infos,
+            self.config.primary_output.as_deref(),
+        )
+        .and_then(|primary| {
+            self.wallpapers
+                .iter()
+                .position(|w| w.layers.iter().any(|l| l.output_info.name == primary.name))
+        });
+
+        let default = self
+            .wallpapers
+            .iter()
+            .position(|w| w.entry.output == "all" && !has_layer(w));
+
+        let position =
+            new_output_wallpaper(self.config.new_output_policy, matched, mirrored, default);
+
+        forget_replaced_display(&output_info);
+
+        if let Some(pos) = position {
+            let layer = self.new_layer(wl_output, output_info, &self.wallpapers[pos].entry);
             self.wallpapers[pos].layers.push(layer);
             if let Err(err) = self.wallpapers[pos].save_state() {
                 tracing::error!("{err}");
             }
         }
+
+        // The new output may be the primary one.
+        self.match_primary_colors();
     }
 
     fn update_output(
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _output: wl_output::WlOutput,
+        output: wl_output::WlOutput,
     ) {
-        // TODO
+        let Some(output_info) = self.output_state.info(&output) else {
+            return;
+        };
+
+        // A change of mode, such as of resolution, is answered by a configure of
+        // the new size for layers that the compositor sizes, whose draw scales
+        // the decoded source to that size. Layers sized by their anchor and
+        // margins need to request the new logical size themselves.
+        for wallpaper in &mut self.wallpapers {
+            let Some(layer) = wallpaper.layers.iter_mut().find(|l| l.wl_output == output) else {
+                continue;
+            };
+
+            let resized = layer.output_info.logical_size != output_info.logical_size;
+            layer.output_info = output_info;
+
+            if resized {
+                if let Some((width, height)) =
+                    requested_size(&wallpaper.entry, layer.output_info.logical_size)
+                {
+                    tracing::debug!(width, height, "output resized, requesting layer size");
+                    layer.layer.set_size(width, height);
+                    layer.layer.commit();
+                }
+            }
+
+            break;
+        }
     }
 
     fn output_destroyed(
@@ -441,22 +1361,17 @@ impl OutputHandler for CosmicBg {
 
         // state cleanup
         if let Ok(state_helper) = State::state() {
-            let mut state = State::get_entry(&state_helper).unwrap_or_default();
-            state
-                .wallpapers
-                .retain(|(o_name, _source)| Some(o_name) != output_info.name.as_ref());
+            let mut state = State::get_entry(&state_helper).unwrap_or_else(|(_, state)| state);
+            state.remove_output(output_info.name.as_deref().unwrap_or_default());
             if let Err(err) = state.write_entry(&state_helper) {
                 error!("{err}");
             }
         }
 
-        let Some(output_wallpaper) =
-            self.wallpapers
-                .iter_mut()
-                .find(|w| match w.entry.output.as_str() {
-                    "all" => true,
-                    name => Some(name) == output_info.name.as_deref(),
-                })
+        let Some(output_wallpaper) = self
+            .wallpapers
+            .iter_mut()
+            .find(|w| w.layers.iter().any(|l| l.wl_output == output))
         else {
             return;
         };
@@ -470,6 +1385,9 @@ impl OutputHandler for CosmicBg {
         };
 
         output_wallpaper.layers.remove(layer_position);
+
+        // Another output may have become the primary one.
+        self.match_primary_colors();
     }
 }
 
@@ -489,7 +1407,7 @@ impl LayerShellHandler for CosmicBg {
 
     fn configure(
         &mut self,
-        _conn: &Connection,
+        conn: &Connection,
         _qh: &QueueHandle<Self>,
         layer: &LayerSurface,
         configure: LayerSurfaceConfigure,
@@ -501,24 +1419,23 @@ impl LayerShellHandler for CosmicBg {
         for wallpaper in &mut self.wallpapers {
             let (w, h) = configure.new_size;
             if let Some(w_layer) = wallpaper.layers.iter_mut().find(|l| &l.layer == layer) {
+                if !valid_size(w, h) {
+                    tracing::warn!(w, h, "ignoring configure with invalid size");
+                    break;
+                }
+
                 w_layer.size = Some((w, h));
                 w_layer.needs_redraw = true;
 
-                if let Some(pool) = w_layer.pool.as_mut() {
-                    if let Err(why) = pool.resize(w as usize * h as usize * 4) {
-                        tracing::error!(?why, "failed to resize pool");
-                        continue;
-                    }
-                } else {
-                    match SlotPool::new(w as usize * h as usize * 4, &self.shm_state) {
-                        Ok(pool) => {
-                            w_layer.pool.replace(pool);
-                        }
+                if !w_layer.allocate_pool(&self.shm_state, self.config.max_render_edge) {
+                    continue;
+                }
 
-                        Err(why) => {
-                            tracing::error!(?why, "failed to create pool");
-                            continue;
-                        }
+                // The last wallpaper of the output is shown while its source is
+                // decoded, which the compositor needs to receive beforehand.
+                if !w_layer.drawn && warmstart::draw(w_layer, &self.qh) {
+                    if let Err(why) = conn.flush() {
+                        tracing::warn!(?why, "failed to flush warm start image");
                     }
                 }
 
@@ -566,6 +1483,7 @@ impl Dispatch<wp_fractional_scale_v1::WpFractionalScaleV1, Weak<wl_surface::WlSu
                             .find(|layer| layer.layer.wl_surface() == &surface)
                         {
                             layer.fractional_scale = Some(scale);
+                            layer.allocate_pool(&state.shm_state, state.config.max_render_edge);
                             wallpaper.draw();
                             break;
                         }
@@ -590,6 +1508,26 @@ fn init_logger() {
         .and_then(|level| level.parse::<tracing::Level>().ok())
         .unwrap_or(tracing::Level::INFO);
 
+    let log_file = std::env::var_os(logfile::ENV);
+    let (layers, log_file_error) = log_layers(log_level, log_file.as_deref().map(Path::new));
+
+    tracing_subscriber::registry().with(layers).init();
+
+    if let Some(why) = log_file_error {
+        tracing::error!(?why, "failed to open log file");
+    }
+}
+
+/// A layer that logs are written to.
+type LogLayer = Box<dyn tracing_subscriber::Layer<tracing_subscriber::Registry> + Send + Sync>;
+
+/// Layers that logs are written to, which are stderr and, if a `log_file` is
+/// given, size limited files rotated daily. The error of opening the log file
+/// is returned to be logged once the layers are installed.
+fn log_layers(
+    log_level: tracing::Level,
+    log_file: Option<&Path>,
+) -> (Vec<LogLayer>, Option<tracing_appender::rolling::InitError>) {
     let log_format = tracing_subscriber::fmt::format()
         .pretty()
         .without_time()
@@ -598,13 +1536,390 @@ fn init_logger() {
         .with_target(false)
         .with_thread_names(true);
 
-    let log_filter = tracing_subscriber::fmt::Layer::default()
+    let filter = move |metadata: &tracing::Metadata<'_>| {
+        metadata.level() == &tracing::Level::ERROR
+            || (metadata.target().starts_with("cosmic_bg") && metadata.level() <= &log_level)
+    };
+
+    let mut layers = vec![tracing_subscriber::fmt::Layer::default()
         .with_writer(std::io::stderr)
         .event_format(log_format)
-        .with_filter(tracing_subscriber::filter::filter_fn(move |metadata| {
-            metadata.level() == &tracing::Level::ERROR
-                || (metadata.target().starts_with("cosmic_bg") && metadata.level() <= &log_level)
-        }));
+        .with_filter(tracing_subscriber::filter::filter_fn(filter))
+        .boxed()];
+
+    let Some(log_file) = log_file else {
+        return (layers, None);
+    };
+
+    match logfile::appender(log_file) {
+        Ok(appender) => {
+            layers.push(
+                tracing_subscriber::fmt::Layer::default()
+                    .with_writer(appender)
+                    .with_ansi(false)
+                    .with_target(false)
+                    .with_filter(tracing_subscriber::filter::filter_fn(filter))
+                    .boxed(),
+            );
+            (layers, None)
+        }
+        Err(why) => (layers, Some(why)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(anchor: Vec<Edge>) -> Entry {
+        Entry {
+            anchor,
+            margin_top: 10,
+            margin_right: 20,
+            margin_bottom: 30,
+            margin_left: 40,
+            ..Entry::fallback()
+        }
+    }
+
+    #[test]
+    fn anchors_to_all_edges_by_default() {
+        assert_eq!(layer_anchor(&[]), Anchor::all());
+        assert_eq!(
+            layer_anchor(&[Edge::Top, Edge::Left]),
+            Anchor::TOP | Anchor::LEFT
+        );
+    }
+
+    #[test]
+    fn requested_size_subtracts_margins() {
+        let size = Some((1920, 1080));
+
+        assert_eq!(requested_size(&entry(Vec::new()), size), None);
+        assert_eq!(
+            requested_size(&entry(vec![Edge::Top]), size),
+            Some((1860, 1040))
+        );
+        // Axes anchored to both edges are sized by the compositor.
+        assert_eq!(
+            requested_size(&entry(vec![Edge::Left, Edge::Right]), size),
+            Some((0, 1040))
+        );
+    }
+
+    #[test]
+    fn degraded_size_halves_resolution() {
+        assert_eq!(degraded_size(3840, 2160), (1920, 1080));
+        assert_eq!(degraded_size(1366, 768), (683, 384));
+    }
+
+    #[test]
+    fn degraded_size_is_never_empty() {
+        assert_eq!(degraded_size(1, 1), (1, 1));
+        assert_eq!(degraded_size(3, 1), (1, 1));
+    }
+
+    #[test]
+    fn valid_size_rejects_empty_and_implausible() {
+        assert!(valid_size(1920, 1080));
+        assert!(valid_size(1, MAX_BUFFER_SIZE));
+        assert!(!valid_size(0, 1080));
+        assert!(!valid_size(1920, 0));
+        assert!(!valid_size(MAX_BUFFER_SIZE + 1, 1080));
+    }
+
+    #[test]
+    fn overlay_mode_selects_overlay_layer() {
+        assert_eq!(surface_layer(false), Layer::Background);
+        assert_eq!(surface_layer(true), Layer::Overlay);
+    }
+
+    #[test]
+    fn capped_size_limits_the_longest_edge() {
+        assert_eq!(capped_size(7680, 4320, 3840), (3840, 2160));
+        assert_eq!(capped_size(4320, 7680, 3840), (2160, 3840));
+        assert_eq!(capped_size(2560, 1440, 3840), (2560, 1440));
+        assert_eq!(capped_size(7680, 4320, 0), (7680, 4320));
+    }
+
+    #[test]
+    fn new_output_policy_chooses_the_wallpaper() {
+        let (matched, mirrored, default) = (Some(0), Some(1), Some(2));
+
+        // An entry for the output itself always wins.
+        for policy in [NewOutputPolicy::Default, NewOutputPolicy::MirrorPrimary] {
+            assert_eq!(
+                new_output_wallpaper(policy, matched, mirrored, default),
+                matched
+            );
+        }
+
+        assert_eq!(
+            new_output_wallpaper(NewOutputPolicy::Default, None, mirrored, default),
+            default
+        );
+        assert_eq!(
+            new_output_wallpaper(NewOutputPolicy::MirrorPrimary, None, mirrored, default),
+            mirrored
+        );
+        // Without another output to mirror, the default wallpaper is shown.
+        assert_eq!(
+            new_output_wallpaper(NewOutputPolicy::MirrorPrimary, None, None, default),
+            default
+        );
+
+        // The configured primary output is mirrored rather than the first one.
+        let outputs = ["eDP-1", "DP-1", "HDMI-A-1"].map(Named);
+        let mirrored_name = |new: usize, primary| {
+            mirrored_output(&outputs[new], &outputs, primary).map(|info| info.0)
+        };
+        assert_eq!(mirrored_name(2, None), Some("eDP-1"));
+        assert_eq!(mirrored_name(2, Some("DP-1")), Some("DP-1"));
+        assert_eq!(mirrored_name(2, Some("DP-2")), Some("eDP-1"));
+        // A new output that is itself the primary one has nothing to mirror.
+        assert_eq!(mirrored_name(1, Some("DP-1")), None);
+    }
+
+    /// A display known only by its connector name.
+    struct Named(&'static str);
+
+    impl output::Display for Named {
+        fn name(&self) -> Option<&str> {
+            Some(self.0)
+        }
+
+        fn description(&self) -> Option<&str> {
+            None
+        }
+
+        fn make(&self) -> &str {
+            ""
+        }
+
+        fn model(&self) -> &str {
+            ""
+        }
+    }
+
+    /// A slideshow which records the actions run on it.
+    struct Recorder {
+        entry: Entry,
+        actions: Vec<&'static str>,
+        /// Image shown from the source, which is cleared when the source changes.
+        current_image: Option<std::path::PathBuf>,
+    }
 
-    tracing_subscriber::registry().with(log_filter).init();
+    impl Recorder {
+        fn new(output: &str) -> Self {
+            Self {
+                entry: Entry::new(String::from(output), Source::solid([0.0; 3])),
+                actions: Vec::new(),
+                current_image: Some(["/usr/share/backgrounds/", output, ".jpg"].concat().into()),
+            }
+        }
+    }
+
+    impl Slideshow for Recorder {
+        fn entry(&self) -> &Entry {
+            &self.entry
+        }
+
+        /// Entries can't move the layers of recorders.
+        fn set_entry(&mut self, entry: Entry) -> bool {
+            if entry.anchor != self.entry.anchor {
+                return false;
+            }
+
+            if entry.source != self.entry.source {
+                self.current_image = None;
+            }
+
+            self.entry = entry;
+            true
+        }
+
+        fn shows_output(&self, output: &str) -> bool {
+            self.entry.output == output
+        }
+
+        fn show_next(&mut self) {
+            self.actions.push("next");
+        }
+
+        fn show_previous(&mut self) {
+            self.actions.push("previous");
+        }
+
+        fn show_random(&mut self) {
+            self.actions.push("random");
+        }
+
+        fn pin(&mut self) {
+            self.actions.push("pin");
+        }
+
+        fn unpin(&mut self) {
+            self.actions.push("unpin");
+        }
+    }
+
+    #[test]
+    fn commands_run_their_actions() {
+        let mut slideshows = ["DP-1", "HDMI-A-1"].map(Recorder::new);
+
+        for command in [Command::Next, Command::Previous, Command::Random] {
+            assert!(!run_command(&mut slideshows, &command));
+        }
+        assert!(!run_command(
+            &mut slideshows,
+            &Command::Pin(String::from("DP-1"))
+        ));
+        assert!(!run_command(
+            &mut slideshows,
+            &Command::Unpin(String::from("HDMI-A-1"))
+        ));
+        assert!(run_command(&mut slideshows, &Command::Reload));
+
+        assert_eq!(slideshows[0].actions, ["next", "previous", "random", "pin"]);
+        assert_eq!(
+            slideshows[1].actions,
+            ["next", "previous", "random", "unpin"]
+        );
+    }
+
+    #[test]
+    fn render_size_overrides_the_scaled_size() {
+        assert_eq!(scaled_size(1280, 720, 120, None), (1280, 720));
+        assert_eq!(scaled_size(1280, 720, 180, None), (1920, 1080));
+        assert_eq!(
+            scaled_size(1280, 720, 180, Some((3840, 2160))),
+            (3840, 2160)
+        );
+    }
+
+    #[test]
+    fn log_file_adds_a_layer() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let (layers, error) = log_layers(tracing::Level::INFO, None);
+        assert_eq!(layers.len(), 1);
+        assert!(error.is_none());
+
+        let path = dir.path().join("logs/cosmic-bg.log");
+        let (layers, error) = log_layers(tracing::Level::INFO, Some(&path));
+        assert_eq!(layers.len(), 2);
+        assert!(error.is_none());
+        assert!(dir.path().join("logs").is_dir());
+
+        // Logs still go to stderr if the file can't be opened.
+        let not_a_dir = dir.path().join("file");
+        std::fs::write(&not_a_dir, b"").unwrap();
+        let (layers, error) = log_layers(tracing::Level::INFO, Some(&not_a_dir.join("log")));
+        assert_eq!(layers.len(), 1);
+        assert!(error.is_some());
+    }
+
+    #[test]
+    fn previews_are_reverted_to_the_latest_saved_entry() {
+        let saved = |output: &str, color| Entry::new(String::from(output), Source::solid(color));
+        let mut config = Config::default();
+        config.backgrounds = vec![saved("DP-1", [0.1; 3]), saved("HDMI-A-1", [0.2; 3])];
+
+        let mut slideshows = ["DP-1", "HDMI-A-1"].map(Recorder::new);
+        let mut preview = Preview::default();
+
+        assert!(preview.show(&mut slideshows, &config, saved("DP-1", [1.0; 3])));
+        assert!(preview.is("DP-1"));
+        assert_eq!(slideshows[0].entry.source, Source::solid([1.0; 3]));
+
+        // The entry saved during the preview is restored, rather than the one
+        // saved when it started.
+        config.backgrounds[0] = saved("DP-1", [0.3; 3]);
+        assert!(preview.cancel(&mut slideshows, &config));
+        assert!(!preview.is("DP-1"));
+        assert_eq!(slideshows[0].entry.source, Source::solid([0.3; 3]));
+
+        // Previewing another output restores the previous one.
+        assert!(preview.show(&mut slideshows, &config, saved("DP-1", [1.0; 3])));
+        assert!(preview.show(&mut slideshows, &config, saved("HDMI-A-1", [1.0; 3])));
+        assert!(preview.is("HDMI-A-1"));
+        assert_eq!(slideshows[0].entry.source, Source::solid([0.3; 3]));
+        assert_eq!(slideshows[1].entry.source, Source::solid([1.0; 3]));
+
+        // Entries that move layers aren't previewed.
+        let moved = Entry {
+            anchor: vec![Edge::Top],
+            ..saved("DP-1", [1.0; 3])
+        };
+        assert!(preview.show(&mut slideshows, &config, moved));
+        assert_eq!(preview.0, None);
+        assert_eq!(slideshows[0].entry.source, Source::solid([0.3; 3]));
+
+        // Restoring a saved entry that moves layers recreates the wallpapers.
+        assert!(preview.show(&mut slideshows, &config, saved("DP-1", [1.0; 3])));
+        config.backgrounds[0].anchor = vec![Edge::Bottom];
+        assert!(!preview.cancel(&mut slideshows, &config));
+    }
+
+    #[test]
+    fn pools_are_sized_for_the_drawn_buffer() {
+        // A 1.5 scale is drawn at the fractional size, and capped to the edge limit.
+        assert_eq!(
+            draw_size((1920, 1080), 180, None, 8192, false),
+            (2880, 1620)
+        );
+        assert_eq!(
+            draw_size((1920, 1080), 180, None, 2048, false),
+            (2048, 1152)
+        );
+
+        // Degraded layers are halved after the cap, so that they fit the pool.
+        assert_eq!(draw_size((1920, 1080), 180, None, 2048, true), (1024, 576));
+        assert_eq!(
+            draw_size((1920, 1080), 120, Some((1280, 720)), 8192, false),
+            (1280, 720)
+        );
+    }
+
+    #[test]
+    fn output_entries_update_only_their_wallpaper() {
+        let mut slideshows = ["DP-1", "HDMI-1"].map(Recorder::new);
+        let mut config = Config {
+            same_on_all: false,
+            backgrounds: slideshows.iter().map(|s| s.entry.clone()).collect(),
+            ..Config::default()
+        };
+        let hdmi_image = slideshows[1].current_image.clone();
+
+        let entry = Entry::new(String::from("DP-1"), Source::solid([1.0; 3]));
+        assert!(update_output_entry(
+            &mut slideshows,
+            &Preview::default(),
+            &mut config,
+            "DP-1",
+            entry.clone()
+        ));
+
+        assert_eq!(config.entry("DP-1"), Some(&entry));
+        assert_eq!(slideshows[0].entry, entry);
+        assert!(slideshows[0].current_image.is_none());
+
+        // The other output keeps its entry and the image it shows.
+        assert_eq!(config.entry("HDMI-1"), Some(&slideshows[1].entry));
+        assert_eq!(slideshows[1].current_image, hdmi_image);
+        assert!(hdmi_image.is_some());
+
+        // A previewed output only has its saved entry updated.
+        let preview = Preview(Some(String::from("HDMI-1")));
+        let saved = Entry::new(String::from("HDMI-1"), Source::solid([0.5; 3]));
+        assert!(update_output_entry(
+            &mut slideshows,
+            &preview,
+            &mut config,
+            "HDMI-1",
+            saved.clone()
+        ));
+        assert_eq!(config.entry("HDMI-1"), Some(&saved));
+        assert_eq!(slideshows[1].current_image, hdmi_image);
+    }
 }
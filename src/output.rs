@@ -0,0 +1,243 @@
+// SPDX-License-Identifier: MPL-2.0-only
+
+//! Matching of configured output keys against connected outputs.
+//!
+//! An entry's output is usually a connector name such as `DP-1`, but connector
+//! names can change across reboots and docks. An entry may instead identify its
+//! display by the description advertised by the compositor with `desc:<description>`,
+//! or by its make and model with `edid:<make> <model>`.
+//!
+//! The role keys `primary` and `secondary` match the primary output, which is
+//! the configured primary output or else the first connected one, and every
+//! other output respectively.
+
+use sctk::output::OutputInfo;
+
+pub const DESCRIPTION_PREFIX: &str = "desc:";
+pub const EDID_PREFIX: &str = "edid:";
+pub const PRIMARY: &str = "primary";
+pub const SECONDARY: &str = "secondary";
+
+/// The identity of a connected display that output keys are matched against.
+pub trait Display {
+    fn name(&self) -> Option<&str>;
+    fn description(&self) -> Option<&str>;
+    fn make(&self) -> &str;
+    fn model(&self) -> &str;
+}
+
+impl Display for OutputInfo {
+    fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    fn make(&self) -> &str {
+        &self.make
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+}
+
+/// How an entry's output key matched an output, from least to most specific.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Match {
+    /// Matched the role of the output as primary or secondary.
+    Role,
+    /// Matched the connector name.
+    Name,
+    /// Matched the make and model of the display.
+    Model,
+    /// Matched the description of the display.
+    Description,
+}
+
+/// Checks if the output `key` of an entry refers to the output of `info`.
+///
+/// `outputs` lists every connected output. A description or make and model match
+/// is ignored when several connected displays share the same description or make
+/// and model, which leaves these to be matched by their connector names instead. `primary` is the connector
+/// name of the configured primary output.
+#[must_use]
+pub fn matches<D: Display>(
+    key: &str,
+    info: &D,
+    outputs: &[D],
+    primary: Option<&str>,
+) -> Option<Match> {
+    if key == PRIMARY || key == SECONDARY {
+        return ((key == PRIMARY) == is_primary(info, outputs, primary)).then_some(Match::Role);
+    }
+
+    if let Some(description) = key.strip_prefix(DESCRIPTION_PREFIX) {
+        if info.description() != Some(description) {
+            return None;
+        }
+
+        let identical = outputs
+            .iter()
+            .filter(|other| other.description() == Some(description))
+            .count();
+
+        return (identical <= 1).then_some(Match::Description);
+    }
+
+    if let Some(model) = key.strip_prefix(EDID_PREFIX) {
+        if model_key(info) != model {
+            return None;
+        }
+
+        let identical = outputs
+            .iter()
+            .filter(|other| other.make() == info.make() && other.model() == info.model())
+            .count();
+
+        return (identical <= 1).then_some(Match::Model);
+    }
+
+    (info.name() == Some(key)).then_some(Match::Name)
+}
+
+/// Whether an output is the primary one, which is the output named `primary` if
+/// it is connected, or else the first of the connected `outputs`.
+#[must_use]
+pub fn is_primary<D: Display>(info: &D, outputs: &[D], primary: Option<&str>) -> bool {
+    let primary = primary
+        .filter(|name| outputs.iter().any(|o| o.name() == Some(name)))
+        .or_else(|| outputs.first()?.name());
+
+    primary.is_some() && info.name() == primary
+}
+
+/// The make and model of a display in the format used by `edid:` keys.
+#[must_use]
+pub fn model_key<D: Display>(info: &D) -> String {
+    format!("{} {}", info.make(), info.model())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestDisplay {
+        name: &'static str,
+        description: String,
+        make: &'static str,
+        model: &'static str,
+    }
+
+    impl Display for TestDisplay {
+        fn name(&self) -> Option<&str> {
+            Some(self.name)
+        }
+
+        fn description(&self) -> Option<&str> {
+            Some(&self.description)
+        }
+
+        fn make(&self) -> &str {
+            self.make
+        }
+
+        fn model(&self) -> &str {
+            self.model
+        }
+    }
+
+    fn display(name: &'static str, model: &'static str) -> TestDisplay {
+        TestDisplay {
+            name,
+            description: format!("Dell Inc. {model}"),
+            make: "Dell Inc.",
+            model,
+        }
+    }
+
+    #[test]
+    fn matches_connector_description_and_model() {
+        let outputs = [display("DP-1", "U2720Q"), display("HDMI-A-1", "P2419H")];
+
+        assert_eq!(
+            matches("DP-1", &outputs[0], &outputs, None),
+            Some(Match::Name)
+        );
+        assert_eq!(matches("DP-2", &outputs[0], &outputs, None), None);
+        assert_eq!(
+            matches("desc:Dell Inc. U2720Q", &outputs[0], &outputs, None),
+            Some(Match::Description)
+        );
+        assert_eq!(
+            matches("edid:Dell Inc. U2720Q", &outputs[0], &outputs, None),
+            Some(Match::Model)
+        );
+        assert_eq!(
+            matches("edid:Dell Inc. U2720Q", &outputs[1], &outputs, None),
+            None
+        );
+    }
+
+    #[test]
+    fn identical_models_are_not_matched() {
+        let outputs = [display("DP-1", "U2720Q"), display("DP-2", "U2720Q")];
+
+        assert_eq!(
+            matches("edid:Dell Inc. U2720Q", &outputs[0], &outputs, None),
+            None
+        );
+        assert_eq!(
+            matches("DP-2", &outputs[1], &outputs, None),
+            Some(Match::Name)
+        );
+    }
+
+    #[test]
+    fn shared_descriptions_are_not_matched() {
+        let outputs = [display("DP-1", "U2720Q"), display("DP-2", "U2720Q")];
+
+        assert_eq!(
+            matches("desc:Dell Inc. U2720Q", &outputs[0], &outputs, None),
+            None
+        );
+        assert_eq!(
+            matches("desc:Dell Inc. U2720Q", &outputs[1], &outputs, None),
+            None
+        );
+    }
+
+    #[test]
+    fn roles_follow_the_primary_output() {
+        let outputs = [display("eDP-1", "A"), display("DP-1", "B")];
+
+        // Without a configured primary output, the first one is primary.
+        assert_eq!(
+            matches(PRIMARY, &outputs[0], &outputs, None),
+            Some(Match::Role)
+        );
+        assert_eq!(
+            matches(SECONDARY, &outputs[1], &outputs, None),
+            Some(Match::Role)
+        );
+        assert_eq!(
+            matches(PRIMARY, &outputs[0], &outputs, Some("HDMI-A-1")),
+            Some(Match::Role)
+        );
+
+        assert_eq!(
+            matches(PRIMARY, &outputs[1], &outputs, Some("DP-1")),
+            Some(Match::Role)
+        );
+        assert_eq!(
+            matches(SECONDARY, &outputs[0], &outputs, Some("DP-1")),
+            Some(Match::Role)
+        );
+        assert_eq!(matches(PRIMARY, &outputs[0], &outputs, Some("DP-1")), None);
+
+        // Entries for a connector take precedence over those for its role.
+        assert!(Match::Name > Match::Role);
+    }
+}
@@ -0,0 +1,242 @@
+// SPDX-License-Identifier: MPL-2.0-only
+
+//! On-screen debugging overlay for render statistics.
+//!
+//! Enabled by launching with `COSMIC_BG_DEBUG_OVERLAY=1`. When unset, the
+//! environment is checked once and every call becomes a no-op.
+
+use std::{
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+/// Size in canvas pixels of a single font pixel.
+const PIXEL_SIZE: usize = 3;
+/// Width and height in font pixels of a glyph, including spacing.
+pub const GLYPH_WIDTH: usize = 4;
+pub const GLYPH_HEIGHT: usize = 6;
+/// Distance in canvas pixels from the top left corner of the surface.
+const MARGIN: usize = 16;
+
+#[must_use]
+pub fn enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+
+    *ENABLED.get_or_init(|| {
+        std::env::var("COSMIC_BG_DEBUG_OVERLAY").map_or(false, |value| value == "1")
+    })
+}
+
+/// Timing statistics of a wallpaper's draws.
+#[derive(Debug, Default)]
+pub struct Stats {
+    draws: u64,
+    last_draw: Option<Instant>,
+    draw_time: Duration,
+    fps: f64,
+    failed_draws: u64,
+    decode_errors: u64,
+}
+
+impl Stats {
+    /// Record a completed draw that took `elapsed`.
+    pub fn record(&mut self, elapsed: Duration) {
+        let now = Instant::now();
+
+        if let Some(last) = self.last_draw {
+            let interval = now.duration_since(last).as_secs_f64();
+            if interval > 0.0 {
+                self.fps = 1.0 / interval;
+            }
+        }
+
+        self.draws += 1;
+        self.last_draw = Some(now);
+        self.draw_time = elapsed;
+    }
+
+    /// Record a draw whose buffer could not be submitted.
+    pub fn record_failure(&mut self) {
+        self.failed_draws += 1;
+    }
+
+    /// Record an image that could not be decoded for a draw.
+    pub fn record_decode_error(&mut self) {
+        self.decode_errors += 1;
+    }
+
+    #[must_use]
+    pub fn draws(&self) -> u64 {
+        self.draws
+    }
+
+    #[must_use]
+    pub fn failed_draws(&self) -> u64 {
+        self.failed_draws
+    }
+
+    #[must_use]
+    pub fn decode_errors(&self) -> u64 {
+        self.decode_errors
+    }
+
+    #[must_use]
+    pub fn text(&self, width: u32, height: u32, format: &str) -> String {
+        format!(
+            "FPS {:.1}\nDRAWS {}\nDRAW {:.1}MS\n{width}X{height} {format}",
+            self.fps,
+            self.draws,
+            self.draw_time.as_secs_f64() * 1000.0,
+        )
+    }
+}
+
+/// Rasterizes `text` onto the top left corner of a 32-bit XRGB/ARGB canvas.
+pub fn draw(canvas: &mut [u8], width: usize, height: usize, text: &str) {
+    let lines = text.lines().collect::<Vec<_>>();
+    let columns = lines.iter().map(|line| line.len()).max().unwrap_or(0);
+
+    let box_width = (columns * GLYPH_WIDTH + 1) * PIXEL_SIZE;
+    let box_height = (lines.len() * GLYPH_HEIGHT + 1) * PIXEL_SIZE;
+
+    fill(
+        canvas,
+        width,
+        height,
+        (MARGIN, MARGIN),
+        (box_width, box_height),
+        0xFF00_0000,
+    );
+
+    for (row, line) in lines.iter().enumerate() {
+        for (column, c) in line.chars().enumerate() {
+            let Some(glyph) = glyph(c) else {
+                continue;
+            };
+
+            let origin_x = MARGIN + (column * GLYPH_WIDTH + 1) * PIXEL_SIZE;
+            let origin_y = MARGIN + (row * GLYPH_HEIGHT + 1) * PIXEL_SIZE;
+
+            for (y, bits) in glyph.iter().enumerate() {
+                for x in 0..3 {
+                    if bits & (0b100 >> x) != 0 {
+                        fill(
+                            canvas,
+                            width,
+                            height,
+                            (origin_x + x * PIXEL_SIZE, origin_y + y * PIXEL_SIZE),
+                            (PIXEL_SIZE, PIXEL_SIZE),
+                            0xFFFF_FFFF,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn fill(
+    canvas: &mut [u8],
+    width: usize,
+    height: usize,
+    (x, y): (usize, usize),
+    (w, h): (usize, usize),
+    pixel: u32,
+) {
+    let bytes = pixel.to_le_bytes();
+
+    for row in y..(y + h).min(height) {
+        for column in x..(x + w).min(width) {
+            let indice = (row * width + column) * 4;
+            if let Some(dst) = canvas.get_mut(indice..indice + 4) {
+                dst.copy_from_slice(&bytes);
+            }
+        }
+    }
+}
+
+/// A 3x5 bitmap glyph, one row per byte with the leftmost pixel in bit 2.
+#[must_use]
+pub fn glyph(c: char) -> Option<[u8; 5]> {
+    let glyph = match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        _ => return None,
+    };
+
+    Some(glyph)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draw_rasterizes_text() {
+        let (width, height) = (128, 64);
+        let mut canvas = vec![0; width * height * 4];
+
+        draw(&mut canvas, width, height, "FPS 60");
+
+        let white = 0xFFFF_FFFF_u32.to_le_bytes();
+        let lit = canvas
+            .chunks_exact(4)
+            .filter(|pixel| *pixel == white)
+            .count();
+        assert!(lit > 0);
+    }
+
+    #[test]
+    fn draw_clips_to_canvas() {
+        let (width, height) = (8, 8);
+        let mut canvas = vec![0; width * height * 4];
+
+        draw(&mut canvas, width, height, "A LONG LINE\nOF TEXT");
+
+        assert_eq!(canvas.len(), width * height * 4);
+    }
+
+    #[test]
+    fn glyphs_ignore_case() {
+        assert_eq!(glyph('a'), glyph('A'));
+        assert!(glyph('~').is_none());
+    }
+}
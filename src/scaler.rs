@@ -2,59 +2,157 @@
 
 //! Background scaling methods such as fit, stretch, and zoom.
 
+use cosmic_bg_config::FilterMethod;
 use image::imageops::FilterType;
-use image::{DynamicImage, Pixel};
+use image::{ColorType, DynamicImage, Pixel};
 
+/// Fit the image within the layer, placed by `gravity` from `0.0` (left or top)
+/// to `1.0` (right or bottom) on each axis, and fill the rest with a color.
 pub fn fit(
     img: &image::DynamicImage,
     color: &[f32; 3],
     layer_width: u32,
     layer_height: u32,
+    [horizontal, vertical]: [f32; 2],
+    filter: FilterMethod,
 ) -> image::DynamicImage {
-    // TODO: convert color to the same format as the input image.
+    // The fill color is linear, while the pixels of images are sRGB encoded.
+    let color = crate::colored::srgb(*color);
     let mut filled_image =
-        image::ImageBuffer::from_pixel(layer_width, layer_height, *image::Rgb::from_slice(color));
+        image::ImageBuffer::from_pixel(layer_width, layer_height, *image::Rgb::from_slice(&color));
 
-    let (w, h) = (img.width(), img.height());
+    let (new_width, new_height) =
+        fit_size((img.width(), img.height()), (layer_width, layer_height));
 
-    let ratio = (layer_width as f64 / w as f64).min(layer_height as f64 / h as f64);
+    let resized_image = resize(img, new_width, new_height, filter);
 
-    let (new_width, new_height) = (
-        (w as f64 * ratio).round() as u32,
-        (h as f64 * ratio).round() as u32,
-    );
-
-    let resized_image = resize(img, new_width, new_height);
+    let offset = |space: u32, gravity: f32| (space as f32 * gravity.clamp(0.0, 1.0)).round() as i64;
 
     image::imageops::replace(
         &mut filled_image,
         &resized_image.to_rgb32f(),
-        ((layer_width - new_width) / 2).into(),
-        ((layer_height - new_height) / 2).into(),
+        offset(layer_width - new_width, horizontal),
+        offset(layer_height - new_height, vertical),
     );
 
     DynamicImage::from(filled_image)
 }
 
+/// Size of an image fitted within a layer, keeping its aspect ratio.
+fn fit_size((w, h): (u32, u32), (layer_width, layer_height): (u32, u32)) -> (u32, u32) {
+    let ratio = (layer_width as f64 / w as f64).min(layer_height as f64 / h as f64);
+
+    (
+        (w as f64 * ratio).round() as u32,
+        (h as f64 * ratio).round() as u32,
+    )
+}
+
 pub fn stretch(
     img: &image::DynamicImage,
     layer_width: u32,
     layer_height: u32,
+    filter: FilterMethod,
 ) -> image::DynamicImage {
-    resize(img, layer_width, layer_height)
+    resize(img, layer_width, layer_height, filter)
 }
 
-pub fn zoom(img: &image::DynamicImage, layer_width: u32, layer_height: u32) -> image::DynamicImage {
+pub fn zoom(
+    img: &image::DynamicImage,
+    layer_width: u32,
+    layer_height: u32,
+    filter: FilterMethod,
+) -> image::DynamicImage {
+    zoom_offset(img, layer_width, layer_height, 0.0, (0.0, 0.0), filter)
+}
+
+/// Zoom the image, enlarged by `strength`, and shift the crop by `offset`.
+///
+/// The offset ranges from `-1.0` to `1.0` on each axis, where the extremes move
+/// the crop flush against an edge of the enlarged image. The crop never exceeds
+/// the bounds of the image.
+pub fn zoom_offset(
+    img: &image::DynamicImage,
+    layer_width: u32,
+    layer_height: u32,
+    strength: f32,
+    offset: (f32, f32),
+    filter: FilterMethod,
+) -> image::DynamicImage {
+    let new_image = zoom_enlarged(img, layer_width, layer_height, strength, filter);
+    let (x, y) = zoom_crop(
+        (new_image.width(), new_image.height()),
+        (layer_width, layer_height),
+        offset,
+    );
+
+    new_image.crop_imm(x, y, layer_width, layer_height)
+}
+
+/// Zoom the image to cover the layer, enlarged by `strength`, without cropping
+/// it to the layer.
+///
+/// Wallpapers that follow the pointer are rendered once at this size, and the
+/// viewport shows the part of it at [`zoom_crop`].
+pub fn zoom_enlarged(
+    img: &image::DynamicImage,
+    layer_width: u32,
+    layer_height: u32,
+    strength: f32,
+    filter: FilterMethod,
+) -> image::DynamicImage {
     let (w, h) = (img.width(), img.height());
 
-    let ratio = (layer_width as f64 / w as f64).max(layer_height as f64 / h as f64);
+    let ratio = (layer_width as f64 / w as f64).max(layer_height as f64 / h as f64)
+        * (1.0 + f64::from(strength.clamp(0.0, 1.0)));
 
     let (new_width, new_height) = (
-        (w as f64 * ratio).round() as u32,
-        (h as f64 * ratio).round() as u32,
+        ((w as f64 * ratio).round() as u32).max(layer_width),
+        ((h as f64 * ratio).round() as u32).max(layer_height),
     );
 
-    let mut new_image = resize(img, new_width, new_height);
+    resize(img, new_width, new_height, filter)
+}
+
+/// Position of the layer sized crop of an enlarged zoom at `offset`.
+#[must_use]
+pub fn zoom_crop(
+    (enlarged_width, enlarged_height): (u32, u32),
+    (layer_width, layer_height): (u32, u32),
+    (dx, dy): (f32, f32),
+) -> (u32, u32) {
+    let crop_offset = |overflow: u32, shift: f32| {
+        let center = f64::from(overflow) / 2.0;
+        (center + center * f64::from(shift.clamp(-1.0, 1.0)))
+            .round()
+            .clamp(0.0, f64::from(overflow)) as u32
+    };
+
+    (
+        crop_offset(enlarged_width.saturating_sub(layer_width), dx),
+        crop_offset(enlarged_height.saturating_sub(layer_height), dy),
+    )
+}
+
+/// Stretch the image non-uniformly towards the aspect ratio of the layer by up
+/// to `max_stretch` times, then zoom and center-crop what remains to fill it.
+///
+/// A `max_stretch` of `1.0` is equivalent to [`zoom`], while an unlimited one is
+/// equivalent to [`stretch`].
+pub fn smart_fill(
+    img: &image::DynamicImage,
+    layer_width: u32,
+    layer_height: u32,
+    max_stretch: f32,
+    filter: FilterMethod,
+) -> image::DynamicImage {
+    let (new_width, new_height) = smart_fill_size(
+        (img.width(), img.height()),
+        (layer_width, layer_height),
+        max_stretch,
+    );
+
+    let mut new_image = resize(img, new_width, new_height, filter);
 
     image::imageops::crop(
         &mut new_image,
@@ -67,19 +165,405 @@ pub fn zoom(img: &image::DynamicImage, layer_width: u32, layer_height: u32) -> i
     .into()
 }
 
-fn resize(img: &image::DynamicImage, new_width: u32, new_height: u32) -> image::DynamicImage {
+/// Factor by which the background of [`smart_blur`] is downscaled before it is
+/// blurred, which makes a heavy blur cheap.
+const BLUR_DOWNSCALE: u32 = 16;
+/// Standard deviation of the blur of the downscaled background.
+const BLUR_SIGMA: f32 = 2.0;
+/// Brightness of the blurred background, which keeps it from drawing attention
+/// away from the image.
+const BLUR_BRIGHTNESS: f32 = 0.7;
+
+/// Fit the image over a blurred and dimmed zoom of itself, such as a portrait
+/// photo on a landscape output, so that there are no bars beside it.
+///
+/// The blur is only used if the aspect ratios of the image and the layer differ
+/// by more than `threshold`. Otherwise the image is zoomed, as little of it
+/// would be cropped.
+pub fn smart_blur(
+    img: &image::DynamicImage,
+    layer_width: u32,
+    layer_height: u32,
+    threshold: f32,
+    filter: FilterMethod,
+) -> image::DynamicImage {
+    if !aspect_mismatched(
+        (img.width(), img.height()),
+        (layer_width, layer_height),
+        threshold,
+    ) {
+        return zoom(img, layer_width, layer_height, filter);
+    }
+
+    let background = zoom(
+        img,
+        (layer_width / BLUR_DOWNSCALE).max(1),
+        (layer_height / BLUR_DOWNSCALE).max(1),
+        FilterMethod::Linear,
+    );
+    let background = crate::effects::darken(&background.blur(BLUR_SIGMA), BLUR_BRIGHTNESS);
+    let mut background =
+        resize(&background, layer_width, layer_height, FilterMethod::Linear).to_rgba8();
+
+    let (new_width, new_height) =
+        fit_size((img.width(), img.height()), (layer_width, layer_height));
+    let fitted = resize(img, new_width, new_height, filter);
+
+    image::imageops::overlay(
+        &mut background,
+        &fitted.to_rgba8(),
+        ((layer_width - new_width) / 2).into(),
+        ((layer_height - new_height) / 2).into(),
+    );
+
+    DynamicImage::ImageRgba8(background)
+}
+
+/// Whether the aspect ratios of an image and a layer differ by more than
+/// `threshold`, as a fraction of the narrower one.
+fn aspect_mismatched(
+    (w, h): (u32, u32),
+    (layer_width, layer_height): (u32, u32),
+    threshold: f32,
+) -> bool {
+    if w == 0 || h == 0 || layer_width == 0 || layer_height == 0 {
+        return false;
+    }
+
+    let aspect = w as f64 / h as f64;
+    let layer_aspect = layer_width as f64 / layer_height as f64;
+
+    aspect.max(layer_aspect) / aspect.min(layer_aspect) - 1.0 > f64::from(threshold.max(0.0))
+}
+
+/// Size to resize an image to before cropping it to the layer for [`smart_fill`].
+fn smart_fill_size(
+    (w, h): (u32, u32),
+    (layer_width, layer_height): (u32, u32),
+    max_stretch: f32,
+) -> (u32, u32) {
+    let max_stretch = f64::from(max_stretch.max(1.0));
+
+    // Stretch the axis which is too short for the aspect ratio of the layer.
+    let mismatch = (layer_width as f64 / layer_height as f64) / (w as f64 / h as f64);
+    let (stretched_w, stretched_h) = if mismatch > 1.0 {
+        (w as f64 * mismatch.min(max_stretch), h as f64)
+    } else {
+        (w as f64, h as f64 * (1.0 / mismatch).min(max_stretch))
+    };
+
+    let ratio = (layer_width as f64 / stretched_w).max(layer_height as f64 / stretched_h);
+
+    (
+        ((stretched_w * ratio).round() as u32).max(layer_width),
+        ((stretched_h * ratio).round() as u32).max(layer_height),
+    )
+}
+
+fn resize(
+    img: &image::DynamicImage,
+    new_width: u32,
+    new_height: u32,
+    filter: FilterMethod,
+) -> image::DynamicImage {
+    let filter = match filter {
+        FilterMethod::Auto => auto_filter((img.width(), img.height()), (new_width, new_height)),
+        filter => filter,
+    };
+
+    // Images of other pixel formats are converted once, rather than taking the
+    // slower fallback of the image crate.
+    let converted;
+    let img = if fast_resizable(img.color()) {
+        img
+    } else {
+        tracing::debug!(color = ?img.color(), "converting image to RGBA8 for resizing");
+        converted = DynamicImage::ImageRgba8(img.to_rgba8());
+        &converted
+    };
+
     let mut resizer = fast_image_resize::Resizer::new();
     let options = fast_image_resize::ResizeOptions {
-        algorithm: fast_image_resize::ResizeAlg::Convolution(
-            fast_image_resize::FilterType::Lanczos3,
-        ),
+        algorithm: algorithm(filter),
         ..Default::default()
     };
     let mut new_image = image::DynamicImage::new(new_width, new_height, img.color());
     if let Err(err) = resizer.resize(img, &mut new_image, &options) {
         tracing::warn!(?err, "Failed to use `fast_image_resize`. Falling back.");
         new_image =
-            image::imageops::resize(img, new_width, new_height, FilterType::Lanczos3).into();
+            image::imageops::resize(img, new_width, new_height, FilterType::from(filter)).into();
     }
     new_image
 }
+
+/// The algorithm of `fast_image_resize` for a filter.
+fn algorithm(filter: FilterMethod) -> fast_image_resize::ResizeAlg {
+    match filter {
+        FilterMethod::Nearest => fast_image_resize::ResizeAlg::Nearest,
+        FilterMethod::Linear => {
+            fast_image_resize::ResizeAlg::Convolution(fast_image_resize::FilterType::Bilinear)
+        }
+        FilterMethod::Lanczos(window) => {
+            if window != 3 {
+                tracing::warn!(window, "unsupported lanczos window, using 3");
+            }
+            fast_image_resize::ResizeAlg::Convolution(fast_image_resize::FilterType::Lanczos3)
+        }
+        FilterMethod::Auto => {
+            fast_image_resize::ResizeAlg::Convolution(fast_image_resize::FilterType::Lanczos3)
+        }
+        FilterMethod::Mitchell => {
+            fast_image_resize::ResizeAlg::Convolution(fast_image_resize::FilterType::Mitchell)
+        }
+        FilterMethod::CatmullRom => {
+            fast_image_resize::ResizeAlg::Convolution(fast_image_resize::FilterType::CatmullRom)
+        }
+    }
+}
+
+/// Whether `fast_image_resize` supports the pixels of images of a color type.
+fn fast_resizable(color: ColorType) -> bool {
+    matches!(
+        color,
+        ColorType::L8
+            | ColorType::La8
+            | ColorType::Rgb8
+            | ColorType::Rgba8
+            | ColorType::L16
+            | ColorType::La16
+            | ColorType::Rgb16
+            | ColorType::Rgba16
+            | ColorType::Rgb32F
+            | ColorType::Rgba32F
+    )
+}
+
+/// Largest width and height of images, such as pixel art and icons, whose
+/// integer upscales keep their sharp edges with nearest neighbor filtering.
+const NEAREST_MAX_SIZE: u32 = 256;
+
+/// Smallest integer upscale of larger images that is filtered by nearest neighbor.
+const NEAREST_MIN_FACTOR: u32 = 4;
+
+/// Chooses a filter for resizing an image from `from` to `to` pixels.
+///
+/// Exact integer upscales of small images, as of pixel art, and large integer
+/// upscales of any image keep their sharp edges with nearest neighbor filtering,
+/// while a photo at twice its size would look blocky. Other significant upscales
+/// benefit from the detail of Lanczos, while linear filtering is
+/// indistinguishable for mild scaling and downscaling.
+fn auto_filter((w, h): (u32, u32), (new_w, new_h): (u32, u32)) -> FilterMethod {
+    if w == 0 || h == 0 {
+        return FilterMethod::Linear;
+    }
+
+    let ratio = (f64::from(new_w) / f64::from(w)).max(f64::from(new_h) / f64::from(h));
+
+    let integer_multiple = new_w % w == 0 && new_h % h == 0 && new_w / w == new_h / h;
+    let factor = new_w / w;
+    let small = w <= NEAREST_MAX_SIZE && h <= NEAREST_MAX_SIZE;
+
+    if integer_multiple && factor >= 2 && (small || factor >= NEAREST_MIN_FACTOR) {
+        FilterMethod::Nearest
+    } else if ratio >= 1.5 {
+        FilterMethod::Lanczos(3)
+    } else {
+        FilterMethod::Linear
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smart_fill_stretches_up_to_the_limit() {
+        let square = (1000, 1000);
+
+        // Without stretching, the image is zoomed to cover the layer.
+        assert_eq!(smart_fill_size(square, (2000, 1000), 1.0), (2000, 2000));
+        assert_eq!(smart_fill_size(square, (2000, 1000), 1.5), (2000, 1333));
+        assert_eq!(smart_fill_size(square, (2000, 1000), 2.0), (2000, 1000));
+        assert_eq!(smart_fill_size(square, (2000, 1000), 4.0), (2000, 1000));
+        assert_eq!(smart_fill_size(square, (1000, 2000), 1.5), (1333, 2000));
+
+        assert_eq!(
+            smart_fill_size((1920, 1080), (3840, 2160), 1.5),
+            (3840, 2160)
+        );
+    }
+
+    #[test]
+    fn auto_filter_by_scale_ratio() {
+        // Integer upscales of photos are smoothed, unlike those of pixel art.
+        assert_eq!(
+            auto_filter((1920, 1080), (3840, 2160)),
+            FilterMethod::Lanczos(3)
+        );
+        assert_eq!(auto_filter((128, 128), (256, 256)), FilterMethod::Nearest);
+        assert_eq!(auto_filter((64, 32), (1024, 512)), FilterMethod::Nearest);
+        assert_eq!(auto_filter((960, 540), (3840, 2160)), FilterMethod::Nearest);
+        assert_eq!(
+            auto_filter((960, 540), (2880, 1620)),
+            FilterMethod::Lanczos(3)
+        );
+
+        assert_eq!(
+            auto_filter((1280, 720), (1920, 1080)),
+            FilterMethod::Lanczos(3)
+        );
+        assert_eq!(
+            auto_filter((1000, 1000), (1100, 1100)),
+            FilterMethod::Linear
+        );
+        assert_eq!(
+            auto_filter((3840, 2160), (1920, 1080)),
+            FilterMethod::Linear
+        );
+        assert_eq!(auto_filter((0, 0), (1920, 1080)), FilterMethod::Linear);
+    }
+
+    #[test]
+    fn filters_map_to_their_algorithms() {
+        use fast_image_resize::{FilterType as Fir, ResizeAlg};
+        use image::imageops::FilterType as Image;
+
+        let filters = [
+            (FilterMethod::Nearest, Image::Nearest),
+            (FilterMethod::Linear, Image::Triangle),
+            (FilterMethod::Lanczos(3), Image::Lanczos3),
+            (FilterMethod::Lanczos(2), Image::Lanczos3),
+            (FilterMethod::Mitchell, Image::CatmullRom),
+            (FilterMethod::CatmullRom, Image::CatmullRom),
+        ];
+
+        for (filter, fallback) in filters {
+            assert_eq!(Image::from(filter), fallback, "{filter:?}");
+        }
+
+        assert!(matches!(
+            algorithm(FilterMethod::Nearest),
+            ResizeAlg::Nearest
+        ));
+        assert!(matches!(
+            algorithm(FilterMethod::Linear),
+            ResizeAlg::Convolution(Fir::Bilinear)
+        ));
+        assert!(matches!(
+            algorithm(FilterMethod::Lanczos(3)),
+            ResizeAlg::Convolution(Fir::Lanczos3)
+        ));
+        // Other windows fall back to 3.
+        assert!(matches!(
+            algorithm(FilterMethod::Lanczos(2)),
+            ResizeAlg::Convolution(Fir::Lanczos3)
+        ));
+        assert!(matches!(
+            algorithm(FilterMethod::Mitchell),
+            ResizeAlg::Convolution(Fir::Mitchell)
+        ));
+        assert!(matches!(
+            algorithm(FilterMethod::CatmullRom),
+            ResizeAlg::Convolution(Fir::CatmullRom)
+        ));
+    }
+
+    #[test]
+    fn float_images_are_resized_by_the_fast_path() {
+        let image = DynamicImage::ImageRgb32F(image::Rgb32FImage::from_pixel(
+            64,
+            32,
+            image::Rgb([0.25, 0.5, 1.0]),
+        ));
+        assert!(fast_resizable(image.color()));
+
+        // The fallback of the image crate would return an RGBA8 image.
+        let resized = resize(&image, 16, 8, FilterMethod::Lanczos(3));
+        assert_eq!(resized.color(), ColorType::Rgb32F);
+        assert_eq!((resized.width(), resized.height()), (16, 8));
+
+        for (a, b) in resized
+            .to_rgb32f()
+            .get_pixel(8, 4)
+            .0
+            .into_iter()
+            .zip([0.25, 0.5, 1.0])
+        {
+            assert!((a - b).abs() < 1e-3, "{a} != {b}");
+        }
+    }
+
+    #[test]
+    fn smart_blur_only_fills_mismatched_aspects() {
+        assert!(!aspect_mismatched((1920, 1080), (3840, 2160), 0.1));
+        assert!(aspect_mismatched((1080, 1920), (1920, 1080), 0.1));
+        // 16:10 on 16:9 differs by about 11%.
+        assert!(aspect_mismatched((1920, 1200), (1920, 1080), 0.1));
+        assert!(!aspect_mismatched((1920, 1200), (1920, 1080), 0.2));
+        assert!(!aspect_mismatched((0, 0), (1920, 1080), 0.1));
+
+        let white = |width, height| {
+            DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+                width,
+                height,
+                image::Rgba([255; 4]),
+            ))
+        };
+
+        let zoomed = smart_blur(&white(320, 180), 160, 90, 0.1, FilterMethod::Linear).to_rgba8();
+        assert_eq!(zoomed.get_pixel(0, 45).0, [255; 4]);
+
+        // The bars beside a portrait image are filled with a darkened blur.
+        let filled = smart_blur(&white(90, 180), 160, 90, 0.1, FilterMethod::Linear).to_rgba8();
+        assert_eq!(filled.get_pixel(80, 45).0, [255; 4]);
+        assert!(filled.get_pixel(0, 45).0[0] < 200);
+    }
+
+    #[test]
+    fn bottom_gravity_keeps_fitted_images_flush_with_the_bottom() {
+        let wide =
+            DynamicImage::ImageRgb8(image::RgbImage::from_pixel(200, 50, image::Rgb([255; 3])));
+        let fitted =
+            |gravity| fit(&wide, &[0.0; 3], 100, 100, gravity, FilterMethod::Nearest).to_rgb8();
+
+        for gravity in [[0.5, 1.0], [0.5, 3.0]] {
+            let image = fitted(gravity);
+            assert_eq!(image.get_pixel(50, 99).0, [255; 3]);
+            assert_eq!(image.get_pixel(50, 75).0, [255; 3]);
+            assert_eq!(image.get_pixel(50, 74).0, [0; 3]);
+        }
+
+        let centered = fitted([0.5, 0.5]);
+        assert_eq!(centered.get_pixel(50, 99).0, [0; 3]);
+        assert_eq!(centered.get_pixel(50, 50).0, [255; 3]);
+    }
+
+    #[test]
+    fn zoom_offsets_are_clamped_to_the_image() {
+        // Columns of the enlarged image get brighter to the right, so the crop
+        // position can be read back from its first pixel.
+        let img = DynamicImage::from(image::RgbImage::from_fn(200, 100, |x, _| {
+            image::Rgb([x as u8, 0, 0])
+        }));
+
+        let left = |offset| {
+            zoom_offset(&img, 100, 100, 0.0, offset, FilterMethod::Nearest)
+                .to_rgb8()
+                .get_pixel(0, 0)[0]
+        };
+
+        assert_eq!(left((0.0, 0.0)), 50);
+        assert_eq!(left((-1.0, 0.0)), 0);
+        assert_eq!(left((1.0, 0.0)), 100);
+
+        // Offsets beyond the extremes stay flush with an edge.
+        assert_eq!(left((-5.0, 0.0)), 0);
+        assert_eq!(left((5.0, 0.0)), 100);
+
+        let zoomed = zoom_offset(&img, 100, 100, 1.0, (10.0, 10.0), FilterMethod::Nearest);
+        assert_eq!((zoomed.width(), zoomed.height()), (100, 100));
+        assert_eq!(zoom_crop((400, 200), (100, 100), (10.0, 10.0)), (300, 100));
+        assert_eq!(zoom_crop((400, 200), (100, 100), (-10.0, -10.0)), (0, 0));
+        assert_eq!(zoom_crop((100, 100), (100, 100), (1.0, -1.0)), (0, 0));
+    }
+}
@@ -0,0 +1,176 @@
+// SPDX-License-Identifier: MPL-2.0-only
+
+//! Local time of day, and wallpaper settings scheduled by it.
+
+/// Minutes in a day.
+pub const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// Seconds in a day.
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Seconds since the Unix epoch, and the local time if it can be determined.
+fn now() -> (u64, Option<libc::tm>) {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+
+    let time = now as libc::time_t;
+    // SAFETY: `tm` is a plain C struct for which all zeroes is a valid value.
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+
+    // SAFETY: Both pointers are valid for the duration of the call, and the
+    // reentrant variant doesn't share a static buffer between threads.
+    let local = !unsafe { libc::localtime_r(&time, &mut tm) }.is_null();
+
+    (now, local.then_some(tm))
+}
+
+/// The local time, if it can be determined.
+#[must_use]
+pub fn local_time() -> Option<libc::tm> {
+    now().1
+}
+
+/// Seconds since midnight in the local time zone, or in UTC if the local time
+/// can't be determined.
+#[must_use]
+pub fn local_second_of_day() -> u32 {
+    match now() {
+        (_, Some(tm)) => tm.tm_hour as u32 * 3600 + tm.tm_min as u32 * 60 + tm.tm_sec as u32,
+        (now, None) => (now % SECONDS_PER_DAY) as u32,
+    }
+}
+
+/// Minutes since midnight in the local time zone, or in UTC if the local time
+/// can't be determined.
+#[must_use]
+pub fn local_minute_of_day() -> u32 {
+    local_second_of_day() / 60
+}
+
+/// Days since the start of the year in the local time zone, from 0.
+#[must_use]
+pub fn local_day_of_year() -> u32 {
+    match now() {
+        (_, Some(tm)) => tm.tm_yday as u32,
+        (now, None) => ((now / SECONDS_PER_DAY) % 365) as u32,
+    }
+}
+
+/// Today's year, month and day in the local time zone, or in UTC if the local
+/// time can't be determined.
+#[must_use]
+pub fn local_date() -> (i32, u32, u32) {
+    match now() {
+        (_, Some(tm)) => (tm.tm_year + 1900, tm.tm_mon as u32 + 1, tm.tm_mday as u32),
+        (now, None) => civil_date((now / SECONDS_PER_DAY) as i64),
+    }
+}
+
+/// The year, month and day of a number of days since the Unix epoch, in the
+/// proleptic Gregorian calendar.
+fn civil_date(days: i64) -> (i32, u32, u32) {
+    // From Howard Hinnant's `civil_from_days`, with eras of 400 years from March.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year as i32, month, day)
+}
+
+/// Time until the next local midnight.
+#[must_use]
+pub fn until_midnight() -> std::time::Duration {
+    // Leap seconds may report a 61st second.
+    let elapsed = u64::from(local_second_of_day()).min(SECONDS_PER_DAY - 1);
+    std::time::Duration::from_secs(SECONDS_PER_DAY - elapsed)
+}
+
+/// Brightness at `minute` of the day, interpolated linearly between the points
+/// of a schedule of minutes of the day and brightness. The schedule wraps
+/// around midnight from its last point to its first.
+#[must_use]
+pub fn brightness(schedule: &[(u32, f32)], minute: u32) -> f32 {
+    let mut points = schedule
+        .iter()
+        .map(|&(m, b)| (m % MINUTES_PER_DAY, b.clamp(0.0, 1.0)))
+        .collect::<Vec<_>>();
+
+    points.sort_by_key(|&(m, _)| m);
+
+    let (Some(&first), Some(&last)) = (points.first(), points.last()) else {
+        return 1.0;
+    };
+
+    let minute = minute % MINUTES_PER_DAY;
+
+    let previous = points
+        .iter()
+        .rev()
+        .find(|&&(m, _)| m <= minute)
+        .copied()
+        .unwrap_or(last);
+
+    let next = points
+        .iter()
+        .find(|&&(m, _)| m > minute)
+        .copied()
+        .unwrap_or(first);
+
+    let span = (next.0 + MINUTES_PER_DAY - previous.0) % MINUTES_PER_DAY;
+    if span == 0 {
+        return previous.1;
+    }
+
+    let elapsed = (minute + MINUTES_PER_DAY - previous.0) % MINUTES_PER_DAY;
+    let t = elapsed as f32 / span as f32;
+
+    previous.1 + (next.1 - previous.1) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn brightness_interpolates_between_points() {
+        let schedule = [(8 * 60, 1.0), (20 * 60, 0.5)];
+
+        assert_eq!(brightness(&schedule, 8 * 60), 1.0);
+        assert_eq!(brightness(&schedule, 14 * 60), 0.75);
+        assert_eq!(brightness(&schedule, 20 * 60), 0.5);
+    }
+
+    #[test]
+    fn brightness_wraps_around_midnight() {
+        let schedule = [(20 * 60, 0.5), (8 * 60, 1.0)];
+
+        // Midnight is a third of the way from 20:00 to 08:00.
+        let midnight = brightness(&schedule, 0);
+        assert!((midnight - 2.0 / 3.0).abs() < 1e-6, "{midnight}");
+        assert_eq!(brightness(&schedule, 2 * 60), 0.75);
+        assert_eq!(brightness(&schedule, MINUTES_PER_DAY + 14 * 60), 0.75);
+    }
+
+    #[test]
+    fn brightness_without_a_schedule_is_full() {
+        assert_eq!(brightness(&[], 600), 1.0);
+        assert_eq!(brightness(&[(600, 2.0)], 0), 1.0);
+        assert_eq!(brightness(&[(600, 0.3)], 1200), 0.3);
+    }
+
+    #[test]
+    fn civil_dates_of_days_since_the_epoch() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(-1), (1969, 12, 31));
+        assert_eq!(civil_date(11_016), (2000, 2, 29));
+        assert_eq!(civil_date(19_723), (2024, 1, 1));
+    }
+}
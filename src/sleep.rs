@@ -0,0 +1,66 @@
+// SPDX-License-Identifier: MPL-2.0-only
+
+//! Notifications of resumes from suspend, from the `PrepareForSleep` signal of
+//! logind, which is emitted with `true` before suspending and `false` after
+//! resuming.
+//!
+//! The signal is received on a thread of its own, which only wakes the event
+//! loop once the system resumes.
+
+use sctk::reexports::calloop::channel::Sender;
+
+const DESTINATION: &str = "org.freedesktop.login1";
+const PATH: &str = "/org/freedesktop/login1";
+const INTERFACE: &str = "org.freedesktop.login1.Manager";
+
+/// Sends a message to `tx` whenever the system resumes from suspend.
+pub fn watch(tx: Sender<()>) {
+    let spawned = std::thread::Builder::new()
+        .name("logind".into())
+        .spawn(move || {
+            if let Err(why) = receive(&tx) {
+                tracing::warn!(?why, "can't detect resumes from suspend through logind");
+            }
+        });
+
+    if let Err(why) = spawned {
+        tracing::warn!(
+            ?why,
+            "failed to spawn thread watching for resumes from suspend"
+        );
+    }
+}
+
+fn receive(tx: &Sender<()>) -> zbus::Result<()> {
+    let connection = zbus::blocking::Connection::system()?;
+    let proxy = zbus::blocking::Proxy::new(&connection, DESTINATION, PATH, INTERFACE)?;
+
+    let sleeping = proxy
+        .receive_signal("PrepareForSleep")?
+        .filter_map(|message| message.body().deserialize::<bool>().ok());
+
+    for () in resumes(sleeping) {
+        if tx.send(()).is_err() {
+            // The event loop has exited.
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Resumes from a sequence of `PrepareForSleep` values.
+fn resumes(sleeping: impl Iterator<Item = bool>) -> impl Iterator<Item = ()> {
+    sleeping.filter(|&start| !start).map(drop)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_end_of_sleep_is_a_resume() {
+        assert_eq!(resumes([true, false, true, false].into_iter()).count(), 2);
+        assert_eq!(resumes([true].into_iter()).count(), 0);
+    }
+}
@@ -0,0 +1,199 @@
+// SPDX-License-Identifier: MPL-2.0-only
+
+//! Health statistics for monitoring long-running sessions such as kiosks,
+//! enabled with `COSMIC_BG_STATS_FILE`.
+//!
+//! The file is rewritten every [`INTERVAL`] in the Prometheus text format, so
+//! that it can be collected by node_exporter's textfile collector.
+
+use std::{fmt::Write as _, fs, io, path::Path, time::Duration};
+
+use crate::wallpaper::Wallpaper;
+
+/// Environment variable with the path of the stats file.
+pub const ENV: &str = "COSMIC_BG_STATS_FILE";
+
+/// Interval between writes of the stats file.
+pub const INTERVAL: Duration = Duration::from_secs(15);
+
+/// Statistics of one wallpaper.
+#[derive(Debug)]
+pub struct WallpaperStats {
+    pub output: String,
+    pub source: String,
+    pub draws: u64,
+    pub failed_draws: u64,
+    pub decode_errors: u64,
+}
+
+/// Statistics of the daemon, gathered from all wallpapers.
+#[derive(Debug)]
+pub struct Stats {
+    /// Resident memory in bytes, if it could be read.
+    pub resident_memory: Option<u64>,
+    /// Outputs that have a wallpaper layer.
+    pub outputs: usize,
+    pub wallpapers: Vec<WallpaperStats>,
+}
+
+impl Stats {
+    #[must_use]
+    pub fn gather(wallpapers: &[Wallpaper]) -> Self {
+        Self {
+            resident_memory: resident_memory(),
+            outputs: wallpapers.iter().map(|w| w.layers.len()).sum(),
+            wallpapers: wallpapers
+                .iter()
+                .map(|wallpaper| {
+                    let stats = wallpaper.stats();
+                    let source = wallpaper
+                        .current_source()
+                        .unwrap_or(&wallpaper.entry.source);
+
+                    WallpaperStats {
+                        output: wallpaper.entry.output.clone(),
+                        source: crate::doctor::source_summary(source),
+                        draws: stats.draws(),
+                        failed_draws: stats.failed_draws(),
+                        decode_errors: stats.decode_errors(),
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Formats the statistics in the Prometheus text format.
+    #[must_use]
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+
+        header(&mut out, "outputs", "gauge", "Outputs with a wallpaper.");
+        let _ = writeln!(out, "cosmic_bg_outputs {}", self.outputs);
+
+        if let Some(bytes) = self.resident_memory {
+            header(
+                &mut out,
+                "resident_memory_bytes",
+                "gauge",
+                "Resident memory size.",
+            );
+            let _ = writeln!(out, "cosmic_bg_resident_memory_bytes {bytes}");
+        }
+
+        header(
+            &mut out,
+            "source_info",
+            "gauge",
+            "Source shown by each wallpaper.",
+        );
+        for w in &self.wallpapers {
+            let _ = writeln!(
+                out,
+                "cosmic_bg_source_info{{output=\"{}\",source=\"{}\"}} 1",
+                label(&w.output),
+                label(&w.source)
+            );
+        }
+
+        let counters: [(&str, &str, fn(&WallpaperStats) -> u64); 3] = [
+            ("draws_total", "Completed wallpaper draws.", |w| w.draws),
+            (
+                "failed_draws_total",
+                "Draws that could not be submitted.",
+                |w| w.failed_draws,
+            ),
+            (
+                "decode_errors_total",
+                "Images that could not be decoded.",
+                |w| w.decode_errors,
+            ),
+        ];
+
+        for (name, help, value) in counters {
+            header(&mut out, name, "counter", help);
+            for w in &self.wallpapers {
+                let _ = writeln!(
+                    out,
+                    "cosmic_bg_{name}{{output=\"{}\"}} {}",
+                    label(&w.output),
+                    value(w)
+                );
+            }
+        }
+
+        out
+    }
+}
+
+/// Writes the stats file, replacing it at once so that collectors never read a
+/// partial file.
+pub fn write(path: &Path, wallpapers: &[Wallpaper]) -> io::Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".tmp");
+
+    fs::write(&partial, Stats::gather(wallpapers).to_text())?;
+    fs::rename(partial, path)
+}
+
+/// Resident memory of the process, from the pages counted in `/proc/self/statm`.
+fn resident_memory() -> Option<u64> {
+    let statm = fs::read_to_string("/proc/self/statm").ok()?;
+    let pages = statm.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+
+    // SAFETY: `sysconf` has no preconditions.
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    u64::try_from(page_size).ok().map(|size| pages * size)
+}
+
+/// Writes the help and type lines that precede the samples of a metric.
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP cosmic_bg_{name} {help}");
+    let _ = writeln!(out, "# TYPE cosmic_bg_{name} {kind}");
+}
+
+/// Escapes a label value of the Prometheus text format.
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_are_formatted_as_prometheus_text() {
+        let stats = Stats {
+            resident_memory: Some(4096),
+            outputs: 1,
+            wallpapers: vec![WallpaperStats {
+                output: String::from("DP-1"),
+                source: String::from("say \"hi\""),
+                draws: 3,
+                failed_draws: 1,
+                decode_errors: 0,
+            }],
+        };
+
+        let text = stats.to_text();
+        let samples = text
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            samples,
+            [
+                "cosmic_bg_outputs 1",
+                "cosmic_bg_resident_memory_bytes 4096",
+                r#"cosmic_bg_source_info{output="DP-1",source="say \"hi\""} 1"#,
+                r#"cosmic_bg_draws_total{output="DP-1"} 3"#,
+                r#"cosmic_bg_failed_draws_total{output="DP-1"} 1"#,
+                r#"cosmic_bg_decode_errors_total{output="DP-1"} 0"#,
+            ]
+        );
+        assert!(text.contains("# TYPE cosmic_bg_draws_total counter\n"));
+    }
+}
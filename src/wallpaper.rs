@@ -1,31 +1,40 @@
 // SPDX-License-Identifier: MPL-2.0-only
 
-use crate::{CosmicBg, CosmicBgLayer};
+use crate::{
+    img_source::{Scan, Sources},
+    CosmicBg, CosmicBgLayer,
+};
 
 use std::{
-    collections::VecDeque,
-    fs,
-    path::PathBuf,
+    collections::{HashMap, HashSet, VecDeque},
+    io::{BufRead, Seek},
+    path::{Path, PathBuf},
+    rc::Rc,
     time::{Duration, Instant},
 };
 
-use cosmic_bg_config::{state::State, Color, Entry, SamplingMethod, ScalingMode, Source};
+use cosmic_bg_config::{
+    state::{State, Status},
+    Color, Config, Entry, SamplingMethod, ScalingMode, Source,
+};
 use cosmic_config::CosmicConfigEntry;
 use eyre::{eyre, OptionExt};
-use image::{DynamicImage, GrayAlphaImage, GrayImage, ImageReader, RgbImage, RgbaImage};
+use image::{
+    DynamicImage, GrayAlphaImage, GrayImage, ImageDecoder, ImageReader, RgbImage, RgbaImage,
+};
 use jxl_oxide::{EnumColourEncoding, JxlImage, PixelFormat};
-use notify::{RecommendedWatcher, RecursiveMode, Watcher};
-use rand::{seq::SliceRandom, thread_rng};
+use rand::{seq::SliceRandom, thread_rng, Rng};
+use rayon::prelude::*;
 use sctk::reexports::{
     calloop::{
-        self,
+        self, channel,
         timer::{TimeoutAction, Timer},
         RegistrationToken,
     },
     client::QueueHandle,
 };
+use sctk::shell::WaylandSurface;
 use tracing::error;
-use walkdir::WalkDir;
 
 // TODO filter images by whether they seem to match dark / light mode
 // Alternatively only load from light / dark subdirectories given a directory source when this is active
@@ -38,9 +47,58 @@ pub struct Wallpaper {
     loop_handle: calloop::LoopHandle<'static, CosmicBg>,
     queue_handle: QueueHandle<CosmicBg>,
     current_source: Option<Source>,
+    /// Source whose images are loaded, which is the first available source of
+    /// a fallback chain.
+    source: Source,
     // Cache of source image, if `current_source` is a `Source::Path`
     current_image: Option<image::DynamicImage>,
+    // Cache of the overlay image, if `current_source` is a `Source::Blend`
+    overlay_image: Option<image::DynamicImage>,
     timer_token: Option<RegistrationToken>,
+    draw_stats: crate::overlay::Stats,
+    /// Pointer offset applied to zoomed images with a parallax strength.
+    pub parallax_offset: (f32, f32),
+    /// Whether a ping-pong slideshow is walking backward.
+    reversing: bool,
+    /// Whether the slideshow is pinned to its current image.
+    pinned: bool,
+    /// Images shown most recently, up to the entry's no-repeat window.
+    recent: VecDeque<PathBuf>,
+    sources: Sources,
+    /// Scan of the source path, shared with wallpapers of the same source.
+    scan: Option<Rc<Scan>>,
+    /// Whether layers fade in on their first draw, which is only the case for
+    /// wallpapers created at startup.
+    pub startup_fade: bool,
+    fade_token: Option<RegistrationToken>,
+    /// Timer moving a daily album slideshow to the next album at midnight.
+    album_token: Option<RegistrationToken>,
+    /// Timer checking whether the source of a fallback chain should change.
+    fallback_token: Option<RegistrationToken>,
+    /// Timer redrawing a clock at the start of every minute.
+    clock_token: Option<RegistrationToken>,
+    /// Source last drawn, whose changes are matched by wallpapers of
+    /// `AutoMatchPrimary` sources if this is the primary output's wallpaper.
+    drawn_source: Option<Source>,
+    /// Largest edge of the buffers drawn, or `0` for no limit.
+    max_render_edge: u32,
+    /// Scheduled brightness applied to the wallpaper, from 0.0 to 1.0.
+    pub brightness: f32,
+    /// Color temperature of the night light in kelvin, which the wallpaper is
+    /// warmed to match.
+    pub night_light: Option<u32>,
+    /// Status of the source found when loading its images.
+    load_status: Status,
+    /// Upcoming images of the slideshow decoded ahead of being shown.
+    preloaded: HashMap<PathBuf, DynamicImage>,
+    /// Upcoming images of the slideshow being decoded on another thread.
+    preloading: HashSet<PathBuf>,
+    preload_tx: channel::Sender<(PathBuf, Option<DynamicImage>)>,
+    preload_token: Option<RegistrationToken>,
+    /// Hashes of hashed sources being located on another thread.
+    resolving: HashSet<String>,
+    resolve_tx: channel::Sender<(String, Option<PathBuf>)>,
+    resolve_token: Option<RegistrationToken>,
 }
 
 impl Drop for Wallpaper {
@@ -48,6 +106,30 @@ impl Drop for Wallpaper {
         if let Some(token) = self.timer_token.take() {
             self.loop_handle.remove(token);
         }
+
+        if let Some(token) = self.fade_token.take() {
+            self.loop_handle.remove(token);
+        }
+
+        if let Some(token) = self.album_token.take() {
+            self.loop_handle.remove(token);
+        }
+
+        if let Some(token) = self.fallback_token.take() {
+            self.loop_handle.remove(token);
+        }
+
+        if let Some(token) = self.clock_token.take() {
+            self.loop_handle.remove(token);
+        }
+
+        if let Some(token) = self.preload_token.take() {
+            self.loop_handle.remove(token);
+        }
+
+        if let Some(token) = self.resolve_token.take() {
+            self.loop_handle.remove(token);
+        }
     }
 }
 
@@ -56,22 +138,95 @@ impl Wallpaper {
         entry: Entry,
         queue_handle: QueueHandle<CosmicBg>,
         loop_handle: calloop::LoopHandle<'static, CosmicBg>,
-        source_tx: calloop::channel::SyncSender<(String, notify::Event)>,
+        sources: Sources,
+        max_render_edge: u32,
+        brightness: f32,
+        night_light: Option<u32>,
     ) -> Self {
+        let (preload_tx, preload_rx) = channel::channel();
+        let output = entry.output.clone();
+
+        let preload_token = loop_handle
+            .insert_source(preload_rx, move |event, _, state: &mut CosmicBg| {
+                let channel::Event::Msg((path, image)) = event else {
+                    return;
+                };
+
+                let Some(item) = state
+                    .wallpapers
+                    .iter_mut()
+                    .find(|w| w.entry.output == output)
+                else {
+                    return;
+                };
+
+                // Images requested before the slideshow was reloaded are discarded.
+                if item.preloading.remove(&path) {
+                    if let Some(image) = image {
+                        item.preloaded.insert(path, image);
+                    }
+                }
+            })
+            .ok();
+
+        let (resolve_tx, resolve_rx) = channel::channel();
+        let output = entry.output.clone();
+
+        let resolve_token = loop_handle
+            .insert_source(resolve_rx, move |event, _, state: &mut CosmicBg| {
+                let channel::Event::Msg((sha256, path)) = event else {
+                    return;
+                };
+
+                if let Some(item) = state
+                    .wallpapers
+                    .iter_mut()
+                    .find(|w| w.entry.output == output)
+                {
+                    item.resolved(sha256, path);
+                }
+            })
+            .ok();
+
         let mut wallpaper = Wallpaper {
             entry,
             layers: Vec::new(),
             current_source: None,
+            source: Source::None,
             current_image: None,
+            overlay_image: None,
             image_queue: VecDeque::default(),
             timer_token: None,
+            draw_stats: crate::overlay::Stats::default(),
+            parallax_offset: (0.0, 0.0),
+            reversing: false,
+            pinned: false,
+            recent: VecDeque::new(),
+            sources,
+            scan: None,
+            startup_fade: false,
+            fade_token: None,
+            album_token: None,
+            fallback_token: None,
+            clock_token: None,
+            drawn_source: None,
+            max_render_edge,
+            brightness,
+            night_light,
+            load_status: Status::Ok,
+            preloaded: HashMap::new(),
+            preloading: HashSet::new(),
+            preload_tx,
+            preload_token,
+            resolving: HashSet::new(),
+            resolve_tx,
+            resolve_token,
             loop_handle,
             queue_handle,
         };
 
         wallpaper.load_images();
         wallpaper.register_timer();
-        wallpaper.watch_source(source_tx);
         wallpaper
     }
 
@@ -80,18 +235,12 @@ impl Wallpaper {
             return Ok(());
         };
         let state_helper = State::state()?;
-        let mut state = State::get_entry(&state_helper).unwrap_or_default();
+        // State saved before displays were recorded lacks that key.
+        let mut state = State::get_entry(&state_helper).unwrap_or_else(|(_, state)| state);
         for l in &self.layers {
-            let name = l.output_info.name.clone().unwrap_or_default();
-            if let Some((_, source)) = state
-                .wallpapers
-                .iter_mut()
-                .find(|(output, _)| *output == name)
-            {
-                *source = cur_source.clone();
-            } else {
-                state.wallpapers.push((name, cur_source.clone()))
-            }
+            let name = l.output_info.name.as_deref().unwrap_or_default();
+            let display = crate::output::model_key(&l.output_info);
+            state.set_wallpaper(name, &display, cur_source.clone());
         }
         state.write_entry(&state_helper)
     }
@@ -99,115 +248,213 @@ impl Wallpaper {
     #[allow(clippy::too_many_lines)]
     pub fn draw(&mut self) {
         let start = Instant::now();
-        let mut cur_resized_img: Option<DynamicImage> = None;
+        let fade_duration = Duration::from_millis(self.entry.startup_fade_ms);
+
+        // Distinct sizes and scaling modes to scale the source for, and the
+        // target of each layer to draw. Layers without a target reuse the
+        // scaled image of the previous frame of their fade.
+        let mut targets: Vec<Target> = Vec::new();
+        let mut layer_targets: Vec<(usize, (u32, u32), Option<usize>)> = Vec::new();
 
-        for layer in self.layers.iter_mut().filter(|layer| layer.needs_redraw) {
-            let Some(pool) = layer.pool.as_mut() else {
+        for (index, layer) in self.layers.iter_mut().enumerate() {
+            if !layer.needs_redraw || layer.pool.is_none() {
                 continue;
-            };
+            }
 
-            let Some(fractional_scale) = layer.fractional_scale else {
+            let (Some(size), Some(fractional_scale)) = (layer.size, layer.fractional_scale) else {
                 continue;
             };
 
-            let Some((width, height)) = layer.size else {
+            // The same size that the layer's pool was allocated for.
+            let (width, height) = crate::draw_size(
+                size,
+                fractional_scale,
+                layer.render_size,
+                self.max_render_edge,
+                layer.degraded,
+            );
+
+            if !crate::valid_size(width, height) {
+                tracing::warn!(width, height, "skipping draw of invalid size");
                 continue;
-            };
+            }
 
-            let width = width * fractional_scale / 120;
-            let height = height * fractional_scale / 120;
+            if self.startup_fade && !fade_duration.is_zero() && !layer.drawn {
+                layer.fade.start = Some(Instant::now());
+            }
 
-            if cur_resized_img
-                .as_ref()
-                .map_or(true, |img| img.width() != width || img.height() != height)
+            if layer.fade.start.is_some()
+                && layer.fade.image.as_ref().is_some_and(|img| {
+                    layer
+                        .zoom_crop
+                        .map_or((img.width(), img.height()), |(_, size)| size)
+                        == (width, height)
+                })
             {
-                let Some(source) = self.current_source.as_ref() else {
-                    tracing::info!("No source for wallpaper");
-                    continue;
-                };
+                layer_targets.push((index, (width, height), None));
+                continue;
+            }
 
-                cur_resized_img = match source {
-                    Source::Path(ref path) => {
-                        if self.current_image.is_none() {
-                            self.current_image = Some(match path.extension() {
-                                Some(ext) if ext == "jxl" => match decode_jpegxl(&path) {
-                                    Ok(image) => image,
-                                    Err(why) => {
-                                        tracing::warn!(
-                                            ?why,
-                                            "jpegl-xl image decode failed: {}",
-                                            path.display()
-                                        );
-                                        continue;
-                                    }
-                                },
-
-                                _ => match ImageReader::open(&path) {
-                                    Ok(img) => {
-                                        match img
-                                            .with_guessed_format()
-                                            .ok()
-                                            .and_then(|f| f.decode().ok())
-                                        {
-                                            Some(img) => img,
-                                            None => {
-                                                tracing::warn!(
-                                                    "could not decode image: {}",
-                                                    path.display()
-                                                );
-                                                continue;
-                                            }
-                                        }
-                                    }
-                                    Err(_) => continue,
-                                },
-                            });
-                        }
-                        let img = self.current_image.as_ref().unwrap();
+            let target = Target {
+                width,
+                height,
+                scaling_mode: self
+                    .entry
+                    .scaling_mode_for(layer.output_info.name.as_deref().unwrap_or_default()),
+            };
 
-                        match self.entry.scaling_mode {
-                            ScalingMode::Fit(color) => {
-                                Some(crate::scaler::fit(img, &color, width, height))
-                            }
+            let target_index = targets
+                .iter()
+                .position(|t| *t == target)
+                .unwrap_or_else(|| {
+                    targets.push(target);
+                    targets.len() - 1
+                });
 
-                            ScalingMode::Zoom => Some(crate::scaler::zoom(img, width, height)),
+            layer_targets.push((index, (width, height), Some(target_index)));
+        }
 
-                            ScalingMode::Stretch => {
-                                Some(crate::scaler::stretch(img, width, height))
-                            }
-                        }
-                    }
+        if layer_targets.is_empty() {
+            return;
+        }
 
-                    Source::Color(Color::Single([ref r, ref g, ref b])) => {
-                        Some(image::DynamicImage::from(crate::colored::single(
-                            [*r, *g, *b],
-                            width,
-                            height,
-                        )))
-                    }
+        if !targets.is_empty() {
+            if let Err(path) = self.load_source_images() {
+                self.draw_stats.record_decode_error();
+                self.report_status(&Status::DecodeFailed(path));
+                return;
+            }
+        }
 
-                    Source::Color(Color::Gradient(ref gradient)) => {
-                        match crate::colored::gradient(gradient, width, height) {
-                            Ok(buffer) => Some(image::DynamicImage::from(buffer)),
-                            Err(why) => {
-                                tracing::error!(
-                                    ?gradient,
-                                    ?why,
-                                    "color gradient in config is invalid"
-                                );
-                                None
-                            }
-                        }
-                    }
-                };
+        let Some(source) = self.current_source.as_ref() else {
+            tracing::info!("No source for wallpaper");
+            let status = self.load_status.clone();
+            self.report_status(&status);
+            return;
+        };
+
+        // Scaling is the most expensive part of a draw, so targets are scaled in
+        // parallel. Only the copy into each layer's pool happens on this thread.
+        let entry = &self.entry;
+        let framed = entry.inset > 0 || entry.corner_radius > 0;
+        let transparent = matches!(self.current_source, Some(Source::None));
+        let translucent = entry.image_opacity < 1.0 && entry.background_color.is_none();
+        let (current_image, overlay_image) =
+            (self.current_image.as_ref(), self.overlay_image.as_ref());
+
+        // Splits are composed from their sides, so they're shifted before they
+        // are composed. Other zoomed images are left enlarged for the viewport
+        // to crop as the pointer moves.
+        let parallax_offset =
+            matches!(source, Source::Split { .. }).then_some(self.parallax_offset);
+
+        let scaled = render_targets(&targets, |target| {
+            let image = render(
+                entry,
+                source,
+                current_image,
+                overlay_image,
+                parallax_offset,
+                target,
+            )?;
+
+            let image = if entry.image_opacity < 1.0 || entry.background_color.is_some() {
+                crate::blend::over_color(&image, entry.background_color, entry.image_opacity)
+            } else {
+                image
+            };
+
+            let image = if entry.vignette > 0.0 {
+                crate::effects::vignette(&image, entry.vignette)
+            } else {
+                image
+            };
+
+            if framed {
+                Some(crate::effects::frame(
+                    &image,
+                    entry.inset,
+                    entry.corner_radius,
+                ))
+            } else {
+                Some(image)
+            }
+        });
+
+        // Whether a layer failed to draw at full resolution, which is drawn again
+        // at reduced resolution rather than left blank.
+        let mut retry = false;
+
+        for (index, size, target_index) in layer_targets {
+            let layer = &mut self.layers[index];
+
+            let fade_image = layer.fade.image.take();
+
+            let image = match target_index {
+                Some(target_index) => scaled[target_index].as_ref(),
+                None => fade_image.as_ref(),
+            };
+
+            let (Some(image), Some(pool)) = (image, layer.pool.as_mut()) else {
+                continue;
+            };
+
+            let (width, height) = (image.width(), image.height());
+
+            // Only zoomed images that follow the pointer are rendered larger than
+            // the layer, which the viewport crops.
+            layer.zoom_crop = ((width, height) != size).then_some(((width, height), size));
+
+            let opacity = layer.fade.opacity(fade_duration);
+
+            if opacity < 1.0 {
+                layer.fade.image = Some(image.clone());
+            } else {
+                layer.fade.start = None;
             }
 
-            let image = cur_resized_img.as_ref().unwrap();
-            let buffer_result =
-                crate::draw::canvas(pool, image, width as i32, height as i32, width as i32 * 4);
+            let factor = opacity * self.brightness;
+            let faded;
+            let image = if factor < 1.0 {
+                faded = crate::effects::darken(image, factor);
+                &faded
+            } else {
+                image
+            };
+
+            let warmed;
+            let image = if let Some(kelvin) = self.night_light {
+                warmed = crate::effects::color_temperature(image, kelvin);
+                &warmed
+            } else {
+                image
+            };
+
+            // Framed and translucent wallpapers need the transparency of their
+            // masked edges and lowered opacity.
+            let alpha = (self.entry.preserve_alpha || framed || transparent || translucent)
+                && image.color().has_alpha();
+            let format = if alpha { "ARGB8888" } else { "XRGB8888" };
+
+            let transformed = crate::draw::transform(image, layer.transform);
+            let buffer_image = transformed.as_ref().unwrap_or(image);
+            let (width, height) = crate::draw::buffer_size(width, height, layer.transform);
+
+            let overlay =
+                crate::overlay::enabled().then(|| self.draw_stats.text(width, height, format));
+            let buffer_result = crate::draw::canvas(
+                pool,
+                buffer_image,
+                width as i32,
+                height as i32,
+                width as i32 * 4,
+                alpha,
+                overlay.as_deref(),
+            );
 
             match buffer_result {
                 Ok(buffer) => {
+                    crate::draw::crop(layer, self.parallax_offset);
                     crate::draw::layer_surface(
                         layer,
                         &self.queue_handle,
@@ -215,227 +462,1716 @@ impl Wallpaper {
                         (width as i32, height as i32),
                     );
                     layer.needs_redraw = false;
+                    layer.drawn = true;
+                    report_status(layer, &Status::Ok);
+
+                    // Copies are written once the layer has faded in, so that they
+                    // aren't darkened by the fade.
+                    if layer.fade.start.is_none() && layer.lockscreen_source != self.current_source
+                    {
+                        let output = layer.output_info.name.as_deref().unwrap_or_default();
+                        let cropped = layer.zoom_crop.map(|(enlarged, size)| {
+                            let (x, y) =
+                                crate::scaler::zoom_crop(enlarged, size, self.parallax_offset);
+                            image.crop_imm(x, y, size.0, size.1)
+                        });
+                        let image = cropped.as_ref().unwrap_or(image);
+
+                        if let Err(why) = crate::lockscreen::write(output, image) {
+                            tracing::warn!(?why, output, "failed to write lock screen thumbnail");
+                        }
+                        if let Err(why) = crate::warmstart::write(output, image) {
+                            tracing::warn!(?why, output, "failed to write warm start image");
+                        }
+                        layer.lockscreen_source = self.current_source.clone();
+                    }
 
                     let elapsed = Instant::now().duration_since(start);
+                    self.draw_stats.record(elapsed);
 
                     tracing::debug!(?elapsed, source = ?self.entry.source, "wallpaper draw");
                 }
 
                 Err(why) => {
-                    tracing::error!(?why, "wallpaper could not be drawn");
+                    self.draw_stats.record_failure();
+
+                    if layer.degraded {
+                        tracing::error!(?why, "wallpaper could not be drawn");
+                    } else {
+                        tracing::warn!(
+                            ?why,
+                            "wallpaper could not be drawn, retrying at reduced resolution"
+                        );
+                        layer.degraded = true;
+                        retry = true;
+                    }
                 }
             }
         }
-    }
 
-    pub fn load_images(&mut self) {
-        let mut image_queue = VecDeque::new();
+        if self.fade_token.is_none() && self.layers.iter().any(|l| l.fade.start.is_some()) {
+            self.register_fade_timer();
+        }
 
-        match self.entry.source {
-            Source::Path(ref source) => {
-                tracing::debug!(?source, "loading images");
-
-                if let Ok(source) = source.canonicalize() {
-                    if source.is_dir() {
-                        if source.starts_with("/usr/share/backgrounds/") {
-                            // Store paths of wallpapers to be used for the slideshow.
-                            for img_path in WalkDir::new(source)
-                                .follow_links(true)
-                                .into_iter()
-                                .filter_map(Result::ok)
-                                .filter(|p| p.path().is_file())
-                            {
-                                image_queue.push_front(img_path.path().into());
-                            }
-                        } else if let Ok(dir) = source.read_dir() {
-                            for entry in dir.filter_map(Result::ok) {
-                                let Ok(path) = entry.path().canonicalize() else {
-                                    continue;
-                                };
-
-                                if path.is_file() {
-                                    image_queue.push_front(path);
-                                }
-                            }
-                        }
-                    } else if source.is_file() {
-                        image_queue.push_front(source);
-                    }
-                }
+        if self.drawn_source != self.current_source {
+            self.drawn_source = self.current_source.clone();
+            self.loop_handle
+                .insert_idle(|state: &mut CosmicBg| state.match_primary_colors());
+        }
 
-                if image_queue.len() > 1 {
-                    let image_slice = image_queue.make_contiguous();
-                    match self.entry.sampling_method {
-                        SamplingMethod::Alphanumeric => {
-                            image_slice
-                                .sort_by(|a, b| a.to_string_lossy().cmp(&b.to_string_lossy()));
-                        }
-                        SamplingMethod::Random => image_slice.shuffle(&mut thread_rng()),
-                    };
+        if retry {
+            self.draw();
+        }
+    }
 
-                    // If a wallpaper from this slideshow was previously set, resume with that wallpaper.
-                    if let Some(Source::Path(last_path)) = current_image(&self.entry.output) {
-                        if image_queue.contains(&last_path) {
-                            while let Some(path) = image_queue.pop_front() {
-                                if path == last_path {
-                                    image_queue.push_front(path);
-                                    break;
-                                }
+    /// Decodes the images of the current source that are not yet cached.
+    ///
+    /// Returns the path of an image that could not be decoded.
+    fn load_source_images(&mut self) -> Result<(), PathBuf> {
+        let side_path = |side: &Source| match *side {
+            Source::Path(ref path) => Some(path),
+            _ => None,
+        };
 
-                                image_queue.push_back(path);
-                            }
-                        }
-                    }
-                }
+        let (base, overlay) = match self.current_source {
+            Some(Source::Path(ref path)) => (Some(path), None),
+            Some(Source::Blend {
+                ref base,
+                ref overlay,
+                ..
+            }) => (Some(base), Some(overlay)),
+            // The images of the sides of a split are decoded as the image and
+            // overlay, and colors need none.
+            Some(Source::Split {
+                ref left,
+                ref right,
+                ..
+            }) => (side_path(left), side_path(right)),
+            _ => return Ok(()),
+        };
 
-                image_queue.pop_front().map(|current_image_path| {
-                    self.current_source = Some(Source::Path(current_image_path.clone()));
-                    image_queue.push_back(current_image_path);
-                });
+        let decoded = self.current_image.is_none();
+
+        if let Some(base) = base.filter(|_| decoded) {
+            let image = match self.preloaded.remove(base) {
+                Some(image) => image,
+                None => decode_image(base).ok_or_else(|| base.clone())?,
+            };
+            self.current_image = Some(image);
+        }
+
+        if let Some(overlay) = overlay {
+            if self.overlay_image.is_none() {
+                let Some(image) = decode_image(overlay) else {
+                    return Err(overlay.clone());
+                };
+                self.overlay_image = Some(image);
             }
+        }
 
-            Source::Color(ref c) => {
-                self.current_source = Some(Source::Color(c.clone()));
+        if decoded {
+            self.preload();
+        }
+
+        Ok(())
+    }
+
+    /// Decodes the next images of the slideshow on another thread, so that they
+    /// are shown without waiting for their decode.
+    fn preload(&mut self) {
+        if self.entry.preload == 0 {
+            return;
+        }
+
+        let upcoming = upcoming_images(&self.image_queue, self.reversing, self.entry.preload);
+        self.preloaded.retain(|path, _| upcoming.contains(path));
+
+        for path in upcoming {
+            if self.preloaded.contains_key(&path) || !self.preloading.insert(path.clone()) {
+                continue;
             }
-        };
-        if let Err(err) = self.save_state() {
-            error!("{err}");
+
+            let tx = self.preload_tx.clone();
+            rayon::spawn(move || {
+                let image = decode_image(&path);
+                let _ = tx.send((path, image));
+            });
         }
-        self.image_queue = image_queue;
     }
 
-    fn watch_source(&self, tx: calloop::channel::SyncSender<(String, notify::Event)>) {
-        let Source::Path(ref source) = self.entry.source else {
+    /// Locates the image of a hashed source on another thread.
+    fn resolve_hash(&mut self, sha256: String, search_paths: Vec<PathBuf>) {
+        if !self.resolving.insert(sha256.clone()) {
             return;
-        };
+        }
 
-        let output = self.entry.output.clone();
-        let mut watcher = match RecommendedWatcher::new(
-            move |res| {
-                if let Ok(e) = res {
-                    let _ = tx.send((output.clone(), e));
-                }
-            },
-            notify::Config::default(),
-        ) {
-            Ok(w) => w,
-            Err(_) => return,
-        };
+        let tx = self.resolve_tx.clone();
+        rayon::spawn(move || {
+            let path = crate::hash::resolve(&sha256, &search_paths);
+            let _ = tx.send((sha256, path));
+        });
+    }
+
+    /// Loads the wallpaper again once the image of a hashed source it shows, or
+    /// of its fallback chain, was located.
+    fn resolved(&mut self, sha256: String, path: Option<PathBuf>) {
+        if !self.resolving.remove(&sha256) {
+            return;
+        }
 
-        tracing::debug!(output = self.entry.output, "watching source");
+        let shown =
+            matches!(self.source, Source::Hashed { sha256: ref shown, .. } if *shown == sha256);
 
-        if let Ok(m) = fs::metadata(source) {
-            if m.is_dir() {
-                let _ = watcher.watch(source, RecursiveMode::Recursive);
-            } else if m.is_file() {
-                let _ = watcher.watch(source, RecursiveMode::NonRecursive);
+        if path.is_none() {
+            tracing::warn!(sha256, "no image in the search paths has this hash");
+
+            if shown {
+                self.load_status = Status::HashNotFound(sha256);
+                self.draw();
             }
+
+            return;
         }
-    }
 
-    fn register_timer(&mut self) {
-        let rotation_freq = self.entry.rotation_frequency;
-        let cosmic_bg_clone = self.entry.output.clone();
-        // set timer for rotation
-        if rotation_freq > 0 {
-            self.timer_token = self
-                .loop_handle
-                .insert_source(
-                    Timer::from_duration(Duration::from_secs(rotation_freq)),
-                    move |_, _, state: &mut CosmicBg| {
-                        let span = tracing::debug_span!("Wallpaper::timer");
-                        let _handle = span.enter();
+        let changed = match self.entry.source {
+            Source::Fallback(ref sources) => {
+                first_available(sources).is_some_and(|source| source != self.source)
+            }
+            _ => shown,
+        };
 
-                        let Some(item) = state
-                            .wallpapers
-                            .iter_mut()
-                            .find(|w| w.entry.output == cosmic_bg_clone)
-                        else {
-                            return TimeoutAction::Drop; // Drop if no item found for this timer
-                        };
+        if changed {
+            self.load_images();
+            self.clear_image();
+            self.draw();
+        }
+    }
 
-                        while let Some(next) = item.image_queue.pop_front() {
-                            item.current_source = Some(Source::Path(next.clone()));
-                            if let Err(err) = item.save_state() {
-                                error!("{err}");
-                            }
+    /// Redraws layers that are fading in until they are fully opaque.
+    fn register_fade_timer(&mut self) {
+        let output = self.entry.output.clone();
 
-                            item.image_queue.push_back(next);
-                            item.clear_image();
-                            item.draw();
+        self.fade_token = self
+            .loop_handle
+            .insert_source(
+                Timer::from_duration(FADE_FRAME_INTERVAL),
+                move |_, _, state: &mut CosmicBg| {
+                    let Some(item) = state
+                        .wallpapers
+                        .iter_mut()
+                        .find(|w| w.entry.output == output)
+                    else {
+                        return TimeoutAction::Drop;
+                    };
 
-                            return TimeoutAction::ToDuration(Duration::from_secs(rotation_freq));
+                    for layer in &mut item.layers {
+                        if layer.fade.start.is_some() {
+                            layer.needs_redraw = true;
                         }
+                    }
 
+                    item.draw();
+
+                    if item.layers.iter().any(|l| l.fade.start.is_some()) {
+                        TimeoutAction::ToDuration(FADE_FRAME_INTERVAL)
+                    } else {
+                        item.fade_token = None;
                         TimeoutAction::Drop
-                    },
-                )
-                .ok();
-        }
+                    }
+                },
+            )
+            .ok();
     }
 
-    fn clear_image(&mut self) {
-        self.current_image = None;
-        for l in &mut self.layers {
-            l.needs_redraw = true;
-        }
-    }
-}
+    pub fn load_images(&mut self) {
+        let mut image_queue = VecDeque::new();
 
-fn current_image(output: &str) -> Option<Source> {
-    let state = State::state().ok()?;
-    let mut wallpapers = State::get_entry(&state)
-        .unwrap_or_default()
-        .wallpapers
-        .into_iter();
+        // The order of the slideshow may change, such as by reshuffling.
+        self.preloaded.clear();
+        self.preloading.clear();
 
-    let wallpaper = if output == "all" {
-        wallpapers.next()
-    } else {
-        wallpapers.into_iter().find(|(name, _path)| name == output)
-    };
+        let mut unresolved = match self.entry.source {
+            Source::Fallback(ref sources) => unresolved_hashes(sources),
+            _ => Vec::new(),
+        };
 
-    wallpaper.map(|(_name, path)| path)
-}
+        self.source = match self.entry.source {
+            Source::Fallback(ref sources) => first_available(sources).unwrap_or_else(|| {
+                tracing::warn!("no source of the fallback chain is available");
+                Entry::fallback().source
+            }),
+            ref source => source.clone(),
+        };
 
-/// Decodes JPEG XL image files into `image::DynamicImage` via `jxl-oxide`.
-fn decode_jpegxl(path: &std::path::Path) -> eyre::Result<DynamicImage> {
-    let mut image = JxlImage::builder()
-        .open(path)
-        .map_err(|why| eyre!("failed to read image header: {why}"))?;
+        match self.source {
+            Source::Path(ref source) => {
+                let album = if self.entry.sampling_method == SamplingMethod::DailyAlbum {
+                    daily_album(source)
+                } else {
+                    None
+                };
 
-    image.request_color_encoding(EnumColourEncoding::srgb(
-        jxl_oxide::RenderingIntent::Relative,
-    ));
+                let scan = self.sources.scan(album.as_deref().unwrap_or(source));
 
-    let render = image
-        .render_frame(0)
-        .map_err(|why| eyre!("failed to render image frame: {why}"))?;
+                self.load_status = if !source.exists() {
+                    Status::SourceMissing(source.clone())
+                } else if scan.images.borrow().is_empty() {
+                    Status::NoImages(source.clone())
+                } else {
+                    Status::Ok
+                };
+                if self.entry.sampling_method == SamplingMethod::ByDate {
+                    let today = crate::schedule::local_date();
+                    let image = dated_image(&scan.images.borrow(), today);
+                    if image.is_none() && self.load_status == Status::Ok {
+                        self.load_status = Status::NoImages(source.clone());
+                    }
+                    image_queue.extend(image);
+                } else {
+                    image_queue.extend(scan.images.borrow().iter().cloned());
+                }
+                self.scan = Some(scan);
+            }
 
-    let framebuffer = render.image_all_channels();
+            Source::Archive(ref archive) => {
+                self.scan = None;
 
-    match image.pixel_format() {
-        PixelFormat::Graya => GrayAlphaImage::from_raw(
-            framebuffer.width() as u32,
-            framebuffer.height() as u32,
-            framebuffer
-                .buf()
-                .iter()
-                .map(|x| x * 255. + 0.5)
-                .map(|x| x as u8)
-                .collect::<Vec<_>>(),
-        )
-        .map(DynamicImage::ImageLumaA8)
-        .ok_or_eyre("Can't decode gray alpha buffer"),
-        PixelFormat::Gray => GrayImage::from_raw(
-            framebuffer.width() as u32,
-            framebuffer.height() as u32,
-            framebuffer
-                .buf()
-                .iter()
-                .map(|x| x * 255. + 0.5)
-                .map(|x| x as u8)
-                .collect::<Vec<_>>(),
+                match crate::archive::list(archive) {
+                    Ok(names) => {
+                        self.load_status = if names.is_empty() {
+                            Status::NoImages(archive.clone())
+                        } else {
+                            Status::Ok
+                        };
+                        image_queue.extend(names.iter().map(|name| archive.join(name)));
+                    }
+
+                    Err(why) => {
+                        tracing::warn!(?why, "could not read archive: {}", archive.display());
+                        self.load_status = Status::SourceMissing(archive.clone());
+                    }
+                }
+            }
+
+            Source::Hashed {
+                ref sha256,
+                ref search_paths,
+            } => {
+                self.scan = None;
+
+                self.load_status = Status::Ok;
+
+                // The wallpaper is loaded again once the image is located.
+                match crate::hash::cached(sha256) {
+                    Some(path) => image_queue.push_back(path),
+                    None => unresolved.push((sha256.clone(), search_paths.clone())),
+                }
+            }
+
+            Source::None => {
+                self.scan = None;
+                self.current_source = Some(Source::None);
+                self.clear_image();
+            }
+
+            Source::Color(ref c) => {
+                self.current_source = Some(Source::Color(c.clone()));
+            }
+
+            Source::Blend { .. } | Source::Split { .. } => {
+                self.current_source = Some(self.source.clone());
+            }
+
+            Source::AutoMatchPrimary => {
+                // The color is set once the primary output's wallpaper is known.
+                self.scan = None;
+                self.current_source = None;
+                self.loop_handle
+                    .insert_idle(|state: &mut CosmicBg| state.match_primary_colors());
+            }
+
+            Source::Clock { .. } => {
+                self.scan = None;
+                self.current_source = Some(self.source.clone());
+                if self.clock_token.is_none() {
+                    self.register_clock_timer();
+                }
+            }
+
+            Source::Fallback(_) => unreachable!("fallback chains are resolved above"),
+        };
+
+        for (sha256, search_paths) in unresolved {
+            self.resolve_hash(sha256, search_paths);
+        }
+
+        if matches!(
+            self.source,
+            Source::Path(_) | Source::Archive(_) | Source::Hashed { .. }
+        ) {
+            image_queue.retain(|path| !self.excludes(path));
+
+            if image_queue.len() > 1 {
+                order(image_queue.make_contiguous(), self.entry.sampling_method);
+
+                // If a wallpaper from this slideshow was previously set, resume with that wallpaper.
+                if let Some(Source::Path(last_path)) = self.saved_source() {
+                    if image_queue.contains(&last_path) {
+                        while let Some(path) = image_queue.pop_front() {
+                            if path == last_path {
+                                image_queue.push_front(path);
+                                break;
+                            }
+
+                            image_queue.push_back(path);
+                        }
+                    }
+                }
+            }
+
+            image_queue.pop_front().map(|current_image_path| {
+                self.remember(&current_image_path);
+                self.current_source = Some(Source::Path(current_image_path.clone()));
+                image_queue.push_back(current_image_path);
+            });
+
+            // A slideshow stays pinned if it resumed with the image it was pinned
+            // to, which it didn't if the image was removed.
+            self.pinned = self.is_pinned_in_state()
+                && self.saved_source().as_ref() == self.current_source.as_ref();
+        } else {
+            self.pinned = false;
+        }
+
+        if !self.pinned && self.is_pinned_in_state() {
+            tracing::info!(
+                output = self.entry.output,
+                "pinned image is no longer in the slideshow, unpinning"
+            );
+            self.save_pinned();
+        }
+
+        if let Err(err) = self.save_state() {
+            error!("{err}");
+        }
+        self.image_queue = image_queue;
+
+        if matches!(
+            self.entry.sampling_method,
+            SamplingMethod::DailyAlbum | SamplingMethod::ByDate
+        ) && matches!(self.source, Source::Path(_))
+        {
+            self.register_album_timer();
+        }
+
+        if matches!(self.entry.source, Source::Fallback(_)) && self.fallback_token.is_none() {
+            self.register_fallback_timer();
+        }
+    }
+
+    /// The source saved for the output of the wallpaper, which is found by its
+    /// display once the wallpaper has a layer.
+    fn saved_source(&self) -> Option<Source> {
+        let state = State::state().ok()?;
+        let state = State::get_entry(&state).unwrap_or_else(|(_, state)| state);
+
+        if let Some(layer) = self.layers.first() {
+            let name = layer.output_info.name.as_deref().unwrap_or_default();
+            let display = crate::output::model_key(&layer.output_info);
+            return state.wallpaper(name, &display).cloned();
+        }
+
+        let mut wallpapers = state.wallpapers.into_iter();
+
+        let wallpaper = if self.entry.output == "all" {
+            wallpapers.next()
+        } else {
+            wallpapers.find(|(name, _path)| *name == self.entry.output)
+        };
+
+        wallpaper.map(|(_name, path)| path)
+    }
+
+    /// Records the status of the wallpaper for each of its outputs.
+    fn report_status(&mut self, status: &Status) {
+        for layer in &mut self.layers {
+            report_status(layer, status);
+        }
+    }
+
+    /// The source being shown, such as the current image of a slideshow.
+    pub fn current_source(&self) -> Option<&Source> {
+        self.current_source.as_ref()
+    }
+
+    /// Statistics of the wallpaper's draws.
+    pub fn stats(&self) -> &crate::overlay::Stats {
+        &self.draw_stats
+    }
+
+    /// Whether an image is hidden from the slideshow by the entry's patterns.
+    pub fn excludes(&self, path: &Path) -> bool {
+        excludes(&self.entry.exclude, path)
+    }
+
+    /// Whether the wallpaper's images come from the given source path.
+    pub fn uses_source_path(&self, path: &Path) -> bool {
+        self.scan.as_ref().is_some_and(|scan| scan.source == path)
+    }
+
+    /// Reloads the images of a daily album slideshow at midnight, to move on
+    /// to the album of the next day.
+    fn register_album_timer(&mut self) {
+        if let Some(token) = self.album_token.take() {
+            self.loop_handle.remove(token);
+        }
+
+        let output = self.entry.output.clone();
+
+        self.album_token = self
+            .loop_handle
+            .insert_source(
+                Timer::from_duration(crate::schedule::until_midnight()),
+                move |_, _, state: &mut CosmicBg| {
+                    let Some(item) = state
+                        .wallpapers
+                        .iter_mut()
+                        .find(|w| w.entry.output == output)
+                    else {
+                        return TimeoutAction::Drop;
+                    };
+
+                    // Loading images registers the timer of the next day.
+                    item.album_token = None;
+                    item.load_images();
+                    item.clear_image();
+                    item.draw();
+
+                    TimeoutAction::Drop
+                },
+            )
+            .ok();
+    }
+
+    /// Checks the sources of a fallback chain periodically, to switch to an
+    /// earlier source once it becomes available, such as a mounted drive, or to
+    /// a later one if the current source goes missing.
+    fn register_fallback_timer(&mut self) {
+        let output = self.entry.output.clone();
+
+        self.fallback_token = self
+            .loop_handle
+            .insert_source(
+                Timer::from_duration(FALLBACK_CHECK_INTERVAL),
+                move |_, _, state: &mut CosmicBg| {
+                    let Some(item) = state
+                        .wallpapers
+                        .iter_mut()
+                        .find(|w| w.entry.output == output)
+                    else {
+                        return TimeoutAction::Drop;
+                    };
+
+                    let unresolved = match item.entry.source {
+                        Source::Fallback(ref sources) => unresolved_hashes(sources),
+                        _ => Vec::new(),
+                    };
+
+                    for (sha256, search_paths) in unresolved {
+                        item.resolve_hash(sha256, search_paths);
+                    }
+
+                    let changed = match item.entry.source {
+                        Source::Fallback(ref sources) => first_available(sources)
+                            .map_or(item.source != Entry::fallback().source, |source| {
+                                source != item.source
+                            }),
+
+                        _ => {
+                            item.fallback_token = None;
+                            return TimeoutAction::Drop;
+                        }
+                    };
+
+                    if changed {
+                        tracing::debug!(output, "source of fallback chain changed");
+                        item.load_images();
+                        item.clear_image();
+                        item.draw();
+                    }
+
+                    TimeoutAction::ToDuration(FALLBACK_CHECK_INTERVAL)
+                },
+            )
+            .ok();
+    }
+
+    /// Whether the wallpaper is shown on an output, by its name or by the output
+    /// of its entry.
+    #[must_use]
+    pub fn shows_output(&self, output: &str) -> bool {
+        self.entry.output == output
+            || self
+                .layers
+                .iter()
+                .any(|layer| layer.output_info.name.as_deref() == Some(output))
+    }
+
+    /// Keeps showing the current image of the slideshow, across restarts, until
+    /// it is unpinned or another image is shown.
+    pub fn pin(&mut self) {
+        if !matches!(self.current_source, Some(Source::Path(_))) || self.image_queue.len() < 2 {
+            tracing::debug!(output = self.entry.output, "only slideshows can be pinned");
+            return;
+        }
+
+        self.pinned = true;
+        self.save_pinned();
+    }
+
+    /// Resumes the slideshow from its current image.
+    pub fn unpin(&mut self) {
+        if self.pinned {
+            self.pinned = false;
+            self.save_pinned();
+        }
+    }
+
+    fn is_pinned_in_state(&self) -> bool {
+        State::state()
+            .map(|helper| State::get_entry(&helper).unwrap_or_else(|(_, state)| state))
+            .is_ok_and(|state| state.is_pinned(&self.entry.output))
+    }
+
+    /// Records whether the slideshow is pinned, which the saved source of its
+    /// outputs is pinned to.
+    fn save_pinned(&self) {
+        let result = State::state().and_then(|helper| {
+            let mut state = State::get_entry(&helper).unwrap_or_else(|(_, state)| state);
+            state.set_pinned(&self.entry.output, self.pinned);
+            state.write_entry(&helper)
+        });
+
+        if let Err(err) = result {
+            error!("{err}");
+        }
+    }
+
+    /// Whether the wallpaper shows a color matching the primary output's.
+    #[must_use]
+    pub fn matches_primary(&self) -> bool {
+        self.source == Source::AutoMatchPrimary
+    }
+
+    /// The color that matches the wallpaper, which is its own color, or else the
+    /// average color of its image.
+    #[must_use]
+    pub fn matching_color(&self) -> Option<Color> {
+        matching_color(self.current_source.as_ref(), self.current_image.as_ref())
+    }
+
+    /// Shows the color matching the primary output's wallpaper.
+    pub fn set_matched_color(&mut self, color: Color) {
+        let source = Source::Color(color);
+        if self.current_source.as_ref() == Some(&source) {
+            return;
+        }
+
+        self.current_source = Some(source);
+        if let Err(err) = self.save_state() {
+            error!("{err}");
+        }
+        self.clear_image();
+        self.draw();
+    }
+
+    /// Redraws a clock at the start of every minute, until its source changes.
+    fn register_clock_timer(&mut self) {
+        let output = self.entry.output.clone();
+
+        self.clock_token = self
+            .loop_handle
+            .insert_source(
+                Timer::from_duration(crate::clock::until_next_minute()),
+                move |_, _, state: &mut CosmicBg| {
+                    let Some(item) = state
+                        .wallpapers
+                        .iter_mut()
+                        .find(|w| w.entry.output == output)
+                    else {
+                        return TimeoutAction::Drop;
+                    };
+
+                    if !matches!(item.source, Source::Clock { .. }) {
+                        item.clock_token = None;
+                        return TimeoutAction::Drop;
+                    }
+
+                    item.clear_image();
+                    item.draw();
+
+                    TimeoutAction::ToDuration(crate::clock::until_next_minute())
+                },
+            )
+            .ok();
+    }
+
+    /// Orders the slideshow again by its sampling method after images were added
+    /// or removed, keeping the current image at the back of the queue, so that
+    /// it continues with the image that follows the current one.
+    pub fn reorder_queue(&mut self) {
+        let current = match self.current_source {
+            Some(Source::Path(ref current)) => Some(current.as_path()),
+            _ => None,
+        };
+
+        reorder(&mut self.image_queue, self.entry.sampling_method, current);
+
+        // Preloaded images were decoded for the previous order.
+        self.preloaded.clear();
+        self.preloading.clear();
+    }
+
+    /// Redraws the current image if it is one of the modified `paths`.
+    pub fn reload_image(&mut self, paths: &[PathBuf]) {
+        let Some(Source::Path(ref current)) = self.current_source else {
+            return;
+        };
+
+        let modified = paths
+            .iter()
+            .any(|p| p == current || p.canonicalize().is_ok_and(|p| p == *current));
+
+        if modified {
+            tracing::debug!(?current, "reloading modified image");
+            self.clear_image();
+            self.draw();
+        }
+    }
+
+    fn register_timer(&mut self) {
+        let rotation_freq = self.entry.rotation_frequency;
+        let cosmic_bg_clone = self.entry.output.clone();
+        // set timer for rotation
+        if rotation_freq > 0 {
+            let first = match self.current_source {
+                Some(Source::Path(ref path)) => {
+                    image_duration(path, Duration::from_secs(rotation_freq))
+                }
+                _ => Duration::from_secs(rotation_freq),
+            };
+
+            self.timer_token = self
+                .loop_handle
+                .insert_source(
+                    Timer::from_duration(first),
+                    move |_, _, state: &mut CosmicBg| {
+                        let span = tracing::debug_span!("Wallpaper::timer");
+                        let _handle = span.enter();
+
+                        let Some(item) = state
+                            .wallpapers
+                            .iter_mut()
+                            .find(|w| w.entry.output == cosmic_bg_clone)
+                        else {
+                            return TimeoutAction::Drop; // Drop if no item found for this timer
+                        };
+
+                        let current = match item.current_source {
+                            Some(Source::Path(ref path)) => Some(path.as_path()),
+                            _ => None,
+                        };
+
+                        match tick(&item.image_queue, current, item.pinned) {
+                            Tick::Keep => {
+                                return TimeoutAction::ToDuration(Duration::from_secs(
+                                    rotation_freq,
+                                ));
+                            }
+                            Tick::Resume => {
+                                tracing::info!(
+                                    output = item.entry.output,
+                                    "pinned image was removed, resuming the slideshow"
+                                );
+                                item.unpin();
+                            }
+                            Tick::Rotate => (),
+                        }
+
+                        while let Some(next) = item.next_image() {
+                            item.remember(&next);
+                            let duration =
+                                image_duration(&next, Duration::from_secs(rotation_freq));
+                            item.current_source = Some(Source::Path(next));
+                            if let Err(err) = item.save_state() {
+                                error!("{err}");
+                            }
+
+                            item.clear_image();
+
+                            let draw_start = Instant::now();
+                            item.draw();
+
+                            return TimeoutAction::ToDuration(rotation_interval(
+                                duration,
+                                draw_start.elapsed(),
+                            ));
+                        }
+
+                        TimeoutAction::Drop
+                    },
+                )
+                .ok();
+        }
+    }
+
+    /// Rotates the slideshow to the image that follows the current one in the
+    /// direction of the sampling method, and returns its path.
+    fn next_image(&mut self) -> Option<PathBuf> {
+        next_image(
+            &mut self.image_queue,
+            &mut self.reversing,
+            self.entry.sampling_method,
+            &self.recent,
+            self.entry.no_repeat_window,
+        )
+    }
+
+    /// Records an image as shown, forgetting those beyond the no-repeat window.
+    fn remember(&mut self, path: &Path) {
+        self.recent.push_back(path.to_path_buf());
+        while self.recent.len() > self.entry.no_repeat_window {
+            self.recent.pop_front();
+        }
+    }
+
+    /// Whether an image was shown within the no-repeat window.
+    fn is_recent(&self, path: &Path) -> bool {
+        is_recent(
+            path,
+            self.image_queue.len(),
+            &self.recent,
+            self.entry.no_repeat_window,
+        )
+    }
+
+    /// Rotates the slideshow by one image forward, or backward if `reverse`.
+    fn step(&mut self, reverse: bool) -> Option<PathBuf> {
+        step(&mut self.image_queue, reverse)
+    }
+
+    /// Shows the next image of the slideshow.
+    pub fn show_next(&mut self) {
+        if let Some(path) = self.next_image() {
+            self.show(path);
+        }
+    }
+
+    /// Shows the previous image of the slideshow.
+    pub fn show_previous(&mut self) {
+        if let Some(path) = self.step(!self.reversing) {
+            self.show(path);
+        }
+    }
+
+    /// Shows a random image of the slideshow other than the current one.
+    pub fn show_random(&mut self) {
+        let len = self.image_queue.len();
+        if len < 2 {
+            return;
+        }
+
+        // The current image is at the back of the queue.
+        let candidates = (0..len - 1)
+            .filter(|&index| !self.is_recent(&self.image_queue[index]))
+            .collect::<Vec<_>>();
+
+        let index = candidates
+            .choose(&mut thread_rng())
+            .copied()
+            .unwrap_or_else(|| thread_rng().gen_range(0..len - 1));
+
+        self.image_queue.rotate_left(index + 1);
+
+        if let Some(path) = self.image_queue.back().cloned() {
+            self.show(path);
+        }
+    }
+
+    fn show(&mut self, path: PathBuf) {
+        // Showing another image by hand ends a pin.
+        self.unpin();
+        self.remember(&path);
+        self.current_source = Some(Source::Path(path));
+        if let Err(err) = self.save_state() {
+            error!("{err}");
+        }
+
+        self.clear_image();
+        self.draw();
+    }
+
+    /// Replaces the entry of the wallpaper and redraws its layers, reloading its
+    /// images only if their source or order changed.
+    ///
+    /// Returns `false` without changes if the placement of the layers changed,
+    /// which requires them to be recreated.
+    pub fn set_entry(&mut self, entry: Entry) -> bool {
+        let change = entry_change(&self.entry, &entry);
+        if change == EntryChange::Placement {
+            return false;
+        }
+
+        let reload = change == EntryChange::Source;
+        self.entry = entry;
+
+        if reload {
+            for token in [self.timer_token.take(), self.album_token.take()]
+                .into_iter()
+                .flatten()
+            {
+                self.loop_handle.remove(token);
+            }
+
+            self.current_source = None;
+            self.load_images();
+            self.register_timer();
+            self.clear_image();
+        } else {
+            // The decoded images are kept, and only scaled again.
+            for layer in &mut self.layers {
+                layer.fade.cancel();
+                layer.needs_redraw = true;
+            }
+        }
+
+        self.draw();
+        true
+    }
+
+    /// Redraws the wallpaper after the system resumed from suspend, selecting the
+    /// images of the day again in case the day changed while suspended.
+    pub fn resume(&mut self) {
+        if matches!(
+            self.entry.sampling_method,
+            SamplingMethod::DailyAlbum | SamplingMethod::ByDate
+        ) && matches!(self.source, Source::Path(_))
+        {
+            self.load_images();
+            self.clear_image();
+        } else {
+            for layer in &mut self.layers {
+                layer.fade.image = None;
+                layer.needs_redraw = true;
+            }
+        }
+
+        self.draw();
+    }
+
+    /// Shifts a parallax wallpaper to follow the pointer.
+    pub fn set_parallax_offset(&mut self, offset: (f32, f32)) {
+        if self.entry.parallax_strength <= 0.0
+            || self.entry.scaling_mode != ScalingMode::Zoom
+            || self.parallax_offset == offset
+        {
+            return;
+        }
+
+        self.parallax_offset = offset;
+
+        // Enlarged zooms are only cropped again, and the other layers, such as
+        // the sides of splits, are redrawn.
+        let mut redraw = false;
+        for layer in &mut self.layers {
+            if layer.zoom_crop.is_some() {
+                crate::draw::crop(layer, offset);
+                layer.layer.wl_surface().commit();
+            } else {
+                layer.needs_redraw = true;
+                redraw = true;
+            }
+        }
+
+        if redraw {
+            self.draw();
+        }
+    }
+
+    fn clear_image(&mut self) {
+        self.current_image = None;
+        self.overlay_image = None;
+        for l in &mut self.layers {
+            l.fade.cancel();
+            l.needs_redraw = true;
+        }
+    }
+}
+
+/// Records the status of the wallpaper of a layer's output, if it changed.
+fn report_status(layer: &mut CosmicBgLayer, status: &Status) {
+    if layer.status.as_ref() == Some(status) {
+        return;
+    }
+
+    if *status != Status::Ok {
+        tracing::warn!(?status, "wallpaper could not be displayed");
+    }
+
+    let output = layer.output_info.name.as_deref().unwrap_or_default();
+    let result = State::state().and_then(|state| State::set_status(&state, output, status));
+
+    match result {
+        Ok(()) => layer.status = Some(status.clone()),
+        Err(why) => tracing::error!(?why, output, "failed to record wallpaper status"),
+    }
+}
+
+/// Rotates a slideshow queue to the image that follows the current one in the
+/// direction of the sampling method, and returns its path.
+///
+/// The current image is kept at the back of the queue. A ping-pong slideshow
+/// flips direction upon reaching the first or last image, instead of wrapping.
+fn next_image(
+    queue: &mut VecDeque<PathBuf>,
+    reversing: &mut bool,
+    sampling_method: SamplingMethod,
+    recent: &VecDeque<PathBuf>,
+    no_repeat_window: usize,
+) -> Option<PathBuf> {
+    if sampling_method == SamplingMethod::PingPong && queue.len() > 1 {
+        let current = queue.back();
+        let order = |a: &&PathBuf, b: &&PathBuf| a.to_string_lossy().cmp(&b.to_string_lossy());
+        let end = if *reversing {
+            queue.iter().min_by(order)
+        } else {
+            queue.iter().max_by(order)
+        };
+
+        if current == end {
+            *reversing = !*reversing;
+        }
+    }
+
+    if sampling_method == SamplingMethod::Random {
+        // The current image is at the back of the queue. Images that were
+        // shown recently are skipped to the back with it.
+        let len = queue.len();
+        let index = queue
+            .range(..len.saturating_sub(1))
+            .position(|path| !is_recent(path, len, recent, no_repeat_window));
+
+        if let Some(index) = index {
+            queue.rotate_left(index + 1);
+            return queue.back().cloned();
+        }
+    }
+
+    step(queue, *reversing)
+}
+
+/// Whether an image is among the `recent` images of a slideshow queue of
+/// `queue_len` images. The window is ignored if it covers the whole slideshow,
+/// which would leave no image.
+fn is_recent(
+    path: &Path,
+    queue_len: usize,
+    recent: &VecDeque<PathBuf>,
+    no_repeat_window: usize,
+) -> bool {
+    (1..queue_len).contains(&no_repeat_window) && recent.iter().any(|recent| recent == path)
+}
+
+/// Rotates a slideshow queue by one image forward, or backward if `reverse`.
+fn step(queue: &mut VecDeque<PathBuf>, reverse: bool) -> Option<PathBuf> {
+    if reverse {
+        let current = queue.pop_back()?;
+        queue.push_front(current);
+        queue.back().cloned()
+    } else {
+        let next = queue.pop_front()?;
+        queue.push_back(next.clone());
+        Some(next)
+    }
+}
+
+/// The album of today within a directory of albums, which are its immediate
+/// subdirectories that contain files. Returns `None` for a directory without
+/// albums, which is instead rotated through as a whole.
+fn daily_album(source: &Path) -> Option<PathBuf> {
+    let mut albums = source
+        .read_dir()
+        .ok()?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_dir()
+                && path.read_dir().is_ok_and(|mut dir| {
+                    dir.any(|entry| entry.is_ok_and(|entry| entry.path().is_file()))
+                })
+        })
+        .collect::<Vec<_>>();
+
+    albums.sort();
+
+    let album = album_for_day(albums.len(), crate::schedule::local_day_of_year())?;
+    Some(albums.swap_remove(album))
+}
+
+/// Index of the album shown on a day of the year.
+fn album_for_day(albums: usize, day: u32) -> Option<usize> {
+    (albums > 0).then(|| day as usize % albums)
+}
+
+/// The image named by the latest date up to `today`, as a year, month and day,
+/// among images named by dates as `YYYY-MM-DD`. Other images are ignored.
+fn dated_image(images: &[PathBuf], today: (i32, u32, u32)) -> Option<PathBuf> {
+    images
+        .iter()
+        .filter_map(|path| {
+            let date = parse_date(path.file_stem()?.to_str()?)?;
+            (date <= today).then_some((date, path))
+        })
+        .max_by_key(|&(date, _)| date)
+        .map(|(_, path)| path.clone())
+}
+
+/// Parses a `YYYY-MM-DD` date into its year, month and day.
+fn parse_date(name: &str) -> Option<(i32, u32, u32)> {
+    let mut parts = name.splitn(3, '-');
+    let (year, month, day) = (parts.next()?, parts.next()?, parts.next()?);
+
+    if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+        return None;
+    }
+
+    let digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+    if !(digits(year) && digits(month) && digits(day)) {
+        return None;
+    }
+
+    let (month, day) = (month.parse().ok()?, day.parse().ok()?);
+    ((1..=12).contains(&month) && (1..=31).contains(&day)).then_some((
+        year.parse().ok()?,
+        month,
+        day,
+    ))
+}
+
+/// The next `count` images of a slideshow queue, whose current image is at the
+/// back, in the order that they will be shown.
+fn upcoming_images(queue: &VecDeque<PathBuf>, reverse: bool, count: usize) -> Vec<PathBuf> {
+    let count = count.min(queue.len().saturating_sub(1));
+
+    if reverse {
+        queue.iter().rev().skip(1).take(count).cloned().collect()
+    } else {
+        queue.iter().take(count).cloned().collect()
+    }
+}
+
+/// Whether an image matches any of the patterns, which are matched against its
+/// whole path if they contain a `/`, or else against its file name.
+fn excludes(patterns: &[PathBuf], path: &Path) -> bool {
+    patterns.iter().any(|pattern| {
+        let pattern = pattern.to_string_lossy();
+        if pattern.contains('/') {
+            glob_match(&pattern, &path.to_string_lossy())
+        } else {
+            path.file_name()
+                .is_some_and(|name| glob_match(&pattern, &name.to_string_lossy()))
+        }
+    })
+}
+
+/// Whether `text` matches a `pattern` where `*` matches any characters, including
+/// `/`, and `?` any one character.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+
+    let (mut p, mut t) = (0, 0);
+    // Positions after the last `*` and of the text it has matched up to, from
+    // which matching backtracks by letting the `*` match one more character.
+    let mut star = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            p += 1;
+            star = Some((p, t));
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p;
+            t = star_t + 1;
+            star = Some((star_p, t));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Hashed sources of a fallback chain whose images haven't been located yet.
+fn unresolved_hashes(sources: &[Source]) -> Vec<(String, Vec<PathBuf>)> {
+    sources
+        .iter()
+        .flat_map(|source| match source {
+            Source::Fallback(sources) => unresolved_hashes(sources),
+
+            Source::Hashed {
+                sha256,
+                search_paths,
+            } if crate::hash::cached(sha256).is_none() => {
+                vec![(sha256.clone(), search_paths.clone())]
+            }
+
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+/// Time between checks of the sources of a fallback chain.
+const FALLBACK_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The first source of a fallback chain that is available, descending into
+/// nested chains. Hashed sources are only available once their image was located.
+fn first_available(sources: &[Source]) -> Option<Source> {
+    sources.iter().find_map(|source| match source {
+        Source::Fallback(sources) => first_available(sources),
+
+        Source::Hashed { sha256, .. } => crate::hash::cached(sha256).map(|_| source.clone()),
+
+        _ => Config::validate_source(source)
+            .is_ok()
+            .then(|| source.clone()),
+    })
+}
+
+/// Time between frames of the startup fade.
+const FADE_FRAME_INTERVAL: Duration = Duration::from_millis(33);
+
+/// Startup fade of a layer, which eases its first draw in from black.
+#[derive(Debug, Default)]
+pub struct Fade {
+    /// When the layer began fading in, if it is still fading.
+    start: Option<Instant>,
+    /// Scaled image reused across the frames of the fade.
+    image: Option<DynamicImage>,
+}
+
+impl Fade {
+    /// Opacity of the layer now, which is full once the fade ended.
+    fn opacity(&self, fade_duration: Duration) -> f32 {
+        self.start
+            .map_or(1.0, |start| fade_opacity(start.elapsed(), fade_duration))
+    }
+
+    /// Ends the fade, so that a changed image is shown at full opacity rather
+    /// than fading in from the frames of the previous one.
+    fn cancel(&mut self) {
+        self.start = None;
+        self.image = None;
+    }
+}
+
+/// Opacity of a layer `elapsed` into its startup fade.
+fn fade_opacity(elapsed: Duration, fade_duration: Duration) -> f32 {
+    if elapsed >= fade_duration {
+        return 1.0;
+    }
+
+    let t = elapsed.as_secs_f32() / fade_duration.as_secs_f32();
+    // Smoothstep easing
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// How an output's entry changed, from the change requiring the most work.
+#[derive(Debug, PartialEq, Eq)]
+enum EntryChange {
+    /// The placement of the layers changed, which requires them to be recreated.
+    Placement,
+    /// The images of the slideshow changed, which are loaded again.
+    Source,
+    /// Only how the images are drawn changed, which scales them again.
+    Appearance,
+}
+
+fn entry_change(old: &Entry, new: &Entry) -> EntryChange {
+    let margins = |entry: &Entry| {
+        (
+            entry.margin_top,
+            entry.margin_right,
+            entry.margin_bottom,
+            entry.margin_left,
+        )
+    };
+
+    if old.anchor != new.anchor || margins(old) != margins(new) {
+        EntryChange::Placement
+    } else if old.source != new.source
+        || old.exclude != new.exclude
+        || old.sampling_method != new.sampling_method
+        || old.rotation_frequency != new.rotation_frequency
+    {
+        EntryChange::Source
+    } else {
+        EntryChange::Appearance
+    }
+}
+
+/// What a rotation tick of a slideshow does.
+#[derive(Debug, PartialEq, Eq)]
+enum Tick {
+    /// Keep showing the current image without redrawing it.
+    Keep,
+    /// Unpin the slideshow, whose pinned image was removed, and rotate it.
+    Resume,
+    /// Rotate to the next image.
+    Rotate,
+}
+
+/// What a rotation tick does for a slideshow of `queue` showing `current`.
+///
+/// A single image is already shown, so it keeps ticking without redrawing until
+/// the source gains another.
+fn tick(queue: &VecDeque<PathBuf>, current: Option<&Path>, pinned: bool) -> Tick {
+    if queue.len() < 2 {
+        return Tick::Keep;
+    }
+
+    if !pinned {
+        return Tick::Rotate;
+    }
+
+    if current.is_some_and(|current| queue.iter().any(|path| path == current)) {
+        Tick::Keep
+    } else {
+        Tick::Resume
+    }
+}
+
+/// Time until the next rotation, which is at least twice as long as the last
+/// draw took, so that slow decodes can't keep the event loop perpetually busy.
+fn rotation_interval(rotation_frequency: Duration, draw_duration: Duration) -> Duration {
+    let floor = draw_duration * 2;
+
+    if floor > rotation_frequency {
+        tracing::info!(
+            ?rotation_frequency,
+            ?draw_duration,
+            "rotation frequency is shorter than drawing allows, delaying next rotation"
+        );
+        return floor;
+    }
+
+    rotation_frequency
+}
+
+/// Orders the images of a slideshow by its sampling method.
+fn order(images: &mut [PathBuf], sampling_method: SamplingMethod) {
+    match sampling_method {
+        SamplingMethod::Alphanumeric
+        | SamplingMethod::PingPong
+        | SamplingMethod::DailyAlbum
+        | SamplingMethod::ByDate => {
+            images.sort_by(|a, b| a.to_string_lossy().cmp(&b.to_string_lossy()));
+        }
+        SamplingMethod::Reverse => {
+            images.sort_by(|a, b| b.to_string_lossy().cmp(&a.to_string_lossy()));
+        }
+        SamplingMethod::Random => images.shuffle(&mut thread_rng()),
+    }
+}
+
+/// Orders a slideshow queue again by its sampling method, keeping the current
+/// image at the back. Sorted slideshows continue with the image that follows
+/// the current one, and random slideshows with the new shuffle.
+fn reorder(queue: &mut VecDeque<PathBuf>, sampling_method: SamplingMethod, current: Option<&Path>) {
+    if queue.len() < 2 {
+        return;
+    }
+
+    order(queue.make_contiguous(), sampling_method);
+
+    let Some(position) = current.and_then(|current| queue.iter().position(|p| p == current)) else {
+        return;
+    };
+
+    if sampling_method == SamplingMethod::Random {
+        if let Some(current) = queue.remove(position) {
+            queue.push_back(current);
+        }
+    } else {
+        queue.rotate_left(position + 1);
+    }
+}
+
+/// The color matching a wallpaper showing `source`, which is its own color, or
+/// else the average color of its decoded `image`.
+fn matching_color(source: Option<&Source>, image: Option<&DynamicImage>) -> Option<Color> {
+    match source {
+        Some(Source::Color(color)) => Some(color.clone()),
+        _ => image.map(|image| Color::Single(crate::colored::average(image))),
+    }
+}
+
+/// Shortest time that an image with a duration of its own is shown for.
+const MIN_IMAGE_DURATION: Duration = Duration::from_secs(1);
+
+/// Time to show an image of a slideshow for, which is read in seconds from a
+/// sidecar file named after the image with a `.duration` suffix, such as
+/// `sunset.jpg.duration`, or else `default`.
+fn image_duration(path: &Path, default: Duration) -> Duration {
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".duration");
+
+    let Ok(contents) = std::fs::read_to_string(&sidecar) else {
+        return default;
+    };
+
+    match contents.trim().parse::<u64>() {
+        Ok(seconds) => Duration::from_secs(seconds).max(MIN_IMAGE_DURATION),
+        Err(why) => {
+            tracing::warn!(
+                ?why,
+                "invalid image duration in {}",
+                sidecar.to_string_lossy()
+            );
+            default
+        }
+    }
+}
+
+/// Decodes an image from a path, logging the reason on failure.
+fn decode_image(path: &Path) -> Option<DynamicImage> {
+    if !path.exists() {
+        if let Some((archive, name)) = crate::archive::split(path) {
+            return decode_archive_image(&archive, &name);
+        }
+    }
+
+    match path.extension() {
+        Some(ext) if ext == "jxl" => match decode_jpegxl(path) {
+            Ok(image) => Some(image),
+            Err(why) => {
+                tracing::warn!(?why, "jpegl-xl image decode failed: {}", path.display());
+                None
+            }
+        },
+
+        _ => match ImageReader::open(path) {
+            Ok(img) => {
+                let image = decode_reader(img);
+                if image.is_none() {
+                    tracing::warn!("could not decode image: {}", path.display());
+                }
+                image
+            }
+            Err(_) => None,
+        },
+    }
+}
+
+/// A buffer size and scaling mode to scale the source of a wallpaper for.
+#[derive(Debug, PartialEq)]
+struct Target {
+    width: u32,
+    height: u32,
+    scaling_mode: ScalingMode,
+}
+
+/// Renders each target on the thread pool, so that the outputs of a wallpaper
+/// are scaled concurrently.
+fn render_targets<T: Send>(targets: &[Target], render: impl Fn(&Target) -> T + Sync) -> Vec<T> {
+    targets.par_iter().map(render).collect()
+}
+
+/// Renders the source of a wallpaper at the size of a target.
+///
+/// The images of path and blend sources are expected to be decoded already.
+/// Zoomed images that follow the pointer are shifted by the `parallax_offset`,
+/// or left enlarged for the viewport to crop without one.
+fn render(
+    entry: &Entry,
+    source: &Source,
+    current_image: Option<&DynamicImage>,
+    overlay_image: Option<&DynamicImage>,
+    parallax_offset: Option<(f32, f32)>,
+    target: &Target,
+) -> Option<DynamicImage> {
+    let Target {
+        width,
+        height,
+        ref scaling_mode,
+    } = *target;
+
+    match source {
+        // Images of archives and hashes are set as the current source by their
+        // paths, fallback chains by the source chosen from them, and matched
+        // colors by the color.
+        Source::Archive(_)
+        | Source::Hashed { .. }
+        | Source::Fallback(_)
+        | Source::AutoMatchPrimary => None,
+
+        // A transparent buffer, which some compositors require over no buffer at
+        // all, is stretched over the surface by the viewport.
+        Source::None => Some(DynamicImage::from(RgbaImage::new(1, 1))),
+
+        Source::Path(_) => Some(scale(
+            entry,
+            scaling_mode,
+            parallax_offset,
+            current_image?,
+            width,
+            height,
+        )),
+
+        Source::Clock {
+            format,
+            color,
+            background,
+        } => Some(DynamicImage::from(crate::clock::render(
+            &crate::clock::format(format),
+            *color,
+            *background,
+            width,
+            height,
+        ))),
+
+        Source::Color(Color::Single([ref r, ref g, ref b])) => Some(DynamicImage::from(
+            crate::colored::single([*r, *g, *b], width, height),
+        )),
+
+        Source::Color(Color::Gradient(ref gradient)) => {
+            match crate::colored::gradient(gradient, entry.gradient_blend_space, width, height) {
+                Ok(buffer) => Some(DynamicImage::from(buffer)),
+                Err(why) => {
+                    tracing::error!(?gradient, ?why, "color gradient in config is invalid");
+                    None
+                }
+            }
+        }
+
+        Source::Color(Color::Gradients(ref layers)) => {
+            match crate::colored::gradients(layers, entry.gradient_blend_space, width, height) {
+                Ok(buffer) => Some(DynamicImage::from(buffer)),
+                Err(why) => {
+                    tracing::error!(?layers, ?why, "color gradients in config are invalid");
+                    None
+                }
+            }
+        }
+
+        Source::Blend { mode, opacity, .. } => {
+            // Each image is scaled independently, so that differing aspect
+            // ratios are handled by the scaling mode before compositing.
+            let base = scale(
+                entry,
+                scaling_mode,
+                parallax_offset,
+                current_image?,
+                width,
+                height,
+            );
+            let overlay = scale(
+                entry,
+                scaling_mode,
+                parallax_offset,
+                overlay_image?,
+                width,
+                height,
+            );
+
+            Some(DynamicImage::from(crate::blend::blend(
+                &base, &overlay, *mode, *opacity,
+            )))
+        }
+
+        Source::Split { left, right, ratio } => {
+            let left_width = (width as f32 * ratio.clamp(0.0, 1.0)).round() as u32;
+
+            // Each side is scaled to its part of the surface on its own.
+            let side = |source: &Source, image: Option<&DynamicImage>, width: u32| {
+                if !matches!(source, Source::Path(_) | Source::Color(_)) {
+                    tracing::warn!(?source, "sides of a split must be single images or colors");
+                    return None;
+                }
+
+                let target = Target {
+                    width,
+                    height,
+                    scaling_mode: scaling_mode.clone(),
+                };
+
+                let offset = parallax_offset.unwrap_or_default();
+                render(entry, source, image, None, Some(offset), &target)
+            };
+
+            if left_width == 0 {
+                return side(&**right, overlay_image, width);
+            } else if left_width >= width {
+                return side(&**left, current_image, width);
+            }
+
+            let left = side(&**left, current_image, left_width)?;
+            let right = side(&**right, overlay_image, width - left_width)?;
+
+            let mut canvas = RgbaImage::new(width, height);
+            image::imageops::replace(&mut canvas, &left.to_rgba8(), 0, 0);
+            image::imageops::replace(&mut canvas, &right.to_rgba8(), i64::from(left_width), 0);
+
+            Some(DynamicImage::ImageRgba8(canvas))
+        }
+    }
+}
+
+/// Decodes an image of a guessed format, converting it to sRGB if it embeds a
+/// color profile, and tone mapping it if it's high dynamic range.
+fn decode_reader<R: BufRead + Seek>(reader: ImageReader<R>) -> Option<DynamicImage> {
+    let mut decoder = reader.with_guessed_format().ok()?.into_decoder().ok()?;
+
+    // Images without a profile are assumed to be sRGB already.
+    let profile = match decoder.icc_profile() {
+        Ok(Some(bytes)) => match crate::icc::Profile::parse(&bytes) {
+            Ok(profile) => (!profile.is_srgb()).then_some(profile),
+            Err(crate::icc::Error::Unsupported(why)) => {
+                tracing::debug!(why, "not converting color profile");
+                None
+            }
+            Err(crate::icc::Error::Malformed(why)) => {
+                tracing::warn!(why, "skipping malformed color profile");
+                None
+            }
+        },
+        _ => None,
+    };
+
+    let image = DynamicImage::from_decoder(decoder).ok()?;
+    let image = match profile {
+        Some(profile) => crate::icc::to_srgb(image, &profile),
+        None => image,
+    };
+
+    Some(crate::effects::tone_map(image))
+}
+
+/// Decodes an image within an archive from its contents read into memory.
+fn decode_archive_image(archive: &Path, name: &str) -> Option<DynamicImage> {
+    let bytes = match crate::archive::read(archive, name) {
+        Ok(bytes) => bytes,
+        Err(why) => {
+            tracing::warn!(
+                ?why,
+                name,
+                "could not read image from {}",
+                archive.display()
+            );
+            return None;
+        }
+    };
+
+    let image = decode_reader(ImageReader::new(std::io::Cursor::new(bytes)));
+
+    if image.is_none() {
+        tracing::warn!(name, "could not decode image from {}", archive.display());
+    }
+
+    image
+}
+
+/// Scales an image to the layer size with the scaling mode of the layer's output.
+fn scale(
+    entry: &Entry,
+    scaling_mode: &ScalingMode,
+    parallax_offset: Option<(f32, f32)>,
+    img: &DynamicImage,
+    width: u32,
+    height: u32,
+) -> DynamicImage {
+    match *scaling_mode {
+        ScalingMode::Fit(color) => crate::scaler::fit(
+            img,
+            &color,
+            width,
+            height,
+            entry.fit_gravity,
+            entry.filter_method,
+        ),
+
+        ScalingMode::Zoom if entry.parallax_strength > 0.0 => match parallax_offset {
+            Some(offset) => crate::scaler::zoom_offset(
+                img,
+                width,
+                height,
+                entry.parallax_strength,
+                offset,
+                entry.filter_method,
+            ),
+            None => crate::scaler::zoom_enlarged(
+                img,
+                width,
+                height,
+                entry.parallax_strength,
+                entry.filter_method,
+            ),
+        },
+
+        ScalingMode::Zoom => crate::scaler::zoom(img, width, height, entry.filter_method),
+
+        ScalingMode::Stretch => crate::scaler::stretch(img, width, height, entry.filter_method),
+
+        ScalingMode::SmartFill { max_stretch } => {
+            crate::scaler::smart_fill(img, width, height, max_stretch, entry.filter_method)
+        }
+
+        ScalingMode::SmartBlur { threshold } => {
+            crate::scaler::smart_blur(img, width, height, threshold, entry.filter_method)
+        }
+    }
+}
+
+/// Decodes JPEG XL image files into `image::DynamicImage` via `jxl-oxide`.
+fn decode_jpegxl(path: &std::path::Path) -> eyre::Result<DynamicImage> {
+    let mut image = JxlImage::builder()
+        .open(path)
+        .map_err(|why| eyre!("failed to read image header: {why}"))?;
+
+    image.request_color_encoding(EnumColourEncoding::srgb(
+        jxl_oxide::RenderingIntent::Relative,
+    ));
+
+    let render = image
+        .render_frame(0)
+        .map_err(|why| eyre!("failed to render image frame: {why}"))?;
+
+    let framebuffer = render.image_all_channels();
+
+    match image.pixel_format() {
+        PixelFormat::Graya => GrayAlphaImage::from_raw(
+            framebuffer.width() as u32,
+            framebuffer.height() as u32,
+            framebuffer
+                .buf()
+                .iter()
+                .map(|x| x * 255. + 0.5)
+                .map(|x| x as u8)
+                .collect::<Vec<_>>(),
+        )
+        .map(DynamicImage::ImageLumaA8)
+        .ok_or_eyre("Can't decode gray alpha buffer"),
+        PixelFormat::Gray => GrayImage::from_raw(
+            framebuffer.width() as u32,
+            framebuffer.height() as u32,
+            framebuffer
+                .buf()
+                .iter()
+                .map(|x| x * 255. + 0.5)
+                .map(|x| x as u8)
+                .collect::<Vec<_>>(),
         )
         .map(DynamicImage::ImageLuma8)
         .ok_or_eyre("Can't decode gray buffer"),
@@ -468,3 +2204,531 @@ fn decode_jpegxl(path: &std::path::Path) -> eyre::Result<DynamicImage> {
         PixelFormat::Cmyka => Err(eyre!("unsupported pixel format: CMYKA")),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Images shown by a slideshow of four images, starting with the first one.
+    fn ticks(sampling_method: SamplingMethod, count: usize) -> Vec<String> {
+        let mut images = ["a", "b", "c", "d"].map(PathBuf::from).to_vec();
+        order(&mut images, sampling_method);
+
+        let mut queue = VecDeque::from(images);
+        let mut reversing = false;
+        let recent = VecDeque::new();
+
+        std::iter::once(step(&mut queue, false))
+            .chain(
+                (0..count)
+                    .map(|_| next_image(&mut queue, &mut reversing, sampling_method, &recent, 0)),
+            )
+            .map(|path| path.unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn forward_and_reverse_wrap_around() {
+        assert_eq!(
+            ticks(SamplingMethod::Alphanumeric, 4),
+            ["a", "b", "c", "d", "a"]
+        );
+        assert_eq!(ticks(SamplingMethod::Reverse, 4), ["d", "c", "b", "a", "d"]);
+    }
+
+    #[test]
+    fn ping_pong_flips_at_the_ends() {
+        assert_eq!(
+            ticks(SamplingMethod::PingPong, 7),
+            ["a", "b", "c", "d", "c", "b", "a", "b"]
+        );
+    }
+
+    #[test]
+    fn random_never_repeats_the_current_image() {
+        let shown = ticks(SamplingMethod::Random, 12);
+
+        for pair in shown.windows(2) {
+            assert_ne!(pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn rotation_interval_is_floored_by_draw_time() {
+        let frequency = Duration::from_secs(1);
+
+        assert_eq!(
+            rotation_interval(frequency, Duration::from_millis(200)),
+            frequency
+        );
+        assert_eq!(
+            rotation_interval(frequency, Duration::from_millis(1500)),
+            Duration::from_secs(3)
+        );
+    }
+
+    #[test]
+    fn fade_opacity_eases_to_full() {
+        let duration = Duration::from_millis(1000);
+
+        assert_eq!(fade_opacity(Duration::ZERO, duration), 0.0);
+        assert_eq!(fade_opacity(Duration::from_millis(500), duration), 0.5);
+        assert!(fade_opacity(Duration::from_millis(250), duration) < 0.25);
+        assert!(fade_opacity(Duration::from_millis(750), duration) > 0.75);
+        assert_eq!(fade_opacity(duration, duration), 1.0);
+        assert_eq!(fade_opacity(Duration::from_secs(5), duration), 1.0);
+    }
+
+    #[test]
+    fn outputs_are_rendered_concurrently() {
+        let target = |width| Target {
+            width,
+            height: 2160,
+            scaling_mode: ScalingMode::Zoom,
+        };
+        let targets = [target(3840), target(2560)];
+
+        // Each render waits for the other at the barrier, which only returns if
+        // both are rendered at the same time.
+        let barrier = std::sync::Barrier::new(targets.len());
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+
+        let rendered = pool.install(|| {
+            render_targets(&targets, |target| {
+                barrier.wait();
+                target.width
+            })
+        });
+
+        assert_eq!(rendered, [3840, 2560]);
+    }
+
+    #[test]
+    fn albums_rotate_by_day() {
+        assert_eq!(album_for_day(0, 42), None);
+        assert_eq!(album_for_day(1, 42), Some(0));
+        assert_eq!(album_for_day(3, 0), Some(0));
+        assert_eq!(album_for_day(3, 1), Some(1));
+        assert_eq!(album_for_day(3, 5), Some(2));
+        assert_eq!(album_for_day(3, 365), Some(2));
+    }
+
+    #[test]
+    fn daily_album_skips_empty_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        for album in ["a", "b", "empty"] {
+            std::fs::create_dir(dir.path().join(album)).unwrap();
+        }
+        std::fs::write(dir.path().join("a/1.png"), b"").unwrap();
+        std::fs::write(dir.path().join("b/1.png"), b"").unwrap();
+        std::fs::write(dir.path().join("loose.png"), b"").unwrap();
+
+        let album = daily_album(dir.path()).unwrap();
+        assert!(album == dir.path().join("a") || album == dir.path().join("b"));
+
+        assert_eq!(daily_album(&dir.path().join("empty")), None);
+    }
+
+    #[test]
+    fn corrupt_images_fail_to_decode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broken.png");
+        std::fs::write(&path, b"not a png").unwrap();
+
+        assert!(decode_image(&path).is_none());
+        assert!(decode_image(&dir.path().join("missing.png")).is_none());
+    }
+
+    #[test]
+    fn single_images_are_not_redrawn_on_ticks() {
+        let single = VecDeque::from([PathBuf::from("a.png")]);
+        assert_eq!(tick(&single, Some(Path::new("a.png")), false), Tick::Keep);
+
+        // Rotation resumes once the source gains another image.
+        let grown = VecDeque::from([PathBuf::from("b.png"), PathBuf::from("a.png")]);
+        assert_eq!(tick(&grown, Some(Path::new("a.png")), false), Tick::Rotate);
+
+        assert_eq!(tick(&grown, Some(Path::new("a.png")), true), Tick::Keep);
+        assert_eq!(tick(&grown, Some(Path::new("c.png")), true), Tick::Resume);
+    }
+
+    #[test]
+    fn dated_images_show_the_latest_up_to_today() {
+        let images = [
+            "2024-03-01.jpg",
+            "2024-03-15-sunrise.jpg",
+            "2024-02-28.png",
+            "2024-13-01.png",
+            "forest.jpg",
+            "2024-03-20.jpg",
+        ]
+        .map(PathBuf::from);
+
+        assert_eq!(
+            dated_image(&images, (2024, 3, 14)),
+            Some(PathBuf::from("2024-03-01.jpg"))
+        );
+        assert_eq!(
+            dated_image(&images, (2024, 3, 20)),
+            Some(PathBuf::from("2024-03-20.jpg"))
+        );
+        assert_eq!(dated_image(&images, (2024, 2, 1)), None);
+
+        assert_eq!(parse_date("2024-02-29"), Some((2024, 2, 29)));
+        assert_eq!(parse_date("2024-2-29"), None);
+        assert_eq!(parse_date("2024-00-10"), None);
+    }
+
+    #[test]
+    fn entry_changes_are_classified_by_the_work_they_need() {
+        let entry = Entry::fallback();
+
+        let faded = Entry {
+            image_opacity: 0.5,
+            scaling_mode: ScalingMode::Stretch,
+            ..entry.clone()
+        };
+        assert_eq!(entry_change(&entry, &faded), EntryChange::Appearance);
+        assert_eq!(entry_change(&entry, &entry), EntryChange::Appearance);
+
+        let faster = Entry {
+            rotation_frequency: 60,
+            ..entry.clone()
+        };
+        assert_eq!(entry_change(&entry, &faster), EntryChange::Source);
+
+        let recolored = Entry {
+            source: Source::solid([0.2, 0.2, 0.2]),
+            ..entry.clone()
+        };
+        assert_eq!(entry_change(&entry, &recolored), EntryChange::Source);
+
+        let docked = Entry {
+            margin_top: 32,
+            source: Source::solid([0.2, 0.2, 0.2]),
+            ..entry.clone()
+        };
+        assert_eq!(entry_change(&entry, &docked), EntryChange::Placement);
+    }
+
+    #[test]
+    fn upcoming_images_follow_the_direction() {
+        // The current image is at the back of the queue.
+        let queue: VecDeque<PathBuf> = ["b", "c", "d", "a"].map(PathBuf::from).into();
+
+        assert_eq!(
+            upcoming_images(&queue, false, 2),
+            ["b", "c"].map(PathBuf::from)
+        );
+        assert_eq!(
+            upcoming_images(&queue, true, 2),
+            ["d", "c"].map(PathBuf::from)
+        );
+
+        // The current image itself is never preloaded.
+        assert_eq!(upcoming_images(&queue, false, 10).len(), 3);
+        assert!(upcoming_images(&VecDeque::from([PathBuf::from("a")]), false, 2).is_empty());
+    }
+
+    #[test]
+    fn no_source_renders_nothing_opaque() {
+        let target = Target {
+            width: 1920,
+            height: 1080,
+            scaling_mode: ScalingMode::Zoom,
+        };
+
+        let image = render(&Entry::fallback(), &Source::None, None, None, None, &target).unwrap();
+
+        assert!(image.color().has_alpha());
+        assert!(image.to_rgba8().pixels().all(|pixel| pixel.0[3] == 0));
+    }
+
+    #[test]
+    fn random_skips_images_within_the_no_repeat_window() {
+        let mut images = ["a", "b", "c", "d", "e"].map(PathBuf::from).to_vec();
+        order(&mut images, SamplingMethod::Random);
+
+        let mut queue = VecDeque::from(images);
+        let mut reversing = false;
+        let mut recent = VecDeque::from([step(&mut queue, false).unwrap()]);
+
+        for _ in 0..50 {
+            let next = next_image(
+                &mut queue,
+                &mut reversing,
+                SamplingMethod::Random,
+                &recent,
+                2,
+            )
+            .unwrap();
+            assert!(
+                !recent.contains(&next),
+                "{next:?} repeated within {recent:?}"
+            );
+
+            recent.push_back(next);
+            while recent.len() > 2 {
+                recent.pop_front();
+            }
+        }
+
+        // A window covering the whole slideshow is ignored.
+        let recent = queue.iter().cloned().collect();
+        assert!(next_image(
+            &mut queue,
+            &mut reversing,
+            SamplingMethod::Random,
+            &recent,
+            5
+        )
+        .is_some());
+    }
+
+    #[test]
+    fn fallback_chains_pick_the_first_available_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = Source::Path(dir.path().join("missing.png"));
+        let present = dir.path().join("present.png");
+        std::fs::write(&present, b"").unwrap();
+        let color = Source::Color(Color::Single([0.0, 0.0, 0.0]));
+
+        assert_eq!(
+            first_available(&[
+                missing.clone(),
+                Source::Fallback(vec![missing.clone(), Source::Path(present.clone())]),
+                color.clone(),
+            ]),
+            Some(Source::Path(present))
+        );
+        assert_eq!(
+            first_available(&[missing.clone(), color.clone()]),
+            Some(color)
+        );
+        assert_eq!(first_available(&[missing]), None);
+    }
+
+    #[test]
+    fn glob_patterns_match_across_the_text() {
+        assert!(glob_match("*.png", "forest.png"));
+        assert!(glob_match("forest-?.jpg", "forest-2.jpg"));
+        assert!(glob_match("*a*b*", "xxaxxbxx"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("forest-?.jpg", "forest-12.jpg"));
+        assert!(!glob_match("*.png", "forest.jpg"));
+        assert!(!glob_match("a*b", "ab/c"));
+    }
+
+    #[test]
+    fn excluded_images_are_filtered_from_the_queue() {
+        let patterns = ["ugly-*", "/shared/drafts/*"].map(PathBuf::from);
+        let mut queue = VecDeque::from(
+            [
+                "/shared/forest.jpg",
+                "/shared/ugly-sunset.jpg",
+                "/shared/drafts/lake.png",
+                "/home/drafts/lake.png",
+            ]
+            .map(PathBuf::from),
+        );
+
+        queue.retain(|path| !excludes(&patterns, path));
+
+        assert_eq!(
+            queue,
+            ["/shared/forest.jpg", "/home/drafts/lake.png"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn split_sides_meet_at_the_ratio() {
+        let target = Target {
+            width: 100,
+            height: 10,
+            scaling_mode: ScalingMode::Zoom,
+        };
+        let split = Source::Split {
+            left: Box::new(Source::solid([1.0, 0.0, 0.0])),
+            right: Box::new(Source::solid([0.0, 0.0, 1.0])),
+            ratio: 0.25,
+        };
+
+        let image = render(&Entry::fallback(), &split, None, None, None, &target)
+            .unwrap()
+            .to_rgba8();
+
+        assert_eq!((image.width(), image.height()), (100, 10));
+        assert_eq!(image.get_pixel(24, 5).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(25, 5).0, [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn sidecar_durations_override_the_rotation_frequency() {
+        let dir = tempfile::tempdir().unwrap();
+        let default = Duration::from_secs(900);
+        let [favorite, quick, invalid, plain] =
+            ["favorite.jpg", "quick.jpg", "invalid.jpg", "plain.jpg"]
+                .map(|name| dir.path().join(name));
+
+        std::fs::write(dir.path().join("favorite.jpg.duration"), "3600\n").unwrap();
+        std::fs::write(dir.path().join("quick.jpg.duration"), "0").unwrap();
+        std::fs::write(dir.path().join("invalid.jpg.duration"), "an hour").unwrap();
+
+        assert_eq!(
+            image_duration(&favorite, default),
+            Duration::from_secs(3600)
+        );
+        assert_eq!(image_duration(&quick, default), MIN_IMAGE_DURATION);
+        assert_eq!(image_duration(&invalid, default), default);
+        assert_eq!(image_duration(&plain, default), default);
+    }
+
+    #[test]
+    fn resolution_changes_rescale_the_decoded_image() {
+        let decoded = DynamicImage::ImageRgb8(RgbImage::from_pixel(3840, 2160, image::Rgb([9; 3])));
+        let source = Source::Path(PathBuf::from("/forest.jpg"));
+
+        for (width, height) in [(1920, 1080), (2560, 1440), (1280, 1024)] {
+            let target = Target {
+                width,
+                height,
+                scaling_mode: ScalingMode::Zoom,
+            };
+
+            let image = render(
+                &Entry::fallback(),
+                &source,
+                Some(&decoded),
+                None,
+                None,
+                &target,
+            )
+            .unwrap();
+            assert_eq!((image.width(), image.height()), (width, height));
+        }
+    }
+
+    #[test]
+    fn reshuffled_queues_keep_the_current_image_at_the_back() {
+        let paths = |names: &[&str]| names.iter().map(PathBuf::from).collect::<VecDeque<_>>();
+        let current = Path::new("b");
+
+        // A new image is queued at the front, and sorted into place.
+        let mut queue = paths(&["d", "c", "a", "b"]);
+        reorder(&mut queue, SamplingMethod::Alphanumeric, Some(current));
+        assert_eq!(queue, paths(&["c", "d", "a", "b"]));
+
+        let mut queue = paths(&["d", "c", "a", "b"]);
+        reorder(&mut queue, SamplingMethod::Reverse, Some(current));
+        assert_eq!(queue, paths(&["a", "d", "c", "b"]));
+
+        let mut queue = paths(&["d", "c", "a", "b"]);
+        reorder(&mut queue, SamplingMethod::Random, Some(current));
+        assert_eq!(queue.back(), Some(&PathBuf::from("b")));
+        let mut sorted = Vec::from(queue);
+        sorted.sort();
+        assert_eq!(sorted, ["a", "b", "c", "d"].map(PathBuf::from));
+    }
+
+    fn assert_near(a: [f32; 3], b: [f32; 3]) {
+        assert!(
+            a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-3),
+            "{a:?} != {b:?}"
+        );
+    }
+
+    #[test]
+    fn matched_colors_follow_the_primary_wallpaper() {
+        let photo = Source::Path(PathBuf::from("/forest.jpg"));
+        let solid = |rgb| DynamicImage::ImageRgb8(RgbImage::from_pixel(8, 8, image::Rgb(rgb)));
+
+        let Some(Color::Single(red)) = matching_color(Some(&photo), Some(&solid([255, 0, 0])))
+        else {
+            panic!("photos are matched by a single color");
+        };
+        assert_near(red, [1.0, 0.0, 0.0]);
+
+        // The color changes with the image of the primary output.
+        let Some(Color::Single(blue)) = matching_color(Some(&photo), Some(&solid([0, 0, 255])))
+        else {
+            panic!("photos are matched by a single color");
+        };
+        assert_near(blue, [0.0, 0.0, 1.0]);
+
+        // Colors of the primary output are copied as they are.
+        let gradient = Source::gradient(vec![[0.0; 3], [1.0; 3]], 90.0);
+        assert_eq!(
+            matching_color(Some(&gradient), None).map(Source::Color),
+            Some(gradient)
+        );
+
+        assert_eq!(matching_color(Some(&photo), None), None);
+    }
+
+    #[test]
+    fn changed_images_end_the_startup_fade() {
+        let duration = Duration::from_secs(60);
+        let mut fade = Fade {
+            start: Some(Instant::now()),
+            image: Some(DynamicImage::new_rgb8(1, 1)),
+        };
+        assert!(fade.opacity(duration) < 1.0);
+
+        fade.cancel();
+        assert!(fade.start.is_none());
+        assert!(fade.image.is_none());
+        assert_eq!(fade.opacity(duration), 1.0);
+    }
+
+    #[test]
+    fn every_layer_draws_the_source_of_a_tick() {
+        let colors = |path: &Path| {
+            if path.ends_with("a.jpg") {
+                [255, 0, 0]
+            } else {
+                [0, 0, 255]
+            }
+        };
+        let mut queue = VecDeque::from(["/a.jpg", "/b.jpg"].map(PathBuf::from));
+        let mut reversing = false;
+
+        // The tick sets the source once, which every layer of the wallpaper is
+        // rendered from, whatever the resolution of its output.
+        let next = next_image(
+            &mut queue,
+            &mut reversing,
+            SamplingMethod::Alphanumeric,
+            &VecDeque::new(),
+            0,
+        )
+        .unwrap();
+        let current_source = Source::Path(next.clone());
+        let decoded =
+            DynamicImage::ImageRgb8(RgbImage::from_pixel(64, 36, image::Rgb(colors(&next))));
+
+        let targets = [(1920, 1080), (2560, 1440), (1280, 1024)].map(|(width, height)| Target {
+            width,
+            height,
+            scaling_mode: ScalingMode::Zoom,
+        });
+        let rendered = render_targets(&targets, |target| {
+            render(
+                &Entry::fallback(),
+                &current_source,
+                Some(&decoded),
+                None,
+                None,
+                target,
+            )
+            .unwrap()
+            .to_rgb8()
+            .get_pixel(0, 0)
+            .0
+        });
+
+        assert_eq!(rendered, [colors(&next); 3]);
+    }
+}
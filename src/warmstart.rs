@@ -0,0 +1,101 @@
+// SPDX-License-Identifier: MPL-2.0-only
+
+//! Small copies of the wallpaper last drawn on each output, which are shown as
+//! soon as the layer of the output is first configured, so that a wallpaper is
+//! visible at login while its source is still being decoded.
+//!
+//! A copy is written to `$XDG_STATE_HOME/cosmic-bg/warm-start/<output>.png`
+//! whenever the wallpaper of an output changes. A copy of a wallpaper that has
+//! since been reconfigured is still shown, and replaced by the first full draw.
+
+use std::path::{Path, PathBuf};
+
+use eyre::OptionExt;
+use image::DynamicImage;
+use sctk::reexports::client::QueueHandle;
+
+use crate::{CosmicBg, CosmicBgLayer};
+
+/// Largest width or height of a copy, which the viewport scales to the output.
+const SIZE: u32 = 640;
+
+/// Directory of the copies of the wallpapers of each output.
+#[must_use]
+pub fn dir() -> Option<PathBuf> {
+    dirs::state_dir().map(|dir| dir.join("cosmic-bg").join("warm-start"))
+}
+
+/// Writes a copy of the rendered wallpaper of an output.
+pub fn write(output: &str, image: &DynamicImage) -> eyre::Result<PathBuf> {
+    write_in(&dir().ok_or_eyre("no state directory")?, output, image)
+}
+
+/// Writes the copy of an output as `<output>.png` in `dir`.
+fn write_in(dir: &Path, output: &str, image: &DynamicImage) -> eyre::Result<PathBuf> {
+    let path = dir.join(format!("{output}.png"));
+    std::fs::create_dir_all(dir)?;
+
+    image.thumbnail(SIZE, SIZE).to_rgba8().save(&path)?;
+
+    Ok(path)
+}
+
+/// Reads the copy of an output from `dir`, if one was written.
+fn read_in(dir: &Path, output: &str) -> Option<DynamicImage> {
+    image::open(dir.join(format!("{output}.png"))).ok()
+}
+
+/// Commits the copy of the wallpaper last drawn on the output of a layer, if
+/// there is one, returning whether it was.
+pub fn draw(layer: &mut CosmicBgLayer, queue_handle: &QueueHandle<CosmicBg>) -> bool {
+    let (Some(dir), Some(output)) = (dir(), layer.output_info.name.as_deref()) else {
+        return false;
+    };
+
+    let Some(image) = read_in(&dir, output) else {
+        return false;
+    };
+
+    let Some(pool) = layer.pool.as_mut() else {
+        return false;
+    };
+
+    let transformed = crate::draw::transform(&image, layer.transform);
+    let image = transformed.as_ref().unwrap_or(&image);
+    let (width, height) = (image.width() as i32, image.height() as i32);
+
+    match crate::draw::canvas(pool, image, width, height, width * 4, false, None) {
+        Ok(buffer) => {
+            crate::draw::layer_surface(layer, queue_handle, &buffer, (width, height));
+            layer.drawn = true;
+            true
+        }
+
+        Err(why) => {
+            tracing::warn!(?why, output, "could not draw warm start image");
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn copies_are_read_back_per_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let image =
+            DynamicImage::ImageRgba8(RgbaImage::from_pixel(3840, 2160, Rgba([10, 20, 30, 255])));
+
+        let path = write_in(dir.path(), "DP-1", &image).unwrap();
+        assert_eq!(path, dir.path().join("DP-1.png"));
+
+        let copy = read_in(dir.path(), "DP-1").unwrap().to_rgba8();
+        assert_eq!((copy.width(), copy.height()), (640, 360));
+        assert_eq!(copy.get_pixel(320, 180).0, [10, 20, 30, 255]);
+
+        assert!(read_in(dir.path(), "HDMI-A-1").is_none());
+    }
+}